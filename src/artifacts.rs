@@ -0,0 +1,198 @@
+//! Cache of finished documents (converted stickers and archives).
+//!
+//! Instead of the documents themselves, this stores telegram `file_id`s of the documents that were sent,
+//! so on a hit the document can be re-sent without downloading, converting or uploading anything.
+//!
+//! The cache is stored as a `.json` file in the cache directory (see `cache.dir` in [`config`](crate::config)).
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use teloxide::types::{Sticker, StickerSet};
+
+use crate::{
+    config::Config,
+    query_command::{ActionDownload, DownloadTarget, QueryCommand},
+    settings::UserSettings,
+};
+
+/// Maximum number of remembered documents, when there are more the oldest ones are forgotten.
+const MAX_ARTIFACTS: usize = 10_000;
+
+#[derive(Clone)]
+pub struct Artifacts {
+    path: Arc<PathBuf>,
+    artifacts: Arc<Mutex<HashMap<String, Artifact>>>,
+    /// Number of the last change of `artifacts` (incremented under its lock).
+    version: Arc<Mutex<u64>>,
+    /// Version of the last snapshot written to disk, also serializes the writes.
+    written: Arc<Mutex<u64>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Artifact {
+    file_id: String,
+    /// Unix timestamp of when the document was sent.
+    created: u64,
+}
+
+impl Artifacts {
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+
+        let path = dir.join("artifacts.json");
+        let artifacts = match std::fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            path: Arc::new(path),
+            artifacts: Arc::new(Mutex::new(artifacts)),
+            version: <_>::default(),
+            written: <_>::default(),
+        })
+    }
+
+    /// Returns the cache key for downloading `sticker` (or its `set`) as requested by `action`.
+    ///
+    /// The key includes a hash of the set contents, so when a set is changed, it's not served from the cache.
+    /// Same goes for the parts of the `config` that change documents, so a config change isn't masked by old documents.
+    pub fn key(
+        sticker: &Sticker,
+        set: Option<&StickerSet>,
+        action: ActionDownload,
+        settings: &UserSettings,
+        config: &Config,
+    ) -> String {
+        // The encoded command includes the format, the archive format and the size,
+        // settings that change the document (file names and the thumbnail) are added separately
+        let command = QueryCommand::download(action.target, action.format)
+            .with_archive(action.archive)
+            .with_resize(action.resize)
+            .encode();
+        let [r, g, b] = config.convert.jpeg_background.0;
+        let options = format!(
+            "{command}:{:?}:{}:jpeg{}#{r:02x}{g:02x}{b:02x}:thumb{}q{}",
+            settings.thumbnail,
+            settings.naming,
+            config.convert.jpeg_quality,
+            config.thumbnail.size,
+            config.thumbnail.jpeg_quality,
+        );
+
+        match (action.target, set) {
+            (DownloadTarget::All, Some(set)) => {
+                let hash = set
+                    .stickers
+                    .iter()
+                    .flat_map(|s| [&s.file_unique_id, s.emoji.as_deref().unwrap_or_default()])
+                    .fold(FNV_OFFSET, |hash, s| fnv1a(hash, s.as_bytes()));
+
+                format!("set:{}:{hash:016x}:{options}", set.name)
+            }
+            (DownloadTarget::Single, _) | (DownloadTarget::All, None) => {
+                format!("sticker:{}:{options}", sticker.file_unique_id)
+            }
+        }
+    }
+
+    /// Returns `file_id` of a previously sent document for `key`.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let artifacts = self.artifacts.lock().unwrap();
+        artifacts.get(key).map(|a| a.file_id.clone())
+    }
+
+    pub async fn put(&self, key: String, file_id: String) -> io::Result<()> {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        let snapshot = {
+            let mut artifacts = self.artifacts.lock().unwrap();
+            artifacts.insert(key, Artifact { file_id, created });
+
+            if artifacts.len() > MAX_ARTIFACTS {
+                let oldest = artifacts
+                    .iter()
+                    .min_by_key(|(_, a)| a.created)
+                    .map(|(k, _)| k.clone());
+
+                if let Some(oldest) = oldest {
+                    artifacts.remove(&oldest);
+                }
+            }
+
+            self.snapshot(&artifacts)?
+        };
+
+        self.save(snapshot).await
+    }
+
+    /// Forgets `key`, e.g. when its `file_id` turned out to be invalid.
+    pub async fn remove(&self, key: &str) -> io::Result<()> {
+        let snapshot = {
+            let mut artifacts = self.artifacts.lock().unwrap();
+            if artifacts.remove(key).is_none() {
+                return Ok(());
+            }
+
+            self.snapshot(&artifacts)?
+        };
+
+        self.save(snapshot).await
+    }
+
+    /// Serializes the cache, returning the json with its version. Must be called under the lock of `artifacts`.
+    fn snapshot(&self, artifacts: &HashMap<String, Artifact>) -> io::Result<(u64, Vec<u8>)> {
+        let mut version = self.version.lock().unwrap();
+        *version += 1;
+
+        Ok((*version, serde_json::to_vec(artifacts)?))
+    }
+
+    /// Writes a snapshot of the cache to disk, unless a newer one was already written.
+    ///
+    /// With [`MAX_ARTIFACTS`] entries the file is about 2 MiB, so this is done in `spawn_blocking`.
+    async fn save(&self, (version, json): (u64, Vec<u8>)) -> io::Result<()> {
+        let path = Arc::clone(&self.path);
+        let written = Arc::clone(&self.written);
+
+        tokio::task::spawn_blocking(move || {
+            // Snapshots can reach this in any order, the lock makes sure that the newest one stays on disk
+            let mut written = written.lock().unwrap();
+            if *written > version {
+                return Ok(());
+            }
+
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, json)?;
+            std::fs::rename(&tmp, &*path)?;
+            *written = version;
+
+            Ok(())
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// FNV-1a hash, used because (unlike `DefaultHasher`) it's guaranteed to be stable across restarts and rust versions.
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    // Hash the length too, so that `["ab", "c"]` and `["a", "bc"]` have different hashes
+    (bytes.len() as u64)
+        .to_le_bytes()
+        .iter()
+        .chain(bytes)
+        .fold(hash, |hash, &b| (hash ^ b as u64).wrapping_mul(FNV_PRIME))
+}
@@ -0,0 +1,238 @@
+//! On-disk cache of sticker files, keyed by `file_unique_id`.
+//!
+//! Files are stored as is (exactly as telegram sends them), one file per sticker.
+//! When the cache grows over its limit, the least recently used files are removed.
+//! The "last used" time is stored as the file modification time, so it survives restarts.
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::{config::CacheConfig, error::ResultExt};
+
+/// Default directory of the cache, see `cache.dir` in [`config`](crate::config).
+pub const DEFAULT_DIR: &str = "cache";
+
+/// Default maximum size of all cached files.
+///
+/// Stickers are limited to 512 KiB (and most are way smaller, 20-60 KiB),
+/// so this is enough for tens of thousands of them.
+pub const MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+#[derive(Clone)]
+pub struct Cache {
+    dir: Arc<PathBuf>,
+    index: Arc<Mutex<Index>>,
+}
+
+struct Index {
+    entries: HashMap<String, Entry>,
+    total_size: u64,
+    max_size: u64,
+}
+
+struct Entry {
+    size: u64,
+    last_used: SystemTime,
+}
+
+impl Cache {
+    /// Opens the cache, indexing files that are already there.
+    pub fn open(config: &CacheConfig) -> io::Result<Self> {
+        let dir = config.dir.clone();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut index = Index::new(config.max_size());
+        for entry in std::fs::read_dir(&dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if !meta.is_file() {
+                continue;
+            }
+
+            let name = entry.file_name();
+            let Some(key) = name.to_str().filter(|k| is_valid_key(k)).map(<_>::to_owned) else {
+                // Temporary files of interrupted writes
+                if name.to_string_lossy().ends_with(".tmp") {
+                    std::fs::remove_file(entry.path()).fine();
+                }
+
+                // Other files are not sticker files (e.g. `artifacts.json`)
+                continue;
+            };
+
+            index.total_size += meta.len();
+            index.entries.insert(
+                key,
+                Entry {
+                    size: meta.len(),
+                    last_used: meta.modified()?,
+                },
+            );
+        }
+
+        log::info!(
+            "Opened cache with {} files ({} KiB)",
+            index.entries.len(),
+            index.total_size / 1024
+        );
+
+        Ok(Self {
+            dir: Arc::new(dir),
+            index: Arc::new(Mutex::new(index)),
+        })
+    }
+
+    /// Returns `true` if the file is in the cache (it may still be evicted before it's read).
+    pub fn contains(&self, key: &str) -> bool {
+        self.index.lock().unwrap().entries.contains_key(key)
+    }
+
+    /// Returns the cached file, if any.
+    pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        {
+            let mut index = self.index.lock().unwrap();
+            index.entries.get_mut(key)?.last_used = SystemTime::now();
+        }
+
+        let path = self.dir.join(key);
+        match tokio::fs::read(&path).await {
+            Ok(bytes) => {
+                // Bump the modification time, so the LRU order survives restarts.
+                // Tokio doesn't have `set_modified`, and nobody needs to wait for this anyway.
+                tokio::task::spawn_blocking(move || {
+                    let touch = std::fs::File::options()
+                        .append(true)
+                        .open(&path)
+                        .and_then(|f| f.set_modified(SystemTime::now()));
+                    touch.fine();
+                });
+
+                Some(bytes)
+            }
+            Err(err) => {
+                log::warn!("Couldn't read cached file {key}: {err}");
+                self.forget(key);
+                None
+            }
+        }
+    }
+
+    /// Puts the file in the cache, evicting least recently used files if needed.
+    pub async fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        let max_size = self.index.lock().unwrap().max_size;
+        if !is_valid_key(key) || bytes.len() as u64 > max_size {
+            return Ok(());
+        }
+
+        // Write to a temporary file first, so that a crash doesn't leave a half-written file in the cache
+        let path = self.dir.join(key);
+        let tmp = self.dir.join(format!("{key}.{}.tmp", uuid::Uuid::new_v4()));
+        tokio::fs::write(&tmp, bytes).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+
+        let evicted = {
+            let mut index = self.index.lock().unwrap();
+            let size = bytes.len() as u64;
+            let prev = index.entries.insert(
+                key.to_owned(),
+                Entry {
+                    size,
+                    last_used: SystemTime::now(),
+                },
+            );
+            index.total_size = index.total_size - prev.map_or(0, |e| e.size) + size;
+
+            index.evict()
+        };
+
+        for key in evicted {
+            tokio::fs::remove_file(self.dir.join(key)).await.fine();
+        }
+
+        Ok(())
+    }
+
+    fn forget(&self, key: &str) {
+        let mut index = self.index.lock().unwrap();
+        if let Some(entry) = index.entries.remove(key) {
+            index.total_size -= entry.size;
+        }
+    }
+}
+
+impl Index {
+    fn new(max_size: u64) -> Self {
+        Self {
+            entries: HashMap::new(),
+            total_size: 0,
+            max_size,
+        }
+    }
+
+    /// Removes least recently used entries from the index until it fits in `max_size`, returning their keys.
+    fn evict(&mut self) -> Vec<String> {
+        if self.total_size <= self.max_size {
+            return Vec::new();
+        }
+
+        let mut by_age: Vec<_> = self
+            .entries
+            .iter()
+            .map(|(k, e)| (e.last_used, e.size, k.clone()))
+            .collect();
+        by_age.sort_unstable();
+
+        let mut evicted = Vec::new();
+        for (_, size, key) in by_age {
+            if self.total_size <= self.max_size {
+                break;
+            }
+
+            self.entries.remove(&key);
+            self.total_size -= size;
+            evicted.push(key);
+        }
+
+        evicted
+    }
+}
+
+/// `file_unique_id`s are base64url-ish, anything else is not ours (and may not be a safe file name).
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::{Entry, Index, MAX_SIZE};
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut index = Index::new(MAX_SIZE);
+        let now = SystemTime::now();
+
+        for (key, age) in [("old", 30), ("new", 10), ("older", 40)] {
+            index.entries.insert(
+                key.to_owned(),
+                Entry {
+                    size: MAX_SIZE / 2,
+                    last_used: now - Duration::from_secs(age),
+                },
+            );
+            index.total_size += MAX_SIZE / 2;
+        }
+
+        assert_eq!(index.evict(), ["older"]);
+        assert_eq!(index.total_size, MAX_SIZE);
+        assert!(index.evict().is_empty());
+    }
+}
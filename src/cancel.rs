@@ -0,0 +1,55 @@
+//! Cooperative cancellation of download jobs.
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use futures::future::{self, Either};
+use tokio::sync::Notify;
+
+#[derive(Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Relaxed);
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Returns `true` if the job was cancelled, this is what blocking code (e.g. conversions) should check.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Resolves when the job is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            // N.B. this has to be created before checking the flag, so we don't miss notifications
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Runs `fut` until it's finished or the job is cancelled, returning `None` in the latter case.
+    pub async fn or_cancelled<F: Future>(&self, fut: F) -> Option<F::Output> {
+        match future::select(Box::pin(fut), Box::pin(self.cancelled())).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(((), _)) => None,
+        }
+    }
+}
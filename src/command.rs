@@ -0,0 +1,130 @@
+//! Commands of the bot.
+//!
+//! We could use teloxide derive macros for commands, but for just a few commands that's a bit of an overkill.
+//! Instead, this is the single source for parsing, the command menu (see [`Command::bot_commands`])
+//! and the help text (see [`Command::help_text`]).
+use teloxide::{types::BotCommand, utils::html::escape};
+
+use crate::i18n::Language;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Start,
+    Help,
+    Download,
+    Archive,
+    Resize,
+    Settings,
+    History,
+}
+
+impl Command {
+    /// All commands, in the order they are shown to users.
+    pub const ALL: [Command; 7] = [
+        Command::Download,
+        Command::Resize,
+        Command::History,
+        Command::Settings,
+        Command::Archive,
+        Command::Help,
+        Command::Start,
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::Start => "start",
+            Command::Help => "help",
+            Command::Download => "download",
+            Command::Archive => "archive",
+            Command::Resize => "resize",
+            Command::Settings => "settings",
+            Command::History => "history",
+        }
+    }
+
+    /// Arguments of the command, as shown in the help text.
+    pub fn args(self) -> Option<&'static str> {
+        match self {
+            Command::Start | Command::Help | Command::Settings | Command::History => None,
+            Command::Download => Some("<set name or link>"),
+            Command::Archive => Some("[zip | tar | tar.gz | tar.zst | 7z]"),
+            Command::Resize => Some("<512 | 256 | 128 | 96 | 64 | size> [fit | fill | pad]"),
+        }
+    }
+
+    /// Description of the command in English, see [`Language::command_description`] for translations.
+    pub fn description(self) -> &'static str {
+        match self {
+            Command::Start => "Start the bot",
+            Command::Help => "Show help",
+            Command::Download => {
+                "Download a whole sticker set by its name or t.me/addstickers link"
+            }
+            Command::Archive => "Show or change the format of archives with multiple stickers",
+            Command::Resize => {
+                "Download a sticker or its set resized, e.g. as emoji for other platforms (reply to a sticker)"
+            }
+            Command::Settings => {
+                "Change the default format, file names, language and other settings"
+            }
+            Command::History => "Show previous downloads and get them again",
+        }
+    }
+
+    /// Commands for the menu in telegram clients (see `set_my_commands`).
+    pub fn bot_commands() -> Vec<BotCommand> {
+        Self::ALL
+            .into_iter()
+            .map(|c| BotCommand::new(c.name(), c.description()))
+            .collect()
+    }
+
+    pub fn help_text(bot_username: &str, language: Language) -> String {
+        let commands: String = Self::ALL
+            .into_iter()
+            .map(|c| {
+                let args = language
+                    .command_args(c)
+                    .map(|a| format!(" {}", escape(a)))
+                    .unwrap_or_default();
+                format!(
+                    "/{}{args} — {}\n",
+                    c.name(),
+                    language.command_description(c)
+                )
+            })
+            .collect();
+
+        language.help(bot_username, &commands)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+    use crate::i18n::Language;
+
+    #[test]
+    fn registry() {
+        for command in Command::ALL {
+            assert_eq!(Command::parse(command.name()), Some(command));
+
+            // Limits of `set_my_commands`
+            assert!(command.name().len() <= 32);
+            assert!((3..=256).contains(&command.description().len()));
+        }
+
+        assert_eq!(Command::parse("unknown"), None);
+
+        for language in Language::ALL {
+            let help = Command::help_text("bot", language);
+            assert!(Command::ALL
+                .into_iter()
+                .all(|c| help.contains(language.command_description(c))));
+        }
+    }
+}
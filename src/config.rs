@@ -0,0 +1,729 @@
+//! Configuration of the bot.
+//!
+//! The configuration is read from a `.toml` file (`config.toml` in the working directory,
+//! or the file pointed to by `STICKER_CONFIG`), every field of which is optional:
+//!
+//! ```toml
+//! [runtime]
+//! # Use multi-thread tokio runtime (with `worker_threads` threads, 0 means one per core)
+//! multi_thread = false
+//! worker_threads = 0
+//!
+//! [download]
+//! concurrency = 8
+//! info_concurrency = 16
+//!
+//! [thumbnail]
+//! size = 256
+//! jpeg_quality = 90
+//!
+//! [convert]
+//! # Number of threads converting stickers of a single download (at most `limits.max_conversions` downloads
+//! # are converted at the same time, so up to `max_conversions * workers` threads are used)
+//! workers = 1
+//! # Transparent parts of stickers converted to .jpg are filled with this color
+//! jpeg_background = "#ffffff"
+//! jpeg_quality = 90
+//!
+//! [archive]
+//! # Archives bigger than this are stored in a temporary file (in `tmp_dir`, system temp directory by default)
+//! spill_threshold_mib = 16
+//! # Archives bigger than this are split into volumes (telegram doesn't allow bots to upload files bigger than 50 MB)
+//! max_volume_mib = 45
+//! tmp_dir = "/tmp"
+//!
+//! [limits]
+//! user_burst = 5
+//! user_refill_secs = 20
+//! chat_burst = 10
+//! chat_refill_secs = 10
+//! max_conversions = 2
+//!
+//! [queue]
+//! max_running = 2
+//! max_waiting = 64
+//!
+//! [cache]
+//! dir = "cache"
+//! max_size_mib = 1024
+//!
+//! [settings]
+//! # Sqlite database with settings and download history of users,
+//! # ":memory:" keeps them in memory (i.e. they are lost on restart)
+//! path = "settings.sqlite3"
+//!
+//! # Without this section, long polling is used
+//! [webhook]
+//! url = "https://example.org/some/secret/path"
+//! address = "127.0.0.1:8080"
+//! secret = "..."
+//! ```
+//!
+//! Every field can be overridden by an environment variable named `STICKER_<SECTION>_<FIELD>`,
+//! e.g. `STICKER_DOWNLOAD_CONCURRENCY=16` or `STICKER_WEBHOOK_URL=https://...`.
+//!
+//! `WEBHOOK_URL`, `WEBHOOK_ADDRESS` and `WEBHOOK_SECRET` (the names from before the config file existed)
+//! are still read, when the corresponding `STICKER_WEBHOOK_*` variables are not set.
+use std::{
+    env, fmt,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use url::Url;
+
+use crate::{cache, download, error::config::ConfigError, limiter, queue};
+
+/// Config file that is used if `STICKER_CONFIG` is not set, it's fine if it doesn't exist.
+const DEFAULT_PATH: &str = "config.toml";
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub runtime: RuntimeConfig,
+    pub download: DownloadConfig,
+    pub thumbnail: ThumbnailConfig,
+    pub convert: ConvertConfig,
+    pub archive: ArchiveConfig,
+    pub limits: LimitsConfig,
+    pub queue: QueueConfig,
+    pub cache: CacheConfig,
+    pub settings: SettingsConfig,
+    pub webhook: Option<WebhookConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RuntimeConfig {
+    pub multi_thread: bool,
+    /// Number of threads of the multi-thread runtime, `0` means the number of cores.
+    pub worker_threads: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DownloadConfig {
+    /// How many files are downloaded concurrently, see [`download::C`].
+    pub concurrency: usize,
+    /// How many `get_file` requests are made concurrently when preparing a download.
+    pub info_concurrency: usize,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThumbnailConfig {
+    /// Width and height of thumbnails of sent documents.
+    pub size: u32,
+    pub jpeg_quality: u8,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConvertConfig {
+    /// Number of threads converting stickers of a single download.
+    pub workers: usize,
+    /// Color that `.jpg`s are flattened onto (jpeg doesn't support transparency).
+    pub jpeg_background: Color,
+    pub jpeg_quality: u8,
+}
+
+/// Rgb color, written as `#rrggbb`.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub struct Color(pub [u8; 3]);
+
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    /// Archives bigger than this are written to a temporary file instead of memory.
+    pub spill_threshold_mib: u64,
+    /// Archives bigger than this are split into multiple volumes.
+    pub max_volume_mib: u64,
+    /// Directory of temporary files, system temp directory if not set.
+    pub tmp_dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub user_burst: u32,
+    pub user_refill_secs: u64,
+    pub chat_burst: u32,
+    pub chat_refill_secs: u64,
+    pub max_conversions: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueConfig {
+    pub max_running: usize,
+    pub max_waiting: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub dir: PathBuf,
+    pub max_size_mib: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SettingsConfig {
+    /// Path of the sqlite database, `:memory:` is an in-memory database (as usual for sqlite).
+    pub path: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// Public url that telegram sends updates to.
+    pub url: Url,
+    /// Local address the webhook server binds to.
+    #[serde(default = "default_webhook_address")]
+    pub address: SocketAddr,
+    /// Secret token that telegram sends with every update (random if not set).
+    pub secret: Option<String>,
+}
+
+impl Config {
+    /// Reads the config file, applies environment overrides and validates the result.
+    pub fn load() -> Result<Self, ConfigError> {
+        let (path, required) = match env::var_os("STICKER_CONFIG") {
+            Some(path) => (PathBuf::from(path), true),
+            None => (PathBuf::from(DEFAULT_PATH), false),
+        };
+
+        let mut config = match std::fs::read_to_string(&path) {
+            Ok(toml) => Self::parse(&toml, &path)?,
+            Err(err) if !required && err.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(source) => return Err(ConfigError::Read { path, source }),
+        };
+
+        config.apply_env()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn parse(toml: &str, path: &Path) -> Result<Self, ConfigError> {
+        toml::from_str(toml).map_err(|source| ConfigError::Parse {
+            path: path.to_owned(),
+            source,
+        })
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        let Self {
+            runtime,
+            download,
+            thumbnail,
+            convert,
+            archive,
+            limits,
+            queue,
+            cache,
+            settings,
+            webhook,
+        } = self;
+
+        env_override("STICKER_RUNTIME_MULTI_THREAD", &mut runtime.multi_thread)?;
+        env_override(
+            "STICKER_RUNTIME_WORKER_THREADS",
+            &mut runtime.worker_threads,
+        )?;
+
+        env_override("STICKER_DOWNLOAD_CONCURRENCY", &mut download.concurrency)?;
+        env_override(
+            "STICKER_DOWNLOAD_INFO_CONCURRENCY",
+            &mut download.info_concurrency,
+        )?;
+
+        env_override("STICKER_THUMBNAIL_SIZE", &mut thumbnail.size)?;
+        env_override(
+            "STICKER_THUMBNAIL_JPEG_QUALITY",
+            &mut thumbnail.jpeg_quality,
+        )?;
+
+        env_override("STICKER_CONVERT_WORKERS", &mut convert.workers)?;
+        env_override(
+            "STICKER_CONVERT_JPEG_BACKGROUND",
+            &mut convert.jpeg_background,
+        )?;
+        env_override("STICKER_CONVERT_JPEG_QUALITY", &mut convert.jpeg_quality)?;
+
+        env_override(
+            "STICKER_ARCHIVE_SPILL_THRESHOLD_MIB",
+            &mut archive.spill_threshold_mib,
+        )?;
+        env_override(
+            "STICKER_ARCHIVE_MAX_VOLUME_MIB",
+            &mut archive.max_volume_mib,
+        )?;
+        env_override_opt("STICKER_ARCHIVE_TMP_DIR", &mut archive.tmp_dir)?;
+
+        env_override("STICKER_LIMITS_USER_BURST", &mut limits.user_burst)?;
+        env_override(
+            "STICKER_LIMITS_USER_REFILL_SECS",
+            &mut limits.user_refill_secs,
+        )?;
+        env_override("STICKER_LIMITS_CHAT_BURST", &mut limits.chat_burst)?;
+        env_override(
+            "STICKER_LIMITS_CHAT_REFILL_SECS",
+            &mut limits.chat_refill_secs,
+        )?;
+        env_override(
+            "STICKER_LIMITS_MAX_CONVERSIONS",
+            &mut limits.max_conversions,
+        )?;
+
+        env_override("STICKER_QUEUE_MAX_RUNNING", &mut queue.max_running)?;
+        env_override("STICKER_QUEUE_MAX_WAITING", &mut queue.max_waiting)?;
+
+        env_override("STICKER_CACHE_DIR", &mut cache.dir)?;
+        env_override("STICKER_CACHE_MAX_SIZE_MIB", &mut cache.max_size_mib)?;
+
+        env_override("STICKER_SETTINGS_PATH", &mut settings.path)?;
+
+        // Setting the url enables webhook mode, even if there is no `[webhook]` section in the file
+        let url = env_legacy("STICKER_WEBHOOK_URL", "WEBHOOK_URL")?;
+        match (url, &mut *webhook) {
+            (Some(url), Some(webhook)) => webhook.url = url,
+            (Some(url), None) => {
+                *webhook = Some(WebhookConfig {
+                    url,
+                    address: default_webhook_address(),
+                    secret: None,
+                })
+            }
+            (None, _) => {}
+        }
+
+        if let Some(webhook) = webhook {
+            if let Some(address) = env_legacy("STICKER_WEBHOOK_ADDRESS", "WEBHOOK_ADDRESS")? {
+                webhook.address = address;
+            }
+            if let Some(secret) = env_legacy("STICKER_WEBHOOK_SECRET", "WEBHOOK_SECRET")? {
+                webhook.secret = Some(secret);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let Self {
+            runtime: _,
+            download,
+            thumbnail,
+            convert,
+            archive,
+            limits,
+            queue,
+            cache,
+            settings: _,
+            webhook,
+        } = self;
+
+        check(
+            download.concurrency >= 1,
+            "download.concurrency",
+            "at least 1",
+        )?;
+        check(
+            download.info_concurrency >= 1,
+            "download.info_concurrency",
+            "at least 1",
+        )?;
+
+        // Telegram ignores thumbnails bigger than 320x320
+        check(
+            (1..=320).contains(&thumbnail.size),
+            "thumbnail.size",
+            "from 1 to 320",
+        )?;
+        check(
+            (1..=100).contains(&thumbnail.jpeg_quality),
+            "thumbnail.jpeg_quality",
+            "from 1 to 100",
+        )?;
+
+        check(convert.workers >= 1, "convert.workers", "at least 1")?;
+        check(
+            (1..=100).contains(&convert.jpeg_quality),
+            "convert.jpeg_quality",
+            "from 1 to 100",
+        )?;
+
+        // Bots can't upload files bigger than 50 MB, bigger volumes would fail to send
+        check(
+            (1..=50).contains(&archive.max_volume_mib),
+            "archive.max_volume_mib",
+            "from 1 to 50",
+        )?;
+
+        check(limits.user_burst >= 1, "limits.user_burst", "at least 1")?;
+        check(
+            limits.user_refill_secs >= 1,
+            "limits.user_refill_secs",
+            "at least 1",
+        )?;
+        check(limits.chat_burst >= 1, "limits.chat_burst", "at least 1")?;
+        check(
+            limits.chat_refill_secs >= 1,
+            "limits.chat_refill_secs",
+            "at least 1",
+        )?;
+        check(
+            limits.max_conversions >= 1,
+            "limits.max_conversions",
+            "at least 1",
+        )?;
+
+        check(queue.max_running >= 1, "queue.max_running", "at least 1")?;
+        // A queue without waiting places refuses every job
+        check(queue.max_waiting >= 1, "queue.max_waiting", "at least 1")?;
+
+        check(cache.max_size_mib >= 1, "cache.max_size_mib", "at least 1")?;
+
+        if let Some(webhook) = webhook {
+            check(
+                matches!(webhook.url.scheme(), "http" | "https"),
+                "webhook.url",
+                "an http(s) url",
+            )?;
+
+            // Telegram's requirements, see <https://core.telegram.org/bots/api#setwebhook>
+            if let Some(secret) = &webhook.secret {
+                let valid = (1..=256).contains(&secret.len())
+                    && secret
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+                check(
+                    valid,
+                    "webhook.secret",
+                    "1-256 characters `A-Z`, `a-z`, `0-9`, `_` or `-`",
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl LimitsConfig {
+    pub fn user_refill(&self) -> Duration {
+        Duration::from_secs(self.user_refill_secs)
+    }
+
+    pub fn chat_refill(&self) -> Duration {
+        Duration::from_secs(self.chat_refill_secs)
+    }
+}
+
+impl ArchiveConfig {
+    pub fn spill_threshold(&self) -> u64 {
+        self.spill_threshold_mib * 1024 * 1024
+    }
+
+    pub fn max_volume_size(&self) -> u64 {
+        self.max_volume_mib * 1024 * 1024
+    }
+
+    pub fn tmp_dir(&self) -> PathBuf {
+        self.tmp_dir.clone().unwrap_or_else(env::temp_dir)
+    }
+}
+
+impl CacheConfig {
+    pub fn max_size(&self) -> u64 {
+        self.max_size_mib * 1024 * 1024
+    }
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: download::C,
+            info_concurrency: 16,
+        }
+    }
+}
+
+impl Default for ThumbnailConfig {
+    fn default() -> Self {
+        Self {
+            size: 256,
+            jpeg_quality: 90,
+        }
+    }
+}
+
+impl Default for ConvertConfig {
+    fn default() -> Self {
+        Self {
+            workers: 1,
+            jpeg_background: Color([255, 255, 255]),
+            jpeg_quality: 90,
+        }
+    }
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            spill_threshold_mib: 16,
+            max_volume_mib: 45,
+            tmp_dir: None,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            user_burst: limiter::USER_BURST,
+            user_refill_secs: limiter::USER_REFILL.as_secs(),
+            chat_burst: limiter::CHAT_BURST,
+            chat_refill_secs: limiter::CHAT_REFILL.as_secs(),
+            max_conversions: limiter::MAX_CONVERSIONS,
+        }
+    }
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_running: queue::MAX_RUNNING,
+            max_waiting: queue::MAX_WAITING,
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from(cache::DEFAULT_DIR),
+            max_size_mib: cache::MAX_SIZE / (1024 * 1024),
+        }
+    }
+}
+
+impl Default for SettingsConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("settings.sqlite3"),
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const EXPECTED: &str = "expected a color in the `#rrggbb` format";
+
+        let hex = s.strip_prefix('#').ok_or(EXPECTED)?;
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(EXPECTED);
+        }
+
+        let channel =
+            |i: usize| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| EXPECTED);
+        Ok(Self([channel(0)?, channel(1)?, channel(2)?]))
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = &'static str;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+fn default_webhook_address() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 8080))
+}
+
+/// Replaces `field` with the value of the environment variable `var`, if it's set.
+fn env_override<T>(var: &'static str, field: &mut T) -> Result<(), ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Some(value) = env_var(var)? {
+        *field = parse_var(var, value)?;
+    }
+
+    Ok(())
+}
+
+fn env_override_opt<T>(var: &'static str, field: &mut Option<T>) -> Result<(), ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Some(value) = env_var(var)? {
+        *field = Some(parse_var(var, value)?);
+    }
+
+    Ok(())
+}
+
+/// Returns the parsed value of the environment variable `var`, or of `legacy` if `var` is not set.
+///
+/// Used for the `WEBHOOK_*` variables, so that deployments from before the config file keep working.
+fn env_legacy<T>(var: &'static str, legacy: &'static str) -> Result<Option<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    if let Some(value) = env_var(var)? {
+        return parse_var(var, value).map(Some);
+    }
+
+    match env_var(legacy)? {
+        Some(value) => {
+            log::warn!("`{legacy}` is deprecated, use `{var}` instead");
+            parse_var(legacy, value).map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+fn parse_var<T>(var: &'static str, value: String) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse()
+        .map_err(|err: T::Err| ConfigError::InvalidVar {
+            var,
+            reason: err.to_string(),
+            value: shown_value(var, value),
+        })
+}
+
+/// Returns the value of `var` that can be shown in errors, `None` for secrets.
+fn shown_value(var: &str, value: String) -> Option<String> {
+    // The path of the webhook url is a secret too, see `listener`
+    const SECRETS: &[&str] = &[
+        "STICKER_WEBHOOK_URL",
+        "WEBHOOK_URL",
+        "STICKER_WEBHOOK_SECRET",
+        "WEBHOOK_SECRET",
+    ];
+
+    Some(value).filter(|_| !SECRETS.contains(&var))
+}
+
+/// Returns the value of an environment variable, treating empty values as unset.
+fn env_var(var: &'static str) -> Result<Option<String>, ConfigError> {
+    match env::var(var) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(value)) => Err(ConfigError::InvalidVar {
+            var,
+            value: shown_value(var, value.to_string_lossy().into_owned()),
+            reason: "not valid unicode".to_owned(),
+        }),
+    }
+}
+
+fn check(ok: bool, field: &'static str, expected: &'static str) -> Result<(), ConfigError> {
+    match ok {
+        true => Ok(()),
+        false => Err(ConfigError::Invalid { field, expected }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{env_legacy, parse_var, Color, Config};
+    use crate::error::config::ConfigError;
+
+    #[test]
+    fn parse_and_validate() {
+        let config = Config::parse(
+            "[download]\nconcurrency = 4\n\n[webhook]\nurl = \"https://example.org/hook\"\n",
+            Path::new("test.toml"),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+
+        assert_eq!(config.download.concurrency, 4);
+        assert_eq!(config.download.info_concurrency, 16);
+        assert_eq!(config.webhook.as_ref().unwrap().address.port(), 8080);
+        assert!(config.validate().is_ok());
+
+        // Typos are errors, instead of silently being ignored
+        assert!(Config::parse("[downlaod]\nconcurrency = 4\n", Path::new("test.toml")).is_err());
+
+        for invalid in [
+            "[thumbnail]\nsize = 1000\n",
+            // A queue without waiting places refuses every job
+            "[queue]\nmax_waiting = 0\n",
+            // Over the upload limit of bots
+            "[archive]\nmax_volume_mib = 51\n",
+        ] {
+            let config = Config::parse(invalid, Path::new("test.toml"))
+                .unwrap_or_else(|err| panic!("{err}"));
+            assert!(config.validate().is_err(), "{invalid}");
+        }
+
+        let config = Config::parse(
+            "[convert]\njpeg_background = \"#FF8000\"\n",
+            Path::new("test.toml"),
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(config.convert.jpeg_background, Color([255, 128, 0]));
+        assert!(Config::parse(
+            "[convert]\njpeg_background = \"red\"\n",
+            Path::new("test.toml")
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn legacy_vars() {
+        // Names that nothing else uses, since tests run in parallel
+        std::env::set_var("TEST_LEGACY_WEBHOOK_ADDRESS", "0.0.0.0:80");
+        let address: Option<std::net::SocketAddr> = env_legacy(
+            "STICKER_TEST_LEGACY_WEBHOOK_ADDRESS",
+            "TEST_LEGACY_WEBHOOK_ADDRESS",
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(address.unwrap().port(), 80);
+
+        std::env::set_var("STICKER_TEST_LEGACY_WEBHOOK_ADDRESS", "0.0.0.0:81");
+        let address: Option<std::net::SocketAddr> = env_legacy(
+            "STICKER_TEST_LEGACY_WEBHOOK_ADDRESS",
+            "TEST_LEGACY_WEBHOOK_ADDRESS",
+        )
+        .unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(address.unwrap().port(), 81);
+    }
+
+    #[test]
+    fn secret_vars_are_not_printed() {
+        let secret = "example.org/secret-path".to_owned();
+        let Err(err) = parse_var::<url::Url>("STICKER_WEBHOOK_URL", secret) else {
+            panic!("a url without a scheme is invalid");
+        };
+        assert!(matches!(err, ConfigError::InvalidVar { value: None, .. }));
+        assert!(!err.to_string().contains("secret-path"));
+
+        let Err(err) = parse_var::<u64>("STICKER_ARCHIVE_MAX_VOLUME_MIB", "lots".to_owned()) else {
+            panic!("not a number");
+        };
+        assert!(err.to_string().contains("lots"));
+    }
+}
@@ -0,0 +1,817 @@
+//! Conversion of stickers between formats.
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+};
+
+use flate2::read::GzDecoder;
+use lodepng::RGBA;
+use rgb::FromSlice;
+use serde::Serialize;
+use teloxide::types::StickerKind;
+use tiny_skia::Pixmap;
+use zip::{write::FileOptions, ZipWriter};
+
+#[cfg(feature = "video")]
+use crate::video::Video;
+use crate::{
+    config::ConvertConfig,
+    error::converting::ConvertError,
+    lottie::Animation,
+    pack,
+    query_command::{DownloadFormat, Resize, ResizeMode},
+    resize,
+    webp_anim::AnimEncoder,
+};
+
+/// Maximum frame rate of converted animations.
+///
+/// Telegram animated stickers are (mostly) 60 fps, but gifs can't really do more than 50,
+/// and halving the number of frames makes conversion twice as fast (and files twice as small).
+const MAX_FPS: f32 = 30.;
+
+/// Quality of `.avif`s, from 1 to 100.
+const AVIF_QUALITY: f32 = 80.;
+
+/// Speed of the avif encoder, from 1 (slowest, best compression) to 10.
+///
+/// Avif encoding is *slow*, so anything below 6 makes downloading a set take minutes.
+const AVIF_SPEED: u8 = 8;
+
+/// Quality of re-encoded (resized) `.webp`s, from 0 to 100.
+const WEBP_QUALITY: f32 = 90.;
+
+/// Qualities that are tried one by one when a `.webp` must fit into a size limit.
+const WEBP_LIMITED_QUALITIES: [f32; 5] = [90., 75., 60., 45., 30.];
+
+/// Sizes and frame steps (every `step`-th frame is kept) that are tried one by one
+/// when a `.gif` must fit into a size limit.
+///
+/// Gifs don't have a quality knob, so the only way to make them smaller is to make them smaller.
+const GIF_LIMITED_ATTEMPTS: [(u32, usize); 5] = [(128, 1), (128, 2), (96, 2), (96, 3), (64, 3)];
+
+/// Same as [`GIF_LIMITED_ATTEMPTS`], but for `.png`s.
+///
+/// Frames of apngs are full rgba without any inter-frame compression, so they need to be much smaller
+/// than the original 512×512 to fit into a few hundred KiB.
+const APNG_LIMITED_ATTEMPTS: [(u32, usize); 5] = [(256, 2), (192, 2), (160, 3), (128, 3), (128, 4)];
+
+/// Returns `true` if stickers of kind `kind` can be downloaded as `format`, resized with `resize`.
+pub fn is_supported(kind: &StickerKind, format: DownloadFormat, resize: Option<Resize>) -> bool {
+    use DownloadFormat as F;
+
+    // Vector and video formats are sent as is, so they can't be resized, and packs have fixed sizes
+    if resize.is_some() && (matches!(format, F::Tgs | F::Lottie | F::Webm) || format.is_pack()) {
+        return false;
+    }
+
+    match kind {
+        // Static gif is a single frame gif
+        StickerKind::Webp => matches!(
+            format,
+            F::Png
+                | F::Webp
+                | F::WebpLossless
+                | F::Jpeg
+                | F::Avif
+                | F::Gif
+                | F::WhatsApp
+                | F::Signal
+                | F::Emoji
+        ),
+        // Static formats get the first frame
+        StickerKind::Animated => matches!(
+            format,
+            F::Png
+                | F::WebpLossless
+                | F::Jpeg
+                | F::Avif
+                | F::Gif
+                | F::Apng
+                | F::Frames
+                | F::Tgs
+                | F::Lottie
+                | F::WhatsApp
+                | F::Signal
+                | F::Emoji
+        ),
+        // Decoding videos requires libvpx, so without the `video` feature we can only send them as is
+        StickerKind::Video => {
+            matches!(format, F::Webm)
+                || cfg!(feature = "video")
+                    && matches!(
+                        format,
+                        F::Gif | F::Frames | F::WhatsApp | F::Signal | F::Emoji
+                    )
+        }
+    }
+}
+
+/// Returns `true` if stickers of kind `kind` need to be converted to become `format` resized with `resize`
+/// (as opposed to being sent as is).
+pub fn is_needed(kind: &StickerKind, format: DownloadFormat, resize: Option<Resize>) -> bool {
+    use DownloadFormat as F;
+
+    resize.is_some()
+        || !matches!(
+            (kind, format),
+            (StickerKind::Webp, F::Webp)
+                | (StickerKind::Animated, F::Tgs | F::Lottie)
+                | (StickerKind::Video, F::Webm)
+        )
+}
+
+/// Converts a sticker of kind `kind` to `format`, resizing it with `resize`.
+///
+/// This is a blocking operation, which can take quite some time for animated and video stickers.
+pub fn convert(
+    kind: &StickerKind,
+    format: DownloadFormat,
+    resize: Option<Resize>,
+    bytes: Vec<u8>,
+    config: &ConvertConfig,
+) -> Result<Vec<u8>, ConvertError> {
+    use DownloadFormat as F;
+
+    if !is_needed(kind, format, resize) {
+        return Ok(bytes);
+    }
+
+    match (kind, format) {
+        (
+            StickerKind::Webp | StickerKind::Animated,
+            F::Png | F::WebpLossless | F::Jpeg | F::Avif,
+        )
+        // Webp -> webp is only "converted" when resizing
+        | (StickerKind::Webp, F::Webp) => {
+            let (w, h, raw) = first_frame(kind, &bytes)?;
+            let raw = resize::resize(w, h, raw, resize)?;
+            let (w, h) = resize::dimensions(w, h, resize);
+            encode_static(w, h, &raw, format, config)
+        }
+        (StickerKind::Webp, F::Gif) => {
+            let (w, h, raw) = decode_webp(&bytes)?;
+            let frames = resize_frames(w, h, std::iter::once(Ok((raw, 0.))), resize);
+            let (w, h) = resize::dimensions(w, h, resize);
+            encode_gif(w, h, frames)
+        }
+        (StickerKind::Animated, F::Gif) => {
+            let animation = decode_lottie(&bytes)?;
+            let (w, h) = (animation.width(), animation.height());
+            let frames = resize_frames(w, h, render_frames(&animation)?, resize);
+            let (w, h) = resize::dimensions(w, h, resize);
+            encode_gif(w, h, frames)
+        }
+        (StickerKind::Animated, F::Apng) => {
+            let animation = decode_lottie(&bytes)?;
+            let (w, h) = (animation.width(), animation.height());
+            let count = frames(&animation).count();
+            let frames = resize_frames(w, h, render_frames(&animation)?, resize);
+            let (w, h) = resize::dimensions(w, h, resize);
+            encode_apng(w, h, count, frames)
+        }
+        (StickerKind::Animated, F::Frames) => {
+            let animation = decode_lottie(&bytes)?;
+            let (w, h) = (animation.width(), animation.height());
+            let frames = resize_frames(w, h, render_frames(&animation)?, resize);
+            let (w, h) = resize::dimensions(w, h, resize);
+            encode_frames(w, h, frames)
+        }
+        (StickerKind::Webp, F::WhatsApp) => {
+            let (w, h, raw) = decode_webp(&bytes)?;
+            let raw = resize::resize(w, h, raw, Some(pack::WHATSAPP_RESIZE))?;
+            let size = pack::WHATSAPP_RESIZE.size;
+            encode_webp_within(size, size, &raw, pack::WHATSAPP_STATIC_LIMIT)
+        }
+        (StickerKind::Webp, F::Signal) => {
+            let (w, h, raw) = decode_webp(&bytes)?;
+            let raw = resize::resize(w, h, raw, Some(pack::SIGNAL_RESIZE))?;
+            let size = pack::SIGNAL_RESIZE.size;
+            encode_webp_within(size, size, &raw, pack::SIGNAL_LIMIT)
+        }
+        // A 128×128 png is at most 64 KiB (plus a few headers), so it always fits into the limit
+        (StickerKind::Webp, F::Emoji) => {
+            let (w, h, raw) = decode_webp(&bytes)?;
+            let resize = Resize {
+                size: pack::EMOJI_SIZE,
+                mode: ResizeMode::Pad,
+            };
+            let raw = resize::resize(w, h, raw, Some(resize))?;
+            encode_png(resize.size, resize.size, &raw)
+        }
+        (StickerKind::Animated, F::Emoji) => {
+            let animation = decode_lottie(&bytes)?;
+            let (w, h) = (animation.width(), animation.height());
+            encode_gif_within(w, h, pack::EMOJI_LIMIT, || render_frames(&animation))
+        }
+        #[cfg(feature = "video")]
+        (StickerKind::Video, F::Emoji) => {
+            let video = Video::from_webm(&bytes)?;
+            let (w, h) = (video.width(), video.height());
+            encode_gif_within(w, h, pack::EMOJI_LIMIT, || video.frames())
+        }
+        (StickerKind::Animated, F::WhatsApp) => {
+            let animation = decode_lottie(&bytes)?;
+            let (w, h) = (animation.width(), animation.height());
+            let size = pack::WHATSAPP_RESIZE.size;
+            encode_animated_webp_within(size, size, pack::WHATSAPP_ANIMATED_LIMIT, || {
+                let frames = render_frames(&animation)?;
+                Ok(resize_frames(w, h, frames, Some(pack::WHATSAPP_RESIZE)))
+            })
+        }
+        #[cfg(feature = "video")]
+        (StickerKind::Video, F::WhatsApp) => {
+            let video = Video::from_webm(&bytes)?;
+            let (w, h) = (video.width(), video.height());
+            let size = pack::WHATSAPP_RESIZE.size;
+            encode_animated_webp_within(size, size, pack::WHATSAPP_ANIMATED_LIMIT, || {
+                Ok(resize_frames(w, h, video.frames()?, Some(pack::WHATSAPP_RESIZE)))
+            })
+        }
+        // Signal wants animated stickers as apngs, which are downscaled (and lose frames) to fit into the limit
+        (StickerKind::Animated, F::Signal) => {
+            let animation = decode_lottie(&bytes)?;
+            let (w, h) = (animation.width(), animation.height());
+            encode_apng_within(w, h, pack::SIGNAL_LIMIT, || render_frames(&animation))
+        }
+        #[cfg(feature = "video")]
+        (StickerKind::Video, F::Signal) => {
+            let video = Video::from_webm(&bytes)?;
+            let (w, h) = (video.width(), video.height());
+            encode_apng_within(w, h, pack::SIGNAL_LIMIT, || video.frames())
+        }
+        #[cfg(feature = "video")]
+        (StickerKind::Video, F::Gif) => {
+            let video = Video::from_webm(&bytes)?;
+            let (w, h) = (video.width(), video.height());
+            let frames = resize_frames(w, h, video.frames()?, resize);
+            let (w, h) = resize::dimensions(w, h, resize);
+            encode_gif(w, h, frames)
+        }
+        #[cfg(feature = "video")]
+        (StickerKind::Video, F::Frames) => {
+            let video = Video::from_webm(&bytes)?;
+            let (w, h) = (video.width(), video.height());
+            let frames = resize_frames(w, h, video.frames()?, resize);
+            let (w, h) = resize::dimensions(w, h, resize);
+            encode_frames(w, h, frames)
+        }
+        _ => Err(ConvertError::Unsupported),
+    }
+}
+
+/// Converts all `stickers` using `config.workers` threads, calling `on_converted` with each converted sticker
+/// (and its index) in order.
+///
+/// This stops at the first error, or when `is_cancelled` returns `true` (in which case some of the stickers are
+/// not passed to `on_converted`, so the caller should check for cancellation too).
+pub fn convert_all<E: From<ConvertError>>(
+    kind: &StickerKind,
+    format: DownloadFormat,
+    resize: Option<Resize>,
+    stickers: Vec<Vec<u8>>,
+    config: &ConvertConfig,
+    is_cancelled: impl Fn() -> bool + Sync,
+    mut on_converted: impl FnMut(usize, Vec<u8>) -> Result<(), E>,
+) -> Result<(), E> {
+    let workers = config.workers.clamp(1, stickers.len().max(1));
+
+    // Reversed, so that `pop` returns stickers in order
+    let jobs: Vec<_> = stickers.into_iter().enumerate().rev().collect();
+    let jobs = Mutex::new(jobs);
+    let failed = AtomicBool::new(false);
+
+    let (tx, rx) = mpsc::channel();
+    let mut res = Ok(());
+
+    thread::scope(|s| {
+        let (jobs, failed, is_cancelled) = (&jobs, &failed, &is_cancelled);
+
+        for _ in 0..workers {
+            let tx = tx.clone();
+            s.spawn(move || loop {
+                if failed.load(Ordering::Relaxed) || is_cancelled() {
+                    break;
+                }
+
+                let Some((idx, bytes)) = jobs.lock().unwrap().pop() else {
+                    break;
+                };
+
+                if tx
+                    .send((idx, convert(kind, format, resize, bytes, config)))
+                    .is_err()
+                {
+                    break;
+                }
+            });
+        }
+
+        // Otherwise `rx` would wait for this sender forever
+        drop(tx);
+
+        // Stickers that were converted before the previous ones, at most `workers` of them
+        let mut ahead = BTreeMap::new();
+        let mut next = 0;
+
+        // Results are collected on this thread, so that `on_converted` doesn't need to be `Send`
+        for (idx, converted) in rx {
+            if res.is_err() {
+                continue;
+            }
+
+            let step = converted.map_err(E::from).and_then(|bytes| {
+                ahead.insert(idx, bytes);
+                while let Some(bytes) = ahead.remove(&next) {
+                    on_converted(next, bytes)?;
+                    next += 1;
+                }
+
+                Ok(())
+            });
+
+            if step.is_err() {
+                failed.store(true, Ordering::Relaxed);
+                res = step;
+            }
+        }
+    });
+
+    res
+}
+
+/// Returns the first frame of the sticker as `(width, height, rgba)`, e.g. to generate a thumbnail.
+pub fn first_frame(kind: &StickerKind, bytes: &[u8]) -> Result<(u32, u32, Vec<u8>), ConvertError> {
+    match kind {
+        StickerKind::Webp => decode_webp(bytes),
+        StickerKind::Animated => render_first_frame(&decode_lottie(bytes)?),
+        #[cfg(feature = "video")]
+        StickerKind::Video => {
+            let video = Video::from_webm(bytes)?;
+            let (rgba, _) = video.frames()?.next().ok_or(ConvertError::Unsupported)??;
+            Ok((video.width(), video.height(), rgba))
+        }
+        #[cfg(not(feature = "video"))]
+        StickerKind::Video => Err(ConvertError::Unsupported),
+    }
+}
+
+fn decode_webp(bytes: &[u8]) -> Result<(u32, u32, Vec<u8>), ConvertError> {
+    let (w, h, raw) = libwebp::WebPDecodeRGBA(bytes).map_err(|_| ConvertError::InvalidWebp)?;
+    Ok((w, h, raw.to_vec()))
+}
+
+/// Parses a lottie animation, `bytes` may be either a `.tgs` or a decompressed `.json`.
+fn decode_lottie(bytes: &[u8]) -> Result<Animation, ConvertError> {
+    const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+    if !bytes.starts_with(GZIP_MAGIC) {
+        return Animation::from_json(bytes).map_err(ConvertError::InvalidLottie);
+    }
+
+    let mut json = Vec::with_capacity(bytes.len() * 4);
+    GzDecoder::new(bytes)
+        .read_to_end(&mut json)
+        .map_err(ConvertError::Gzip)?;
+
+    Animation::from_json(&json).map_err(ConvertError::InvalidLottie)
+}
+
+fn render_first_frame(animation: &Animation) -> Result<(u32, u32, Vec<u8>), ConvertError> {
+    let mut canvas = new_canvas(animation)?;
+    animation.render(animation.frames().start, &mut canvas);
+
+    Ok((canvas.width(), canvas.height(), demultiply(&canvas)))
+}
+
+/// Encodes a single image into one of the static formats.
+fn encode_static(
+    w: u32,
+    h: u32,
+    rgba: &[u8],
+    format: DownloadFormat,
+    config: &ConvertConfig,
+) -> Result<Vec<u8>, ConvertError> {
+    match format {
+        DownloadFormat::Png => encode_png(w, h, rgba),
+        DownloadFormat::Webp => {
+            let webp =
+                libwebp::WebPEncodeRGBA(rgba, w, h, w * 4, WEBP_QUALITY).map_err(encode_err)?;
+            Ok(webp.to_vec())
+        }
+        DownloadFormat::WebpLossless => {
+            let webp = libwebp::WebPEncodeLosslessRGBA(rgba, w, h, w * 4).map_err(encode_err)?;
+            Ok(webp.to_vec())
+        }
+        DownloadFormat::Jpeg => encode_jpeg(w, h, rgba, config),
+        DownloadFormat::Avif => {
+            let img = ravif::Img::new(rgba.as_rgba(), w as usize, h as usize);
+            let encoded = ravif::Encoder::new()
+                .with_quality(AVIF_QUALITY)
+                .with_speed(AVIF_SPEED)
+                // Stickers are already converted in parallel by `convert_all`
+                .with_num_threads(Some(1))
+                .encode_rgba(img)
+                .map_err(encode_err)?;
+
+            Ok(encoded.avif_file)
+        }
+        _ => Err(ConvertError::Unsupported),
+    }
+}
+
+/// Encodes an image into a `.jpg`, flattening it onto `config.jpeg_background`.
+fn encode_jpeg(
+    w: u32,
+    h: u32,
+    rgba: &[u8],
+    config: &ConvertConfig,
+) -> Result<Vec<u8>, ConvertError> {
+    let (Ok(jpeg_w), Ok(jpeg_h)) = (u16::try_from(w), u16::try_from(h)) else {
+        return Err(ConvertError::Unsupported);
+    };
+
+    let rgb = flatten(rgba, config.jpeg_background.0);
+
+    let mut out = Vec::new();
+    jpeg_encoder::Encoder::new(&mut out, config.jpeg_quality)
+        .encode(&rgb, jpeg_w, jpeg_h, jpeg_encoder::ColorType::Rgb)
+        .map_err(encode_err)?;
+
+    Ok(out)
+}
+
+/// Converts rgba to rgb, blending it with `background`.
+fn flatten(rgba: &[u8], background: [u8; 3]) -> Vec<u8> {
+    rgba.chunks_exact(4)
+        .flat_map(|p| {
+            let alpha = p[3] as u16;
+            let blend =
+                |c: u8, bg: u8| ((c as u16 * alpha + bg as u16 * (255 - alpha)) / 255) as u8;
+
+            [
+                blend(p[0], background[0]),
+                blend(p[1], background[1]),
+                blend(p[2], background[2]),
+            ]
+        })
+        .collect()
+}
+
+pub fn encode_png(w: u32, h: u32, rgba: &[u8]) -> Result<Vec<u8>, ConvertError> {
+    lodepng::encode32(bytemuck::cast_slice::<u8, RGBA>(rgba), w as _, h as _)
+        .map_err(|e| ConvertError::Encode(e.into()))
+}
+
+/// Encodes an image into a lossy `.webp` of at most `limit` bytes, lowering the quality until it fits.
+pub fn encode_webp_within(
+    w: u32,
+    h: u32,
+    rgba: &[u8],
+    limit: usize,
+) -> Result<Vec<u8>, ConvertError> {
+    for quality in WEBP_LIMITED_QUALITIES {
+        let webp = libwebp::WebPEncodeRGBA(rgba, w, h, w * 4, quality).map_err(encode_err)?;
+        if webp.len() <= limit {
+            return Ok(webp.to_vec());
+        }
+    }
+
+    Err(too_big(limit))
+}
+
+/// Encodes frames, given as `(rgba, duration in seconds)`, into a looping animated `.webp` of at most `limit`
+/// bytes, lowering the quality until it fits.
+///
+/// `frames` is called for every attempt, since keeping all the frames in memory would take way too much of it.
+fn encode_animated_webp_within<I>(
+    w: u32,
+    h: u32,
+    limit: usize,
+    frames: impl Fn() -> Result<I, ConvertError>,
+) -> Result<Vec<u8>, ConvertError>
+where
+    I: Iterator<Item = Result<(Vec<u8>, f32), ConvertError>>,
+{
+    for quality in WEBP_LIMITED_QUALITIES {
+        let mut encoder = AnimEncoder::new(w, h, quality)?;
+
+        // Same as in `encode_gif`, but in milliseconds
+        let mut time = 0.;
+        let mut prev_end = 0;
+        for frame in frames()? {
+            let (rgba, duration) = frame?;
+
+            time += duration;
+            let end = (time * 1000.).round() as i32;
+            encoder.add(&rgba, end - prev_end)?;
+            prev_end = end;
+        }
+
+        let webp = encoder.finish()?;
+        if webp.len() <= limit {
+            return Ok(webp);
+        }
+    }
+
+    Err(too_big(limit))
+}
+
+/// Encodes frames of a `w`×`h` animation, given as `(rgba, duration in seconds)`, into a square looping `.gif`
+/// of at most `limit` bytes, trying [`GIF_LIMITED_ATTEMPTS`] until it fits.
+///
+/// `frames` is called for every attempt, see [`encode_animated_webp_within`].
+fn encode_gif_within<I>(
+    w: u32,
+    h: u32,
+    limit: usize,
+    frames: impl Fn() -> Result<I, ConvertError>,
+) -> Result<Vec<u8>, ConvertError>
+where
+    I: Iterator<Item = Result<(Vec<u8>, f32), ConvertError>>,
+{
+    for (size, step) in GIF_LIMITED_ATTEMPTS {
+        let resize = Resize {
+            size,
+            mode: ResizeMode::Pad,
+        };
+        // Skipped frames are still rendered, but whatever, it's the encoding that takes time
+        let frames = frames()?
+            .step_by(step)
+            .map(|frame| frame.map(|(rgba, duration)| (rgba, duration * step as f32)));
+
+        let gif = encode_gif(size, size, resize_frames(w, h, frames, Some(resize)))?;
+        if gif.len() <= limit {
+            return Ok(gif);
+        }
+    }
+
+    Err(too_big(limit))
+}
+
+/// Encodes frames into a looping `.png` of at most `limit` bytes, see [`APNG_LIMITED_ATTEMPTS`].
+fn encode_apng_within<I>(
+    w: u32,
+    h: u32,
+    limit: usize,
+    frames: impl Fn() -> Result<I, ConvertError>,
+) -> Result<Vec<u8>, ConvertError>
+where
+    I: Iterator<Item = Result<(Vec<u8>, f32), ConvertError>>,
+{
+    for (size, step) in APNG_LIMITED_ATTEMPTS {
+        let resize = Resize {
+            size,
+            mode: ResizeMode::Pad,
+        };
+        let frames = frames()?
+            .step_by(step)
+            .map(|frame| frame.map(|(rgba, duration)| (rgba, duration * step as f32)));
+        // `encode_apng` needs the number of frames upfront
+        let frames: Vec<_> = resize_frames(w, h, frames, Some(resize)).collect::<Result<_, _>>()?;
+
+        let apng = encode_apng(size, size, frames.len(), frames.into_iter().map(Ok))?;
+        if apng.len() <= limit {
+            return Ok(apng);
+        }
+    }
+
+    Err(too_big(limit))
+}
+
+fn too_big(limit: usize) -> ConvertError {
+    let kib = limit / 1024;
+    ConvertError::Encode(format!("couldn't compress the sticker to {kib} KiB").into())
+}
+
+/// Encodes frames, given as `(rgba, duration in seconds)`, into a looping `.gif`.
+fn encode_gif(
+    w: u32,
+    h: u32,
+    frames: impl Iterator<Item = Result<(Vec<u8>, f32), ConvertError>>,
+) -> Result<Vec<u8>, ConvertError> {
+    let (Ok(gif_w), Ok(gif_h)) = (u16::try_from(w), u16::try_from(h)) else {
+        return Err(ConvertError::Unsupported);
+    };
+
+    let mut out = Vec::new();
+    let mut encoder = gif::Encoder::new(&mut out, gif_w, gif_h, &[]).map_err(encode_err)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(encode_err)?;
+
+    let mut time = 0.;
+    let mut prev_end = 0;
+
+    for frame in frames {
+        let (mut rgba, duration) = frame?;
+        let mut gif_frame = gif::Frame::from_rgba_speed(gif_w, gif_h, &mut rgba, 10);
+
+        // Gif delays are in 1/100s of a second, so to not accumulate rounding errors
+        // we compute them from the time at which the frame should end.
+        time += duration;
+        let end = (time * 100.).round() as u32;
+        gif_frame.delay = (end - prev_end) as u16;
+        gif_frame.dispose = gif::DisposalMethod::Background;
+        prev_end = end;
+
+        encoder.write_frame(&gif_frame).map_err(encode_err)?;
+    }
+
+    encoder.into_inner().map_err(encode_err)?;
+    Ok(out)
+}
+
+/// Encodes `count` frames, given as `(rgba, duration in seconds)`, into an animated `.png`.
+///
+/// Apng only supports a single delay for all frames (well, not really, but the `png` crate does),
+/// the duration of the first frame is used.
+fn encode_apng(
+    w: u32,
+    h: u32,
+    count: usize,
+    mut frames: impl Iterator<Item = Result<(Vec<u8>, f32), ConvertError>>,
+) -> Result<Vec<u8>, ConvertError> {
+    let Some(first) = frames.next().transpose()? else {
+        return Err(ConvertError::Unsupported);
+    };
+
+    let mut out = Vec::new();
+    let mut encoder = png::Encoder::new(&mut out, w, h);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(count as u32, 0).map_err(encode_err)?;
+
+    // Delay as a fraction of a second, in milliseconds
+    let delay = (first.1 * 1000.).round() as u16;
+    encoder.set_frame_delay(delay, 1000).map_err(encode_err)?;
+
+    let mut writer = encoder.write_header().map_err(encode_err)?;
+
+    for frame in std::iter::once(Ok(first)).chain(frames) {
+        let (rgba, _) = frame?;
+        writer.write_image_data(&rgba).map_err(encode_err)?;
+    }
+
+    writer.finish().map_err(encode_err)?;
+    Ok(out)
+}
+
+/// Encodes frames, given as `(rgba, duration in seconds)`, into a `.zip` of `.png`s.
+///
+/// Since the frames are not necessarily evenly spaced, the durations are saved in `frames.json`.
+fn encode_frames(
+    w: u32,
+    h: u32,
+    frames: impl Iterator<Item = Result<(Vec<u8>, f32), ConvertError>>,
+) -> Result<Vec<u8>, ConvertError> {
+    #[derive(Serialize)]
+    struct FrameInfo {
+        path: String,
+        duration_ms: u32,
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // .png is already compressed, see `stuff::archive`
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+
+    let mut info = Vec::new();
+    for (idx, frame) in frames.enumerate() {
+        let (rgba, duration) = frame?;
+        let path = format!("{idx:03}.png");
+
+        zip.start_file(&path, options).map_err(encode_err)?;
+        zip.write_all(&encode_png(w, h, &rgba)?)
+            .map_err(encode_err)?;
+
+        let duration_ms = (duration * 1000.).round() as u32;
+        info.push(FrameInfo { path, duration_ms });
+    }
+
+    zip.start_file("frames.json", options).map_err(encode_err)?;
+    serde_json::to_writer_pretty(&mut zip, &info).map_err(encode_err)?;
+
+    Ok(zip.finish().map_err(encode_err)?.into_inner())
+}
+
+/// Resizes every frame of a `w`×`h` animation (the new size is given by [`resize::dimensions`]).
+fn resize_frames(
+    w: u32,
+    h: u32,
+    frames: impl Iterator<Item = Result<(Vec<u8>, f32), ConvertError>>,
+    resize: Option<Resize>,
+) -> impl Iterator<Item = Result<(Vec<u8>, f32), ConvertError>> {
+    frames.map(move |frame| {
+        let (rgba, duration) = frame?;
+        Ok((resize::resize(w, h, rgba, resize)?, duration))
+    })
+}
+
+/// Renders frames of the animation, respecting [`MAX_FPS`].
+fn render_frames(
+    animation: &Animation,
+) -> Result<impl Iterator<Item = Result<(Vec<u8>, f32), ConvertError>> + '_, ConvertError> {
+    let mut canvas = new_canvas(animation)?;
+
+    Ok(frames(animation).map(move |frame| {
+        canvas.fill(tiny_skia::Color::TRANSPARENT);
+        animation.render(frame.time, &mut canvas);
+
+        Ok((demultiply(&canvas), frame.duration))
+    }))
+}
+
+struct Frame {
+    /// Time of the frame in lottie frames.
+    time: f32,
+    /// Duration of the frame in seconds.
+    duration: f32,
+}
+
+/// Returns the frames that should be rendered, respecting [`MAX_FPS`].
+fn frames(animation: &Animation) -> impl Iterator<Item = Frame> {
+    let fps = animation.frame_rate().max(1.);
+    let step = (fps / MAX_FPS).ceil();
+    let duration = step / fps;
+    let range = animation.frames();
+
+    (0..)
+        .map(move |i| range.start + i as f32 * step)
+        .take_while(move |&time| time < range.end)
+        .map(move |time| Frame { time, duration })
+}
+
+fn new_canvas(animation: &Animation) -> Result<Pixmap, ConvertError> {
+    Pixmap::new(animation.width(), animation.height()).ok_or(ConvertError::Unsupported)
+}
+
+/// Converts premultiplied rgba (used by `tiny-skia`) to a normal one (used by everything else).
+fn demultiply(canvas: &Pixmap) -> Vec<u8> {
+    canvas
+        .pixels()
+        .iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect()
+}
+
+pub fn encode_err<E: std::error::Error + Send + Sync + 'static>(e: E) -> ConvertError {
+    ConvertError::Encode(e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::StickerKind;
+
+    use super::{convert_all, flatten};
+    use crate::{
+        config::ConvertConfig, error::converting::ConvertError, query_command::DownloadFormat,
+    };
+
+    #[test]
+    fn convert_all_keeps_order() {
+        let stickers: Vec<_> = (0..20u8).map(|i| vec![i]).collect();
+        let mut converted = Vec::new();
+
+        // Webp -> Webp doesn't need conversion, so this only tests the worker pool
+        let res = convert_all::<ConvertError>(
+            &StickerKind::Webp,
+            DownloadFormat::Webp,
+            None,
+            stickers,
+            &ConvertConfig {
+                workers: 4,
+                ..ConvertConfig::default()
+            },
+            || false,
+            |idx, bytes| {
+                converted.push((idx, bytes[0]));
+                Ok(())
+            },
+        );
+
+        assert!(res.is_ok());
+        assert_eq!(converted, (0..20).map(|i| (i, i as u8)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn flatten_onto_background() {
+        let rgba = [
+            0, 0, 0, 0, // transparent
+            10, 20, 30, 255, // opaque
+            0, 0, 0, 128, // half transparent black
+        ];
+
+        assert_eq!(
+            flatten(&rgba, [255, 0, 100]),
+            [255, 0, 100, 10, 20, 30, 127, 0, 49]
+        );
+    }
+}
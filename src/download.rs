@@ -1,85 +1,186 @@
 use std::{
-    collections::HashSet,
-    pin::Pin,
+    collections::HashMap,
+    io::{self, Read},
     sync::{Arc, Mutex},
-    task::Poll,
 };
 
+use flate2::read::GzDecoder;
 use futures::{stream, Stream, StreamExt};
-use teloxide::net::Download;
+use teloxide::{
+    net::Download,
+    requests::Requester,
+    types::{StickerKind, UserId},
+    DownloadError,
+};
 
 use crate::{
-    error::downloading::AlreadyDownloading,
+    cache::Cache,
+    cancel::CancelToken,
+    config::DownloadConfig,
+    error::{
+        callback_query::CallbackQueryError, downloading::AlreadyDownloading, Error, ResultExt,
+    },
     query_command::{DownloadFormat, DownloadTarget},
 };
 
 #[derive(Clone)]
 pub struct Downloader {
     bot: crate::Bot,
-    in_flight: Arc<Mutex<HashSet<i32>>>,
+    /// `None` if the cache couldn't be opened, then everything is downloaded every time.
+    cache: Option<Cache>,
+    /// How many files are downloaded concurrently.
+    concurrency: usize,
+    /// Jobs by the id of the message they were started from, with the user who started them.
+    in_flight: Arc<Mutex<HashMap<i32, (UserId, CancelToken)>>>,
+}
+
+/// A download job that is in flight, i.e. that is being downloaded/converted/sent.
+///
+/// The job is removed from in flight jobs when this is dropped.
+pub struct InFlight {
+    in_flight: Arc<Mutex<HashMap<i32, (UserId, CancelToken)>>>,
+    message_id: i32,
+    token: CancelToken,
+}
+
+/// Outcome of [`Downloader::cancel`].
+pub enum Cancel {
+    Cancelled,
+    /// There is no such job, e.g. it has already finished.
+    NoSuchJob,
+    /// The job was started by someone else, only they can cancel it.
+    NotOwner,
 }
 
 pub struct Tasks {
-    pub message_id: i32,
     pub format: DownloadFormat,
+    /// Kind of the stickers, some formats have different extensions for different kinds.
+    pub kind: StickerKind,
     pub stickers: Vec<Task>,
 }
 
 pub struct Task {
-    pub path: String,
+    /// `file_unique_id`, used as the cache key.
+    pub unique_id: String,
+    pub file_id: String,
+    /// Path of the file on telegram servers, `None` if the file was in the cache when the task was created
+    /// (then the path is only requested if the file is evicted before it's downloaded).
+    pub path: Option<String>,
     pub name: String,
     pub size: usize,
 }
 
-type Item = (
-    String,
-    Result<Vec<u8>, <crate::Bot as Download<'static>>::Err>,
-);
+type Item = (String, Result<Vec<u8>, Error<CallbackQueryError>>);
 
 impl Downloader {
-    pub fn new(bot: crate::Bot) -> Self {
+    pub fn new(bot: crate::Bot, cache: Option<Cache>, config: &DownloadConfig) -> Self {
         Self {
             bot,
+            cache,
+            concurrency: config.concurrency,
             in_flight: <_>::default(),
         }
     }
 
-    // TODO: progress
-    pub fn download(
+    /// Starts a job from the message `message_id` on behalf of `owner`,
+    /// who is the only one who can [`cancel`](Self::cancel) it.
+    ///
+    /// This should be done before anything is sent, so that pressing a button twice doesn't send things twice.
+    pub fn start(
         &self,
-        t: Tasks,
+        message_id: i32,
         target: DownloadTarget,
-    ) -> Result<impl Stream<Item = Item>, AlreadyDownloading> {
-        if !self.in_flight.lock().unwrap().insert(t.message_id) {
-            return Err(AlreadyDownloading(target));
+        owner: UserId,
+    ) -> Result<InFlight, AlreadyDownloading> {
+        let token = CancelToken::default();
+        {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if in_flight.contains_key(&message_id) {
+                return Err(AlreadyDownloading(target));
+            }
+            in_flight.insert(message_id, (owner, token.clone()));
         }
 
-        let format = t.format;
+        Ok(InFlight {
+            in_flight: Arc::clone(&self.in_flight),
+            message_id,
+            token,
+        })
+    }
 
-        let Self { bot, in_flight } = self.clone();
+    // TODO: progress
+    /// Downloads stickers of a job that was [`start`](Self::start)ed.
+    pub fn download(&self, t: Tasks) -> impl Stream<Item = Item> {
+        let format = t.format;
+        let ext = format.ext_for(&t.kind);
+        let Self {
+            bot,
+            cache,
+            concurrency,
+            ..
+        } = self.clone();
 
-        let stream = stream::iter(t.stickers)
+        stream::iter(t.stickers)
             .map(
                 move |Task {
-                          path, name, size, ..
+                          unique_id,
+                          file_id,
+                          path,
+                          name,
+                          size,
                       }| {
                     let bot = bot.clone();
+                    let cache = cache.clone();
                     async move {
-                        let file_name = format!("{name}.{ext}", ext = format.ext());
+                        let file_name = format!("{name}.{ext}");
 
-                        let mut bytes = Vec::with_capacity(size);
-                        let bytes = bot.download_file(&path, &mut bytes).await.map(|()| bytes);
+                        let bytes = fetch(&bot, cache.as_ref(), &unique_id, &file_id, path, size)
+                            .await
+                            .and_then(|bytes| match format {
+                                DownloadFormat::Lottie => {
+                                    gunzip(&bytes).map_err(|e| DownloadError::Io(e).into())
+                                }
+                                _ => Ok(bytes),
+                            });
 
                         (file_name, bytes)
                     }
                 },
             )
-            .buffered(C);
+            .buffered(concurrency)
+    }
 
-        let stream = defer_stream(stream, move || {
-            in_flight.lock().unwrap().remove(&t.message_id);
-        });
-        Ok(stream)
+    /// Returns `true` if the file with `file_unique_id` is cached, so it doesn't need a path (see [`Task::path`]).
+    pub fn is_cached(&self, unique_id: &str) -> bool {
+        self.cache
+            .as_ref()
+            .is_some_and(|cache| cache.contains(unique_id))
+    }
+
+    /// Cancels the job started from the message `message_id` if it was started by `user`.
+    ///
+    /// The job is removed from in flight jobs once it notices the cancellation and stops.
+    pub fn cancel(&self, message_id: i32, user: UserId) -> Cancel {
+        match self.in_flight.lock().unwrap().get(&message_id) {
+            Some((owner, _)) if *owner != user => Cancel::NotOwner,
+            Some((_, token)) => {
+                token.cancel();
+                Cancel::Cancelled
+            }
+            None => Cancel::NoSuchJob,
+        }
+    }
+}
+
+impl InFlight {
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(&self.message_id);
     }
 }
 
@@ -89,7 +190,7 @@ impl Tasks {
     }
 }
 
-/// How many files should be downloaded concurrently at a time.
+/// How many files should be downloaded concurrently at a time (by default, see `download.concurrency` in [`config`]).
 ///
 /// I've ""benched"" the download code by hand using `Instant::now()`/`.elapsed()`
 /// running with each `C` 3 times on the same 120-sticker sticker pack.
@@ -146,36 +247,47 @@ impl Tasks {
 /// 64,0.7969362,0.494137073,0.385381665
 /// 120,0.6980425659999999,0.313278465,0.30435578900000004
 /// ```
-const C: usize = 8;
-
-/// A hacky way to run something on drop of a stream
-fn defer_stream<S: Stream>(stream: S, f: impl FnOnce()) -> impl Stream<Item = S::Item> {
-    #[pin_project::pin_project(PinnedDrop)]
-    struct DeferStream<S, F: FnOnce()> {
-        #[pin]
-        stream: S,
-        f: Option<F>,
-    }
+///
+/// [`config`]: crate::config
+pub const C: usize = 8;
 
-    #[pin_project::pinned_drop]
-    impl<S, F: FnOnce()> PinnedDrop for DeferStream<S, F> {
-        fn drop(self: Pin<&mut Self>) {
-            if let Some(f) = self.project().f.take() {
-                f()
-            }
-        }
+/// Returns the file from the cache, or downloads it (and puts it in the cache).
+async fn fetch(
+    bot: &crate::Bot,
+    cache: Option<&Cache>,
+    unique_id: &str,
+    file_id: &str,
+    path: Option<String>,
+    size: usize,
+) -> Result<Vec<u8>, Error<CallbackQueryError>> {
+    if let Some(bytes) = match cache {
+        Some(cache) => cache.get(unique_id).await,
+        None => None,
+    } {
+        return Ok(bytes);
     }
 
-    impl<S: Stream, F: FnOnce()> Stream for DeferStream<S, F> {
-        type Item = S::Item;
+    let path = match path {
+        Some(path) => path,
+        // The file was evicted from the cache after the task was created
+        None => bot.get_file(file_id).await?.file_path,
+    };
 
-        fn poll_next(
-            self: std::pin::Pin<&mut Self>,
-            cx: &mut std::task::Context<'_>,
-        ) -> Poll<Option<Self::Item>> {
-            self.project().stream.poll_next(cx)
-        }
+    let mut bytes = Vec::with_capacity(size);
+    bot.download_file(&path, &mut bytes).await?;
+
+    if let Some(cache) = cache {
+        cache.put(unique_id, &bytes).await.fine();
     }
 
-    DeferStream { stream, f: Some(f) }
+    Ok(bytes)
+}
+
+/// Decompresses a gzipped file (e.g. a `.tgs` sticker, which is just a gzipped lottie `.json`).
+///
+/// `.tgs` files are limited to 64 KiB (and are usually way smaller), so doing this right in the async context is fine.
+fn gunzip(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(bytes.len() * 4);
+    GzDecoder::new(bytes).read_to_end(&mut out)?;
+    Ok(out)
 }
@@ -16,27 +16,52 @@ impl<E> From<RequestError> for Error<E> {
 pub mod callback_query {
     use std::fmt;
 
-    use teloxide::DownloadError;
+    use teloxide::{types::StickerKind, DownloadError};
 
     use crate::{
         error::{
+            archiving::ArchiveError,
+            converting::ConvertError,
+            database::DbError,
             downloading::{AlreadyDownloading, SendDocumentError},
+            limiting::RateLimited,
+            queueing::QueueFull,
             Error,
         },
-        query_command::DownloadTarget,
+        i18n::Language,
+        query_command::{DownloadFormat, DownloadTarget},
     };
 
     pub enum CallbackQueryError {
-        InvalidButtonData { data: String },
+        InvalidButtonData {
+            data: String,
+        },
         NoMessage,
         EmptyReply,
         ReplyIsNotSticker,
-        AnimatedStickerNotSupported,
-        VideoStickerNotSupported,
+        EmptySet,
+        PackTooSmall {
+            format: DownloadFormat,
+            min: usize,
+        },
+        UnsupportedFormat {
+            kind: StickerKind,
+            format: DownloadFormat,
+        },
         AlreadyDownloading(AlreadyDownloading),
+        RateLimited(RateLimited),
+        QueueFull,
+        NothingToCancel,
+        NotYourDownload,
+        NoSuchJob,
+        /// Re-sending a job from `/history` failed, this is not a post error, so that the menu stays.
+        ResendFailed(SendDocumentError),
+        Database(DbError),
 
         // post errors
         Download(DownloadError),
+        Convert(ConvertError),
+        Archive(ArchiveError),
         SendDocument(SendDocumentError),
     }
 
@@ -47,10 +72,21 @@ pub mod callback_query {
                 | CallbackQueryError::NoMessage
                 | CallbackQueryError::EmptyReply
                 | CallbackQueryError::ReplyIsNotSticker
-                | CallbackQueryError::AnimatedStickerNotSupported
-                | CallbackQueryError::VideoStickerNotSupported
-                | CallbackQueryError::AlreadyDownloading(_) => false,
-                CallbackQueryError::Download(_) | CallbackQueryError::SendDocument(_) => true,
+                | CallbackQueryError::EmptySet
+                | CallbackQueryError::PackTooSmall { .. }
+                | CallbackQueryError::UnsupportedFormat { .. }
+                | CallbackQueryError::AlreadyDownloading(_)
+                | CallbackQueryError::RateLimited(_)
+                | CallbackQueryError::QueueFull
+                | CallbackQueryError::NothingToCancel
+                | CallbackQueryError::NotYourDownload
+                | CallbackQueryError::NoSuchJob
+                | CallbackQueryError::ResendFailed(_)
+                | CallbackQueryError::Database(_) => false,
+                CallbackQueryError::Download(_)
+                | CallbackQueryError::Convert(_)
+                | CallbackQueryError::Archive(_)
+                | CallbackQueryError::SendDocument(_) => true,
             }
         }
     }
@@ -64,11 +100,24 @@ pub mod callback_query {
                 CallbackQueryError::NoMessage => write!(f, "No message? :c"),
                 CallbackQueryError::EmptyReply => write!(f, "Reply is empty"),
                 CallbackQueryError::ReplyIsNotSticker => write!(f, "Reply is not a sticker"),
-                CallbackQueryError::AnimatedStickerNotSupported => {
-                    write!(f, "Animated stickers are not yet supported")
+                CallbackQueryError::EmptySet => write!(f, "Sticker set is empty"),
+                CallbackQueryError::PackTooSmall { format, min } => {
+                    let format = Language::English.format_name(*format);
+
+                    write!(
+                        f,
+                        "This set is too small to be exported as {format} (at least {min} stickers are needed)"
+                    )
                 }
-                CallbackQueryError::VideoStickerNotSupported => {
-                    write!(f, "Video stickers are not yet supported")
+                CallbackQueryError::UnsupportedFormat { kind, format } => {
+                    let kind = match kind {
+                        StickerKind::Webp => "Static",
+                        StickerKind::Animated => "Animated",
+                        StickerKind::Video => "Video",
+                    };
+                    let format = Language::English.format_name(*format);
+
+                    write!(f, "{kind} stickers can't be downloaded as {format}")
                 }
                 CallbackQueryError::AlreadyDownloading(AlreadyDownloading(target)) => {
                     let what = match target {
@@ -78,10 +127,57 @@ pub mod callback_query {
 
                     write!(f, "This {what} is already being downloaded")
                 }
+                CallbackQueryError::RateLimited(RateLimited { retry_after }) => {
+                    // Round up, "try again in 0 seconds" would be confusing
+                    let secs = retry_after.as_secs() + (retry_after.subsec_nanos() > 0) as u64;
+
+                    write!(f, "Too many requests, try again in {secs} seconds")
+                }
+                CallbackQueryError::QueueFull => {
+                    write!(f, "The bot is too busy right now, try again later")
+                }
+                CallbackQueryError::NothingToCancel => {
+                    write!(
+                        f,
+                        "There is nothing to cancel, the download has already finished"
+                    )
+                }
+                CallbackQueryError::NotYourDownload => {
+                    write!(f, "Only the person who started this download can cancel it")
+                }
+                CallbackQueryError::NoSuchJob => {
+                    write!(f, "This download is not in your history (anymore)")
+                }
+                CallbackQueryError::ResendFailed(SendDocumentError(e)) => {
+                    write!(f, "Couldn't send the document: {e}, try again later")
+                }
+                CallbackQueryError::Database(err) => {
+                    write!(f, "Something went wrong ({err}), try again later")
+                }
                 CallbackQueryError::Download(err) => {
                     // FIXME: determine (s)
                     write!(f, "An error happened while downloading sticker(s): <code>{err}</code> :(\n\nTry again later.")
                 }
+                CallbackQueryError::Convert(err) => {
+                    write!(f, "Couldn't convert sticker(s): ")?;
+                    match err {
+                        ConvertError::Unsupported => write!(f, "unsupported format"),
+                        ConvertError::InvalidWebp => write!(f, "invalid .webp"),
+                        ConvertError::Gzip(e) => write!(f, "invalid .tgs: <code>{e}</code>"),
+                        ConvertError::InvalidLottie(e) => {
+                            write!(f, "invalid lottie: <code>{e}</code>")
+                        }
+                        ConvertError::InvalidWebm(e) => write!(f, "invalid .webm: {e}"),
+                        #[cfg(feature = "video")]
+                        ConvertError::Decode(e) => {
+                            write!(f, "couldn't decode video: <code>{e}</code>")
+                        }
+                        ConvertError::Encode(e) => write!(f, "<code>{e}</code>"),
+                    }
+                }
+                CallbackQueryError::Archive(ArchiveError(e)) => {
+                    write!(f, "Couldn't create the archive: <code>{e}</code>")
+                }
                 CallbackQueryError::SendDocument(SendDocumentError(e)) => {
                     write!(f, "Couldn't send the document: {e}.\n Try again later.")
                 }
@@ -94,11 +190,36 @@ pub mod callback_query {
             Error::Show(CallbackQueryError::AlreadyDownloading(ad))
         }
     }
+    impl From<RateLimited> for Error<CallbackQueryError> {
+        fn from(rl: RateLimited) -> Self {
+            Error::Show(CallbackQueryError::RateLimited(rl))
+        }
+    }
+    impl From<QueueFull> for Error<CallbackQueryError> {
+        fn from(_: QueueFull) -> Self {
+            Error::Show(CallbackQueryError::QueueFull)
+        }
+    }
+    impl From<DbError> for Error<CallbackQueryError> {
+        fn from(db: DbError) -> Self {
+            Error::Show(CallbackQueryError::Database(db))
+        }
+    }
     impl From<DownloadError> for Error<CallbackQueryError> {
         fn from(d: DownloadError) -> Self {
             Error::Show(CallbackQueryError::Download(d))
         }
     }
+    impl From<ConvertError> for Error<CallbackQueryError> {
+        fn from(c: ConvertError) -> Self {
+            Error::Show(CallbackQueryError::Convert(c))
+        }
+    }
+    impl From<ArchiveError> for Error<CallbackQueryError> {
+        fn from(a: ArchiveError) -> Self {
+            Error::Show(CallbackQueryError::Archive(a))
+        }
+    }
     impl From<SendDocumentError> for Error<CallbackQueryError> {
         fn from(sd: SendDocumentError) -> Self {
             Error::Show(CallbackQueryError::SendDocument(sd))
@@ -122,12 +243,52 @@ pub mod callback_query {
         Error::Show(CallbackQueryError::ReplyIsNotSticker)
     }
 
-    pub fn animated_sticker_not_supported() -> Error<CallbackQueryError> {
-        Error::Show(CallbackQueryError::AnimatedStickerNotSupported)
+    pub fn empty_set() -> Error<CallbackQueryError> {
+        Error::Show(CallbackQueryError::EmptySet)
+    }
+
+    pub fn nothing_to_cancel() -> Error<CallbackQueryError> {
+        Error::Show(CallbackQueryError::NothingToCancel)
+    }
+
+    pub fn not_your_download() -> Error<CallbackQueryError> {
+        Error::Show(CallbackQueryError::NotYourDownload)
+    }
+
+    pub fn no_such_job() -> Error<CallbackQueryError> {
+        Error::Show(CallbackQueryError::NoSuchJob)
     }
 
-    pub fn video_sticker_not_supported() -> Error<CallbackQueryError> {
-        Error::Show(CallbackQueryError::VideoStickerNotSupported)
+    pub fn resend_failed(e: SendDocumentError) -> Error<CallbackQueryError> {
+        Error::Show(CallbackQueryError::ResendFailed(e))
+    }
+
+    pub fn pack_too_small(format: DownloadFormat, min: usize) -> Error<CallbackQueryError> {
+        Error::Show(CallbackQueryError::PackTooSmall { format, min })
+    }
+
+    pub fn unsupported_format(
+        kind: &StickerKind,
+        format: DownloadFormat,
+    ) -> Error<CallbackQueryError> {
+        let kind = kind.clone();
+        Error::Show(CallbackQueryError::UnsupportedFormat { kind, format })
+    }
+}
+
+pub mod converting {
+    use std::io;
+
+    #[derive(Debug)]
+    pub enum ConvertError {
+        Unsupported,
+        InvalidWebp,
+        Gzip(io::Error),
+        InvalidLottie(serde_json::Error),
+        InvalidWebm(&'static str),
+        #[cfg(feature = "video")]
+        Decode(String),
+        Encode(Box<dyn std::error::Error + Send + Sync>),
     }
 }
 
@@ -141,6 +302,111 @@ pub mod downloading {
     pub struct AlreadyDownloading(pub DownloadTarget);
 }
 
+pub mod archiving {
+    use std::io;
+
+    pub struct ArchiveError(pub io::Error);
+}
+
+pub mod database {
+    use std::fmt;
+
+    #[derive(Debug)]
+    pub enum DbError {
+        Db(rusqlite::Error),
+        Json(serde_json::Error),
+    }
+
+    impl fmt::Display for DbError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                DbError::Db(e) => write!(f, "database error: {e}"),
+                DbError::Json(e) => write!(f, "invalid json: {e}"),
+            }
+        }
+    }
+
+    impl From<rusqlite::Error> for DbError {
+        fn from(e: rusqlite::Error) -> Self {
+            DbError::Db(e)
+        }
+    }
+    impl From<serde_json::Error> for DbError {
+        fn from(e: serde_json::Error) -> Self {
+            DbError::Json(e)
+        }
+    }
+}
+
+pub mod limiting {
+    use std::time::Duration;
+
+    pub struct RateLimited {
+        pub retry_after: Duration,
+    }
+}
+
+pub mod queueing {
+    pub struct QueueFull;
+}
+
+pub mod config {
+    use std::{fmt, io, path::PathBuf};
+
+    pub enum ConfigError {
+        Read {
+            path: PathBuf,
+            source: io::Error,
+        },
+        Parse {
+            path: PathBuf,
+            source: toml::de::Error,
+        },
+        /// Environment variable `var` is set, but its value is not valid.
+        InvalidVar {
+            var: &'static str,
+            /// `None` if the value is secret (e.g. the webhook url) and must not be printed.
+            value: Option<String>,
+            reason: String,
+        },
+        /// Value of `field` is out of its range.
+        Invalid {
+            field: &'static str,
+            expected: &'static str,
+        },
+    }
+
+    impl fmt::Display for ConfigError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ConfigError::Read { path, source } => {
+                    write!(f, "couldn't read {}: {source}", path.display())
+                }
+                ConfigError::Parse { path, source } => {
+                    write!(f, "couldn't parse {}: {source}", path.display())
+                }
+                ConfigError::InvalidVar {
+                    var,
+                    value: Some(value),
+                    reason,
+                } => {
+                    write!(f, "`{var}` is set to {value:?}, which is invalid: {reason}")
+                }
+                ConfigError::InvalidVar {
+                    var,
+                    value: None,
+                    reason,
+                } => {
+                    write!(f, "`{var}` is set to an invalid value: {reason}")
+                }
+                ConfigError::Invalid { field, expected } => {
+                    write!(f, "`{field}` must be {expected}")
+                }
+            }
+        }
+    }
+}
+
 pub trait ResultExt {
     type Item;
     type Err;
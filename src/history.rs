@@ -0,0 +1,220 @@
+//! History of finished downloads, which can be re-sent with `/history`.
+//!
+//! Only `file_id`s of the sent documents are stored, so re-sending doesn't download or upload anything.
+//! History is stored in the same database as [settings](crate::settings).
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{types::Type, Connection, OptionalExtension, Row};
+use teloxide::types::UserId;
+
+use crate::{
+    error::database::DbError,
+    query_command::{ActionDownload, QueryAction, QueryCommand},
+};
+
+/// Number of jobs shown on a single page of `/history`.
+pub const PAGE_SIZE: u32 = 5;
+
+/// Maximum number of remembered jobs of a single user, when there are more the oldest ones are forgotten.
+const MAX_JOBS: u32 = 100;
+
+#[derive(Clone)]
+pub struct History {
+    db: Arc<Mutex<Connection>>,
+}
+
+/// A finished download.
+pub struct Job {
+    pub id: i64,
+    pub set_name: Option<String>,
+    pub action: ActionDownload,
+    /// Unix timestamp of when the job was finished.
+    pub created: u64,
+    /// `file_id`s of the sent documents (there are multiple if the archive was split into volumes).
+    pub file_ids: Vec<String>,
+    pub caption: String,
+}
+
+impl History {
+    pub fn open(path: &Path) -> Result<Self, DbError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// History that is not persisted, for tests.
+    #[cfg(test)]
+    pub fn in_memory() -> Result<Self, DbError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(db: Connection) -> Result<Self, DbError> {
+        db.execute_batch(
+            "CREATE TABLE IF NOT EXISTS history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL,
+                set_name TEXT,
+                -- Encoded `QueryCommand::download`, i.e. the target, format, archive format and size
+                command TEXT NOT NULL,
+                created INTEGER NOT NULL,
+                -- json array
+                file_ids TEXT NOT NULL,
+                caption TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS history_user ON history (user_id, id);",
+        )?;
+
+        Ok(Self {
+            db: Arc::new(Mutex::new(db)),
+        })
+    }
+
+    /// Remembers a finished download of `user`.
+    pub fn record(
+        &self,
+        user: UserId,
+        set_name: Option<&str>,
+        action: ActionDownload,
+        file_ids: &[String],
+        caption: &str,
+    ) -> Result<(), DbError> {
+        let command = QueryCommand::download(action.target, action.format)
+            .with_archive(action.archive)
+            .with_resize(action.resize)
+            .encode();
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let file_ids = serde_json::to_string(file_ids)?;
+
+        let db = self.db.lock().unwrap();
+        db.execute(
+            "INSERT INTO history (user_id, set_name, command, created, file_ids, caption)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (user.0, set_name, command, created, file_ids, caption),
+        )?;
+        db.execute(
+            "DELETE FROM history WHERE user_id = ?1 AND id NOT IN
+                (SELECT id FROM history WHERE user_id = ?1 ORDER BY id DESC LIMIT ?2)",
+            (user.0, MAX_JOBS),
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns `page`-th page of jobs of `user` (newest first) and the total number of pages.
+    ///
+    /// Pages after the last one (e.g. from crafted button data) are the last page.
+    pub fn page(&self, user: UserId, page: u32) -> Result<(Vec<Job>, u32), DbError> {
+        let db = self.db.lock().unwrap();
+
+        let count: u32 = db.query_row(
+            "SELECT COUNT(*) FROM history WHERE user_id = ?1",
+            [user.0],
+            |row| row.get(0),
+        )?;
+        let pages = count.div_ceil(PAGE_SIZE);
+        let offset = page.min(pages.saturating_sub(1)) * PAGE_SIZE;
+
+        let mut statement = db.prepare(
+            "SELECT id, set_name, command, created, file_ids, caption FROM history
+                WHERE user_id = ?1 ORDER BY id DESC LIMIT ?2 OFFSET ?3",
+        )?;
+        // Jobs with commands that can't be decoded anymore are just skipped
+        let jobs = statement
+            .query_map((user.0, PAGE_SIZE, offset), read_row)?
+            .filter_map(Result::transpose)
+            .collect::<Result<_, _>>()?;
+
+        Ok((jobs, pages))
+    }
+
+    /// Returns a job of `user` by its id.
+    pub fn get(&self, user: UserId, id: i64) -> Result<Option<Job>, DbError> {
+        let db = self.db.lock().unwrap();
+
+        let job = db
+            .query_row(
+                "SELECT id, set_name, command, created, file_ids, caption FROM history
+                    WHERE user_id = ?1 AND id = ?2",
+                (user.0, id),
+                read_row,
+            )
+            .optional()?;
+
+        Ok(job.flatten())
+    }
+}
+
+/// Reads a job from a row, `None` if the command can't be decoded.
+fn read_row(row: &Row<'_>) -> rusqlite::Result<Option<Job>> {
+    let command: String = row.get(2)?;
+    let file_ids: String = row.get(4)?;
+
+    let action = match QueryCommand::decode(&command).map(|c| c.action) {
+        Some(QueryAction::Download(action)) => action,
+        _ => return Ok(None),
+    };
+
+    let file_ids = serde_json::from_str(&file_ids)
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(4, Type::Text, Box::new(err)))?;
+
+    Ok(Some(Job {
+        id: row.get(0)?,
+        set_name: row.get(1)?,
+        action,
+        created: row.get(3)?,
+        file_ids,
+        caption: row.get(5)?,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::UserId;
+
+    use crate::query_command::{ActionDownload, ArchiveFormat, DownloadFormat, DownloadTarget};
+
+    use super::{History, PAGE_SIZE};
+
+    #[test]
+    fn pages() {
+        let history = History::in_memory().unwrap();
+        let user = UserId(42);
+        let action = ActionDownload {
+            target: DownloadTarget::All,
+            format: DownloadFormat::Png,
+            archive: Some(ArchiveFormat::TarGz),
+            resize: None,
+        };
+
+        for i in 0..PAGE_SIZE + 2 {
+            let file_ids = [format!("file{i}")];
+            history
+                .record(user, Some("Animals"), action, &file_ids, "")
+                .unwrap();
+        }
+        history
+            .record(UserId(1), None, action, &["other".to_owned()], "")
+            .unwrap();
+
+        let (first, pages) = history.page(user, 0).unwrap();
+        assert_eq!(pages, 2);
+        assert_eq!(first.len(), PAGE_SIZE as usize);
+        assert_eq!(first[0].file_ids, [format!("file{}", PAGE_SIZE + 1)]);
+        assert_eq!(first[0].action, action);
+
+        let (second, _) = history.page(user, 1).unwrap();
+        assert_eq!(second.len(), 2);
+
+        let (last, _) = history.page(user, u32::MAX).unwrap();
+        assert_eq!(last[0].id, second[0].id);
+
+        let job = history.get(user, second[1].id).unwrap().unwrap();
+        assert_eq!(job.file_ids, ["file0"]);
+        assert!(history.get(UserId(1), job.id).unwrap().is_none());
+    }
+}
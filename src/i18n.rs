@@ -0,0 +1,320 @@
+//! Translations of the messages users see most often.
+//!
+//! FIXME: errors, progress messages and the command menu of telegram clients are not translated yet,
+//!        they are always in English.
+use serde::{Deserialize, Serialize};
+use teloxide::utils::html::{bold, code_inline, escape};
+
+use crate::{
+    command::Command,
+    query_command::{DownloadFormat, DownloadTarget, Resize, ResizeMode},
+    settings::{ThumbnailStyle, UserSettings},
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Language {
+    English,
+    Russian,
+}
+
+impl Language {
+    pub const ALL: [Self; 2] = [Self::English, Self::Russian];
+
+    /// Name of the language, in the language itself.
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::Russian => "Русский",
+        }
+    }
+
+    pub fn what_to_download(self) -> &'static str {
+        match self {
+            Language::English => "What do you want to download?",
+            Language::Russian => "Что вы хотите скачать?",
+        }
+    }
+
+    /// Text of `/help`, `commands` is the list of commands, one per line.
+    pub fn help(self, bot_username: &str, commands: &str) -> String {
+        match self {
+            Language::English => format!(
+                "Send me a sticker and I'll offer to download it, or the whole set it's from, \
+                in a number of formats.\n\
+                \n\
+                You can also share sets in any chat with <code>@{bot_username} &lt;set name or link&gt;</code>.\n\
+                \n\
+                Commands:\n\
+                {commands}"
+            ),
+            Language::Russian => format!(
+                "Пришлите мне стикер, и я предложу скачать его или весь набор, \
+                в одном из нескольких форматов.\n\
+                \n\
+                Ещё можно поделиться набором в любом чате: <code>@{bot_username} &lt;название набора или ссылка&gt;</code>.\n\
+                \n\
+                Команды:\n\
+                {commands}"
+            ),
+        }
+    }
+
+    /// Greeting of `/start`, the help is shown after it.
+    pub fn greeting(self) -> &'static str {
+        match self {
+            Language::English => "Hi! I can download stickers and sticker sets.",
+            Language::Russian => "Привет! Я умею скачивать стикеры и наборы стикеров.",
+        }
+    }
+
+    /// Reply to text which isn't a command.
+    pub fn use_help(self) -> &'static str {
+        match self {
+            Language::English => {
+                "Use /help for the list of available commands and instructions on how to use the bot"
+            }
+            Language::Russian => "Список команд и инструкции по использованию бота — в /help",
+        }
+    }
+
+    /// Reply to an unknown command, `name` is html.
+    pub fn unknown_command(self, name: &str) -> String {
+        match self {
+            Language::English => format!(
+                "Unknown command <code>{name}</code>, see /help for the list of available commands"
+            ),
+            Language::Russian => {
+                format!("Неизвестная команда <code>{name}</code>, список команд есть в /help")
+            }
+        }
+    }
+
+    pub fn command_description(self, command: Command) -> &'static str {
+        match self {
+            Language::English => command.description(),
+            Language::Russian => match command {
+                Command::Start => "Запустить бота",
+                Command::Help => "Показать помощь",
+                Command::Download => {
+                    "Скачать весь набор стикеров по названию или ссылке t.me/addstickers"
+                }
+                Command::Archive => "Показать или изменить формат архивов с несколькими стикерами",
+                Command::Resize => {
+                    "Скачать стикер или его набор другого размера, например как эмодзи для других платформ (ответом на стикер)"
+                }
+                Command::Settings => {
+                    "Изменить основной формат, имена файлов, язык и другие настройки"
+                }
+                Command::History => "Показать прошлые загрузки и получить их снова",
+            },
+        }
+    }
+
+    /// Arguments of a command, as shown in the help text.
+    pub fn command_args(self, command: Command) -> Option<&'static str> {
+        match (self, command) {
+            (Language::Russian, Command::Download) => Some("<название набора или ссылка>"),
+            (Language::Russian, Command::Resize) => {
+                Some("<512 | 256 | 128 | 96 | 64 | размер> [fit | fill | pad]")
+            }
+            (_, command) => command.args(),
+        }
+    }
+
+    pub fn cancel_button(self) -> &'static str {
+        match self {
+            Language::English => "Cancel",
+            Language::Russian => "Отмена",
+        }
+    }
+
+    /// Texts of the buttons under sets shared in inline mode: open the set and download it via the bot.
+    pub fn set_buttons(self) -> [&'static str; 2] {
+        match self {
+            Language::English => ["Open set", "Download"],
+            Language::Russian => ["Открыть набор", "Скачать"],
+        }
+    }
+
+    /// Description of a set (e.g. in captions), `title` is html, e.g. a link to the set.
+    pub fn set_info(self, title: &str, count: usize) -> String {
+        let count = bold(&count.to_string());
+
+        match self {
+            Language::English => format!("Stickers set: {title}\nStickers in set: {count}"),
+            Language::Russian => format!("Набор стикеров: {title}\nСтикеров в наборе: {count}"),
+        }
+    }
+
+    /// Text of a download button, e.g. "set as .png".
+    pub fn download_button(self, target: DownloadTarget, format: DownloadFormat) -> String {
+        let format = self.format_name(format);
+
+        match (self, target) {
+            (Language::English, DownloadTarget::Single) => format!("sticker as {format}"),
+            (Language::English, DownloadTarget::All) => format!("set as {format}"),
+            (Language::Russian, DownloadTarget::Single) => format!("стикер в {format}"),
+            (Language::Russian, DownloadTarget::All) => format!("набор в {format}"),
+        }
+    }
+
+    pub fn format_name(self, format: DownloadFormat) -> String {
+        match (self, format) {
+            (Language::English, DownloadFormat::Apng) => "animated .png".to_owned(),
+            (Language::English, DownloadFormat::Frames) => "frames".to_owned(),
+            (Language::Russian, DownloadFormat::Apng) => "анимированный .png".to_owned(),
+            (Language::Russian, DownloadFormat::Frames) => "кадры".to_owned(),
+            (Language::English, DownloadFormat::WebpLossless) => "lossless .webp".to_owned(),
+            (Language::Russian, DownloadFormat::WebpLossless) => ".webp без потерь".to_owned(),
+            (Language::English, DownloadFormat::WhatsApp) => "WhatsApp pack".to_owned(),
+            (Language::Russian, DownloadFormat::WhatsApp) => "пак WhatsApp".to_owned(),
+            (Language::English, DownloadFormat::Signal) => "Signal pack".to_owned(),
+            (Language::Russian, DownloadFormat::Signal) => "пак Signal".to_owned(),
+            (Language::English, DownloadFormat::Emoji) => "Discord/Slack emoji".to_owned(),
+            (Language::Russian, DownloadFormat::Emoji) => "эмодзи Discord/Slack".to_owned(),
+            (_, format) => format!(".{}", format.ext()),
+        }
+    }
+
+    /// Note about the size of stickers downloaded with `/resize`.
+    pub fn resized_to(self, Resize { size, mode }: Resize) -> String {
+        match (self, mode) {
+            (Language::English, ResizeMode::Fit) => {
+                format!("Stickers will be resized to fit into {size}×{size}")
+            }
+            (Language::English, ResizeMode::Fill) => {
+                format!("Stickers will be resized and cropped to {size}×{size}")
+            }
+            (Language::English, ResizeMode::Pad) => {
+                format!("Stickers will be resized and padded to {size}×{size}")
+            }
+            (Language::Russian, ResizeMode::Fit) => {
+                format!("Стикеры будут вписаны в {size}×{size}")
+            }
+            (Language::Russian, ResizeMode::Fill) => {
+                format!("Стикеры будут обрезаны до {size}×{size}")
+            }
+            (Language::Russian, ResizeMode::Pad) => {
+                format!("Стикеры будут дополнены прозрачными полями до {size}×{size}")
+            }
+        }
+    }
+
+    pub fn history_empty(self) -> &'static str {
+        match self {
+            Language::English => "You haven't downloaded anything yet",
+            Language::Russian => "Вы ещё ничего не скачивали",
+        }
+    }
+
+    /// Header of a `/history` page, the jobs are listed after it.
+    pub fn history_title(self, page: u32, pages: u32) -> String {
+        match self {
+            Language::English => format!(
+                "<b>History</b> (page {page}/{pages})\n\nPress a number to get the file again.\n"
+            ),
+            Language::Russian => format!(
+                "<b>История</b> (страница {page}/{pages})\n\nНажмите на номер, чтобы получить файл снова.\n"
+            ),
+        }
+    }
+
+    /// Texts of the buttons switching to newer and older pages of `/history`.
+    pub fn history_pages(self) -> [&'static str; 2] {
+        match self {
+            Language::English => ["« Newer", "Older »"],
+            Language::Russian => ["« Новее", "Старее »"],
+        }
+    }
+
+    /// Text of the `/settings` menu.
+    pub fn settings_text(self, settings: &UserSettings) -> String {
+        let [format, archive, naming, language, thumbnail] = self.settings_labels();
+        let values = self.settings_values(settings);
+        let naming_help = match self {
+            Language::English => {
+                "Press a button to change the setting. \
+                Custom file names can be set with <code>/settings naming &lt;template&gt;</code>, \
+                the template can contain {index}, {emoji}, {set} and {id}."
+            }
+            Language::Russian => {
+                "Нажмите на кнопку, чтобы изменить настройку. \
+                Свои имена файлов можно задать командой <code>/settings naming &lt;шаблон&gt;</code>, \
+                шаблон может содержать {index}, {emoji}, {set} и {id}."
+            }
+        };
+        let title = match self {
+            Language::English => "Settings",
+            Language::Russian => "Настройки",
+        };
+
+        format!(
+            "<b>{title}</b>\n\
+            \n\
+            {format}: {}\n\
+            {archive}: {}\n\
+            {naming}: {}\n\
+            {language}: {}\n\
+            {thumbnail}: {}\n\
+            \n\
+            {naming_help}",
+            values[0],
+            values[1],
+            code_inline(&escape(&values[2])),
+            values[3],
+            values[4],
+        )
+    }
+
+    /// Texts of the `/settings` menu buttons, in the same order as in [`settings_text`](Self::settings_text).
+    pub fn settings_buttons(self, settings: &UserSettings) -> [String; 5] {
+        let labels = self.settings_labels();
+        let values = self.settings_values(settings);
+
+        [0, 1, 2, 3, 4].map(|i| format!("{}: {}", labels[i], values[i]))
+    }
+
+    fn settings_labels(self) -> [&'static str; 5] {
+        match self {
+            Language::English => [
+                "Preferred format",
+                "Archive",
+                "File names",
+                "Language",
+                "Thumbnails",
+            ],
+            Language::Russian => [
+                "Основной формат",
+                "Архив",
+                "Имена файлов",
+                "Язык",
+                "Миниатюры",
+            ],
+        }
+    }
+
+    fn settings_values(self, settings: &UserSettings) -> [String; 5] {
+        let format = match (self, settings.format) {
+            (_, Some(format)) => self.format_name(format),
+            (Language::English, None) => "none".to_owned(),
+            (Language::Russian, None) => "нет".to_owned(),
+        };
+        let thumbnail = match (self, settings.thumbnail) {
+            (Language::English, ThumbnailStyle::Black) => "black background",
+            (Language::English, ThumbnailStyle::White) => "white background",
+            (Language::English, ThumbnailStyle::None) => "off",
+            (Language::Russian, ThumbnailStyle::Black) => "чёрный фон",
+            (Language::Russian, ThumbnailStyle::White) => "белый фон",
+            (Language::Russian, ThumbnailStyle::None) => "выключены",
+        };
+
+        [
+            format,
+            format!(".{}", settings.archive.ext()),
+            settings.naming.clone(),
+            settings.language.name().to_owned(),
+            thumbnail.to_owned(),
+        ]
+    }
+}
@@ -0,0 +1,177 @@
+//! Rate limiting of download requests and conversions.
+//!
+//! Downloading and (especially) converting stickers is expensive, so without limits it's quite easy to DDOS the bot.
+//!
+//! The constants here are the defaults, the actual limits are set in [`config`](crate::config).
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use teloxide::types::{ChatId, UserId};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{config::LimitsConfig, error::limiting::RateLimited};
+
+/// Number of requests a user can make in a burst.
+pub const USER_BURST: u32 = 5;
+/// How often a user gets a new request.
+pub const USER_REFILL: Duration = Duration::from_secs(20);
+
+/// Number of requests that can be made in a chat in a burst.
+///
+/// This is bigger than [`USER_BURST`], since a (group) chat has multiple users,
+/// but it still needs to be limited (stickers are sent to the chat after all).
+pub const CHAT_BURST: u32 = 10;
+/// How often a chat gets a new request.
+pub const CHAT_REFILL: Duration = Duration::from_secs(10);
+
+/// Maximum number of conversions running at the same time.
+///
+/// Conversions are run in `spawn_blocking` and are CPU-bound,
+/// so running more of them than there are cores only makes all of them slower.
+pub const MAX_CONVERSIONS: usize = 2;
+
+/// Buckets are forgotten when they are full, but only when there are this many of them (to not do that too often).
+const MAX_BUCKETS: usize = 1024;
+
+#[derive(Clone)]
+pub struct Limiter {
+    users: Arc<Mutex<Buckets<UserId>>>,
+    chats: Arc<Mutex<Buckets<ChatId>>>,
+    conversions: Arc<Semaphore>,
+}
+
+impl Limiter {
+    pub fn new(config: &LimitsConfig) -> Self {
+        let users = Buckets::new(config.user_burst as f32, config.user_refill());
+        let chats = Buckets::new(config.chat_burst as f32, config.chat_refill());
+
+        Self {
+            users: Arc::new(Mutex::new(users)),
+            chats: Arc::new(Mutex::new(chats)),
+            conversions: Arc::new(Semaphore::new(config.max_conversions)),
+        }
+    }
+
+    /// Takes a request from both `user`'s and `chat`'s budgets.
+    ///
+    /// If either of them is exhausted, nothing is taken and the error says when to try again.
+    pub fn check(&self, user: UserId, chat: ChatId) -> Result<(), RateLimited> {
+        let now = Instant::now();
+
+        // N.B. the order of locking must be the same everywhere, to prevent deadlocks
+        let mut users = self.users.lock().unwrap();
+        let mut chats = self.chats.lock().unwrap();
+
+        let retry_after = users.wait_time(user, now).max(chats.wait_time(chat, now));
+        if retry_after > Duration::ZERO {
+            return Err(RateLimited { retry_after });
+        }
+
+        users.take(user, now);
+        chats.take(chat, now);
+
+        Ok(())
+    }
+
+    /// Waits for a conversion slot, the conversion should be done while the permit is alive.
+    pub async fn conversion(&self) -> OwnedSemaphorePermit {
+        Arc::clone(&self.conversions)
+            .acquire_owned()
+            .await
+            .expect("the semaphore is never closed")
+    }
+}
+
+/// Token buckets, see <https://en.wikipedia.org/wiki/Token_bucket>.
+struct Buckets<K> {
+    capacity: f32,
+    refill: Duration,
+    buckets: HashMap<K, Bucket>,
+}
+
+struct Bucket {
+    tokens: f32,
+    updated: Instant,
+}
+
+impl<K: Eq + Hash> Buckets<K> {
+    fn new(capacity: f32, refill: Duration) -> Self {
+        Self {
+            capacity,
+            refill,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Returns how long `key` has to wait for a token.
+    fn wait_time(&mut self, key: K, now: Instant) -> Duration {
+        let tokens = self.refilled(key, now).tokens;
+
+        if tokens >= 1. {
+            Duration::ZERO
+        } else {
+            self.refill.mul_f32(1. - tokens)
+        }
+    }
+
+    fn take(&mut self, key: K, now: Instant) {
+        self.refilled(key, now).tokens -= 1.;
+    }
+
+    fn refilled(&mut self, key: K, now: Instant) -> &mut Bucket {
+        let &mut Self {
+            capacity, refill, ..
+        } = self;
+
+        if self.buckets.len() >= MAX_BUCKETS {
+            self.buckets.retain(|_, b| {
+                let elapsed = now.duration_since(b.updated);
+                b.tokens + elapsed.as_secs_f32() / refill.as_secs_f32() < capacity
+            });
+        }
+
+        let bucket = self.buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f32() / refill.as_secs_f32()).min(capacity);
+        bucket.updated = now;
+
+        bucket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Buckets;
+
+    #[test]
+    fn buckets() {
+        let mut buckets = Buckets::new(2., Duration::from_secs(10));
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            assert_eq!(buckets.wait_time(0, now), Duration::ZERO);
+            buckets.take(0, now);
+        }
+        assert_eq!(buckets.wait_time(0, now), Duration::from_secs(10));
+
+        // Other keys are independent
+        assert_eq!(buckets.wait_time(1, now), Duration::ZERO);
+
+        let later = now + Duration::from_secs(5);
+        assert_eq!(buckets.wait_time(0, later), Duration::from_secs(5));
+
+        let even_later = now + Duration::from_secs(10);
+        assert_eq!(buckets.wait_time(0, even_later), Duration::ZERO);
+    }
+}
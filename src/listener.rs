@@ -0,0 +1,79 @@
+//! How the bot receives updates: long polling (default) or webhook.
+//!
+//! Webhook mode is enabled by the `[webhook]` section of the [`config`](crate::config)
+//! (or by the `STICKER_WEBHOOK_URL` environment variable, `WEBHOOK_URL` works too).
+//!
+//! The webhook server speaks plain HTTP, so it's expected to be run behind a reverse proxy that terminates TLS
+//! (telegram only sends updates to `https://` urls) and forwards requests to `webhook.address` *without*
+//! changing the path, since the server only accepts updates on the path of `webhook.url`.
+use std::time::Duration;
+
+use teloxide::{
+    dispatching::update_listeners::{webhooks, Polling},
+    error_handlers::LoggingErrorHandler,
+    prelude::Dispatcher,
+    RequestError,
+};
+
+use crate::config::WebhookConfig;
+
+pub enum Listener {
+    Polling,
+    // Boxed, since options are quite big
+    Webhook(Box<webhooks::Options>),
+}
+
+impl Listener {
+    pub fn new(webhook: Option<WebhookConfig>) -> Self {
+        let Some(WebhookConfig {
+            url,
+            address,
+            secret,
+        }) = webhook
+        else {
+            return Self::Polling;
+        };
+
+        let mut options = webhooks::Options::new(address, url);
+        if let Some(secret) = secret {
+            options = options.secret_token(secret);
+        }
+
+        Self::Webhook(Box::new(options))
+    }
+
+    /// Runs `dp` until it's stopped (e.g. by ctrl+c).
+    pub async fn dispatch(
+        self,
+        bot: crate::Bot,
+        dp: &mut Dispatcher<crate::Bot, RequestError, ()>,
+    ) -> Result<(), RequestError> {
+        match self {
+            Self::Polling => {
+                log::info!("Receiving updates with long polling");
+
+                let listener = Polling::builder(bot)
+                    .timeout(Duration::from_secs(1))
+                    .build();
+
+                dp.dispatch_with_listener(listener, LoggingErrorHandler::new())
+                    .await;
+            }
+            Self::Webhook(options) => {
+                // N.B. the url itself is not logged, since it should be kept private
+                log::info!(
+                    "Receiving updates with webhook, listening on {}",
+                    options.address
+                );
+
+                // This sets the webhook and removes it when the dispatcher is stopped
+                let listener = webhooks::axum(bot, *options).await?;
+
+                dp.dispatch_with_listener(listener, LoggingErrorHandler::new())
+                    .await;
+            }
+        }
+
+        Ok(())
+    }
+}
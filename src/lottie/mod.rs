@@ -0,0 +1,141 @@
+//! A tiny lottie renderer, enough to render `.tgs` stickers.
+//!
+//! There is no (pure rust) lottie player that I could use, so here we are.
+//! See [`render`] for the list of things that are not supported.
+mod model;
+mod property;
+mod render;
+
+use std::ops::Range;
+
+use tiny_skia::Pixmap;
+
+pub struct Animation {
+    model: model::Animation,
+}
+
+impl Animation {
+    /// Parses a lottie animation from (decompressed) `.json`.
+    pub fn from_json(json: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(json).map(|model| Self { model })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.model.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.model.height
+    }
+
+    /// Number of frames per second.
+    pub fn frame_rate(&self) -> f32 {
+        self.model.frame_rate
+    }
+
+    /// Range of frames (not necessarily starting at 0) which make up this animation.
+    pub fn frames(&self) -> Range<f32> {
+        self.model.in_point..self.model.out_point
+    }
+
+    /// Renders `frame` on top of whatever there is on the `canvas`.
+    ///
+    /// The canvas is expected to be of the animation's size.
+    pub fn render(&self, frame: f32, canvas: &mut Pixmap) {
+        render::Renderer::new(&self.model).render(frame, canvas)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tiny_skia::Pixmap;
+
+    use super::Animation;
+
+    /// A red square moving from left to right, with an ease in-out.
+    const SQUARE: &str = r#"{
+        "v": "5.5.2", "fr": 60, "ip": 0, "op": 60, "w": 100, "h": 100,
+        "layers": [{
+            "ty": 4, "ind": 1, "ip": 0, "op": 60, "st": 0,
+            "ks": {
+                "a": { "a": 0, "k": [0, 0] },
+                "p": { "a": 1, "k": [
+                    { "t": 0, "s": [25, 50], "o": { "x": [0.4], "y": [0] }, "i": { "x": [0.6], "y": [1] } },
+                    { "t": 60, "s": [75, 50] }
+                ] },
+                "s": { "a": 0, "k": [100, 100] },
+                "r": { "a": 0, "k": 0 },
+                "o": { "a": 0, "k": 100 }
+            },
+            "shapes": [{
+                "ty": "gr",
+                "it": [
+                    { "ty": "rc", "p": { "a": 0, "k": [0, 0] }, "s": { "a": 0, "k": [20, 20] }, "r": { "a": 0, "k": 0 } },
+                    { "ty": "fl", "c": { "a": 0, "k": [1, 0, 0, 1] }, "o": { "a": 0, "k": 100 } },
+                    { "ty": "tr", "p": { "a": 0, "k": [0, 0] }, "a": { "a": 0, "k": [0, 0] } }
+                ]
+            }]
+        }]
+    }"#;
+
+    #[test]
+    fn moving_square() {
+        let animation = Animation::from_json(SQUARE.as_bytes()).unwrap();
+        assert_eq!(animation.frames(), 0.0..60.0);
+
+        let red_at = |frame: f32, x: u32| {
+            let mut canvas = Pixmap::new(100, 100).unwrap();
+            animation.render(frame, &mut canvas);
+
+            let p = canvas.pixel(x, 50).unwrap();
+            (p.red(), p.alpha()) == (255, 255)
+        };
+
+        assert!(red_at(0., 25));
+        assert!(!red_at(0., 50));
+
+        // halfway through (thanks to easing being symmetric)
+        assert!(red_at(30., 50));
+        assert!(!red_at(30., 25));
+
+        assert!(red_at(59., 75));
+    }
+
+    #[test]
+    fn malformed() {
+        // Position with a single component and truncated tangents
+        let short = SQUARE.replace(
+            r#"{ "t": 0, "s": [25, 50],"#,
+            r#"{ "t": 0, "s": [25], "to": [1], "ti": [1],"#,
+        );
+        assert_ne!(short, SQUARE);
+        let animation = Animation::from_json(short.as_bytes()).unwrap();
+        let mut canvas = Pixmap::new(100, 100).unwrap();
+        animation.render(30., &mut canvas);
+
+        let valueless = SQUARE
+            .replace(r#""s": [25, 50],"#, "")
+            .replace(r#", "s": [75, 50]"#, "");
+        assert!(Animation::from_json(valueless.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn recursive_precomp() {
+        // The asset contains a precomposition of itself (and a red square, so that there is something to draw)
+        const RECURSIVE: &str = r##"{
+            "v": "5.5.2", "fr": 60, "ip": 0, "op": 60, "w": 100, "h": 100,
+            "assets": [{ "id": "self", "layers": [
+                { "ty": 0, "refId": "self", "ip": 0, "op": 60 },
+                { "ty": 1, "sc": "#ff0000", "sw": 20, "sh": 20, "ip": 0, "op": 60 }
+            ] }],
+            "layers": [{ "ty": 0, "refId": "self", "ip": 0, "op": 60 }]
+        }"##;
+
+        let animation = Animation::from_json(RECURSIVE.as_bytes()).unwrap();
+        let mut canvas = Pixmap::new(100, 100).unwrap();
+        animation.render(0., &mut canvas);
+
+        let pixel = canvas.pixel(10, 10).unwrap();
+        assert_eq!((pixel.red(), pixel.alpha()), (255, 255));
+    }
+}
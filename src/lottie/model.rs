@@ -0,0 +1,477 @@
+//! Serde model of (the subset of) lottie that we know how to render.
+//!
+//! Telegram restricts `.tgs` stickers quite a bit (no images, no text, no expressions, no 3d layers, etc),
+//! which makes it feasible to render them without a full-blown lottie player.
+//!
+//! Field names follow the lottie "spec" (<https://lottiefiles.github.io/lottie-docs/>),
+//! which is to say that they are mostly one-two letter abbreviations.
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use serde_json::Value;
+
+#[derive(Deserialize)]
+pub(super) struct Animation {
+    #[serde(rename = "fr")]
+    pub frame_rate: f32,
+    #[serde(rename = "ip")]
+    pub in_point: f32,
+    #[serde(rename = "op")]
+    pub out_point: f32,
+    #[serde(rename = "w")]
+    pub width: u32,
+    #[serde(rename = "h")]
+    pub height: u32,
+    #[serde(default)]
+    pub assets: Vec<Asset>,
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Asset {
+    pub id: String,
+    /// Only precompositions have layers, image assets don't (and we don't support them anyway).
+    #[serde(default)]
+    pub layers: Vec<Layer>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Layer {
+    /// 0 - precomposition, 1 - solid, 2 - image, 3 - null, 4 - shape, 5 - text
+    #[serde(rename = "ty")]
+    pub kind: u8,
+    #[serde(rename = "ind")]
+    pub index: Option<i64>,
+    pub parent: Option<i64>,
+    #[serde(rename = "ks", default)]
+    pub transform: Transform,
+    #[serde(rename = "ip")]
+    pub in_point: f32,
+    #[serde(rename = "op")]
+    pub out_point: f32,
+    #[serde(rename = "st", default)]
+    pub start_time: f32,
+    #[serde(rename = "sr", default = "one")]
+    pub stretch: f32,
+    #[serde(rename = "hd", default)]
+    pub hidden: bool,
+
+    /// Track matte mode: 1 - alpha, 2 - inverted alpha, 3 - luma, 4 - inverted luma
+    #[serde(rename = "tt")]
+    pub matte_mode: Option<u8>,
+    /// Whatever this layer is a track matte for the layer below it
+    #[serde(rename = "td", default)]
+    pub is_matte: u8,
+    #[serde(rename = "masksProperties", default)]
+    pub masks: Vec<Mask>,
+
+    // shape layer
+    #[serde(default)]
+    pub shapes: Vec<Shape>,
+
+    // precomposition layer
+    #[serde(rename = "refId")]
+    pub ref_id: Option<String>,
+    #[serde(rename = "tm")]
+    pub time_remap: Option<Animated<Values>>,
+
+    // solid layer
+    #[serde(rename = "sc")]
+    pub solid_color: Option<String>,
+    #[serde(rename = "sw")]
+    pub solid_width: Option<f32>,
+    #[serde(rename = "sh")]
+    pub solid_height: Option<f32>,
+}
+
+#[derive(Deserialize, Default)]
+pub(super) struct Transform {
+    #[serde(rename = "a")]
+    pub anchor: Option<Animated<Values>>,
+    #[serde(rename = "p")]
+    pub position: Option<Position>,
+    #[serde(rename = "s")]
+    pub scale: Option<Animated<Values>>,
+    #[serde(rename = "r")]
+    pub rotation: Option<Animated<Values>>,
+    #[serde(rename = "o")]
+    pub opacity: Option<Animated<Values>>,
+    #[serde(rename = "sk")]
+    pub skew: Option<Animated<Values>>,
+    #[serde(rename = "sa")]
+    pub skew_axis: Option<Animated<Values>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(super) enum Position {
+    /// Position with separately animated coordinates
+    Split {
+        x: Animated<Values>,
+        y: Animated<Values>,
+    },
+    Combined(Animated<Values>),
+}
+
+#[derive(Deserialize)]
+pub(super) struct Mask {
+    /// "a" - add, "s" - subtract, "i" - intersect, "n" - none, everything else is treated as "a"
+    #[serde(default)]
+    pub mode: String,
+    pub pt: Animated<Bezier>,
+    #[serde(rename = "o")]
+    pub opacity: Option<Animated<Values>>,
+    #[serde(default)]
+    pub inv: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "ty")]
+pub(super) enum Shape {
+    #[serde(rename = "gr")]
+    Group(Group),
+    #[serde(rename = "sh")]
+    Path(Path),
+    #[serde(rename = "rc")]
+    Rect(Rect),
+    #[serde(rename = "el")]
+    Ellipse(Ellipse),
+    #[serde(rename = "sr")]
+    Star(Star),
+    #[serde(rename = "fl")]
+    Fill(Fill),
+    #[serde(rename = "st")]
+    Stroke(Stroke),
+    #[serde(rename = "gf")]
+    GradientFill(GradientFill),
+    #[serde(rename = "gs")]
+    GradientStroke(GradientStroke),
+    #[serde(rename = "tr")]
+    Transform(Transform),
+    #[serde(rename = "tm")]
+    Trim(Trim),
+    /// Repeaters, merge paths, rounded corners, etc
+    #[serde(other)]
+    Unsupported,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Group {
+    #[serde(rename = "it", default)]
+    pub items: Vec<Shape>,
+    #[serde(rename = "hd", default)]
+    pub hidden: bool,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Path {
+    #[serde(rename = "ks")]
+    pub bezier: Animated<Bezier>,
+    #[serde(rename = "hd", default)]
+    pub hidden: bool,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Rect {
+    #[serde(rename = "p")]
+    pub position: Animated<Values>,
+    #[serde(rename = "s")]
+    pub size: Animated<Values>,
+    #[serde(rename = "r")]
+    pub roundness: Option<Animated<Values>>,
+    #[serde(rename = "hd", default)]
+    pub hidden: bool,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Ellipse {
+    #[serde(rename = "p")]
+    pub position: Animated<Values>,
+    #[serde(rename = "s")]
+    pub size: Animated<Values>,
+    #[serde(rename = "hd", default)]
+    pub hidden: bool,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Star {
+    /// 1 - star, 2 - polygon
+    #[serde(rename = "sy", default = "one_u8")]
+    pub star_kind: u8,
+    #[serde(rename = "p")]
+    pub position: Animated<Values>,
+    #[serde(rename = "pt")]
+    pub points: Animated<Values>,
+    #[serde(rename = "r")]
+    pub rotation: Option<Animated<Values>>,
+    #[serde(rename = "ir")]
+    pub inner_radius: Option<Animated<Values>>,
+    #[serde(rename = "or")]
+    pub outer_radius: Animated<Values>,
+    #[serde(rename = "hd", default)]
+    pub hidden: bool,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Fill {
+    #[serde(rename = "c")]
+    pub color: Animated<Values>,
+    #[serde(rename = "o")]
+    pub opacity: Option<Animated<Values>>,
+    /// 1 - non zero, 2 - even odd
+    #[serde(rename = "r", default = "one_u8")]
+    pub fill_rule: u8,
+    #[serde(rename = "hd", default)]
+    pub hidden: bool,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Stroke {
+    #[serde(rename = "c")]
+    pub color: Animated<Values>,
+    #[serde(rename = "o")]
+    pub opacity: Option<Animated<Values>>,
+    #[serde(flatten)]
+    pub line: Line,
+    #[serde(rename = "hd", default)]
+    pub hidden: bool,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Line {
+    #[serde(rename = "w")]
+    pub width: Animated<Values>,
+    /// 1 - butt, 2 - round, 3 - square
+    #[serde(rename = "lc", default = "one_u8")]
+    pub cap: u8,
+    /// 1 - miter, 2 - round, 3 - bevel
+    #[serde(rename = "lj", default = "one_u8")]
+    pub join: u8,
+    #[serde(rename = "ml")]
+    pub miter_limit: Option<f32>,
+    #[serde(rename = "d", default)]
+    pub dashes: Vec<Dash>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Dash {
+    /// "d" - dash, "g" - gap, "o" - offset
+    #[serde(rename = "n")]
+    pub kind: String,
+    #[serde(rename = "v")]
+    pub value: Animated<Values>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct GradientFill {
+    #[serde(flatten)]
+    pub gradient: Gradient,
+    #[serde(rename = "r", default = "one_u8")]
+    pub fill_rule: u8,
+    #[serde(rename = "hd", default)]
+    pub hidden: bool,
+}
+
+#[derive(Deserialize)]
+pub(super) struct GradientStroke {
+    #[serde(flatten)]
+    pub gradient: Gradient,
+    #[serde(flatten)]
+    pub line: Line,
+    #[serde(rename = "hd", default)]
+    pub hidden: bool,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Gradient {
+    #[serde(rename = "o")]
+    pub opacity: Option<Animated<Values>>,
+    #[serde(rename = "s")]
+    pub start: Animated<Values>,
+    #[serde(rename = "e")]
+    pub end: Animated<Values>,
+    /// 1 - linear, 2 - radial
+    #[serde(rename = "t", default = "one_u8")]
+    pub kind: u8,
+    #[serde(rename = "g")]
+    pub colors: GradientColors,
+}
+
+#[derive(Deserialize)]
+pub(super) struct GradientColors {
+    /// Number of color stops, `k` contains `4 * p` numbers for colors (offset, r, g, b),
+    /// optionally followed by `2 * p` numbers for opacity (offset, a).
+    #[serde(rename = "p")]
+    pub count: usize,
+    #[serde(rename = "k")]
+    pub values: Animated<Values>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Trim {
+    #[serde(rename = "s")]
+    pub start: Animated<Values>,
+    #[serde(rename = "e")]
+    pub end: Animated<Values>,
+    #[serde(rename = "o")]
+    pub offset: Option<Animated<Values>>,
+    /// 1 - trim shapes simultaneously, 2 - trim shapes individually (i.e. as if they were one long path)
+    #[serde(rename = "m", default = "one_u8")]
+    pub mode: u8,
+    #[serde(rename = "hd", default)]
+    pub hidden: bool,
+}
+
+/// A property that may be either static or animated.
+pub(super) enum Animated<T> {
+    Static(T),
+    Keyframes(Vec<Keyframe<T>>),
+}
+
+pub(super) struct Keyframe<T> {
+    pub time: f32,
+    /// Value at the start of the keyframe.
+    ///
+    /// Older lottie files do not specify it for the last keyframe (it's only used for its `time`).
+    pub start: Option<T>,
+    /// Value at the end of the keyframe, newer lottie files use `start` of the next keyframe instead.
+    pub end: Option<T>,
+    pub ease_out: Option<Easing>,
+    pub ease_in: Option<Easing>,
+    pub hold: bool,
+    /// Spatial tangents, only used for positions.
+    pub tangent_out: Option<Vec<f32>>,
+    pub tangent_in: Option<Vec<f32>>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct Easing {
+    pub x: Values,
+    pub y: Values,
+}
+
+/// A list of numbers, which may be serialized as a single number.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(super) struct Values(pub Vec<f32>);
+
+/// Cubic bezier path with vertices and relative tangents.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(from = "BezierRepr")]
+pub(super) struct Bezier {
+    pub closed: bool,
+    pub vertices: Vec<[f32; 2]>,
+    pub tangents_in: Vec<[f32; 2]>,
+    pub tangents_out: Vec<[f32; 2]>,
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for Animated<T> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        #[derive(Deserialize)]
+        struct Raw {
+            k: Value,
+        }
+
+        #[derive(Deserialize)]
+        struct RawKeyframe<T> {
+            t: f32,
+            s: Option<T>,
+            e: Option<T>,
+            o: Option<Easing>,
+            i: Option<Easing>,
+            #[serde(default)]
+            h: u8,
+            to: Option<Vec<f32>>,
+            ti: Option<Vec<f32>>,
+        }
+
+        let Raw { k } = Raw::deserialize(d)?;
+
+        let is_animated =
+            matches!(&k, Value::Array(a) if a.first().and_then(|kf| kf.get("t")).is_some());
+        if !is_animated {
+            return serde_json::from_value(k)
+                .map(Animated::Static)
+                .map_err(D::Error::custom);
+        }
+
+        let keyframes: Vec<RawKeyframe<T>> = serde_json::from_value(k).map_err(D::Error::custom)?;
+
+        // Every keyframe takes its value from `s` of itself or of a previous one (see `Animated::at`)
+        if keyframes.iter().all(|kf| kf.s.is_none()) {
+            return Err(D::Error::custom("keyframes without values"));
+        }
+
+        let keyframes = keyframes
+            .into_iter()
+            .map(|kf| Keyframe {
+                time: kf.t,
+                start: kf.s,
+                end: kf.e,
+                ease_out: kf.o,
+                ease_in: kf.i,
+                hold: kf.h == 1,
+                tangent_out: kf.to,
+                tangent_in: kf.ti,
+            })
+            .collect();
+
+        Ok(Animated::Keyframes(keyframes))
+    }
+}
+
+impl<'de> Deserialize<'de> for Values {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            One(f32),
+            Many(Vec<f32>),
+        }
+
+        Ok(match Repr::deserialize(d)? {
+            Repr::One(x) => Values(vec![x]),
+            Repr::Many(xs) => Values(xs),
+        })
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BezierRepr {
+    One(RawBezier),
+    /// Keyframes store shapes as a single element array
+    Many(Vec<RawBezier>),
+}
+
+#[derive(Deserialize, Default)]
+struct RawBezier {
+    #[serde(default)]
+    c: bool,
+    v: Vec<[f32; 2]>,
+    i: Vec<[f32; 2]>,
+    o: Vec<[f32; 2]>,
+}
+
+impl From<BezierRepr> for Bezier {
+    fn from(repr: BezierRepr) -> Self {
+        let RawBezier { c, v, i, o } = match repr {
+            BezierRepr::One(b) => b,
+            BezierRepr::Many(bs) => bs.into_iter().next().unwrap_or_default(),
+        };
+
+        Bezier {
+            closed: c,
+            vertices: v,
+            tangents_in: i,
+            tangents_out: o,
+        }
+    }
+}
+
+fn one() -> f32 {
+    1.
+}
+
+fn one_u8() -> u8 {
+    1
+}
@@ -0,0 +1,219 @@
+//! Evaluation of (possibly animated) properties at a given frame.
+use super::model::{Animated, Bezier, Easing, Keyframe, Values};
+
+/// `Default` is the value of malformed properties (e.g. keyframes without values).
+pub(super) trait Lerp: Clone + Default {
+    /// Interpolates between `a` and `b`, `t` returns the progress for a given dimension.
+    fn lerp(a: &Self, b: &Self, t: &dyn Fn(usize) -> f32) -> Self;
+}
+
+impl<T: Lerp> Animated<T> {
+    pub(super) fn at(&self, frame: f32) -> T {
+        self.at_with(frame, |a, b, _, t| T::lerp(a, b, t))
+    }
+
+    fn at_with(
+        &self,
+        frame: f32,
+        lerp: impl FnOnce(&T, &T, &Keyframe<T>, &dyn Fn(usize) -> f32) -> T,
+    ) -> T {
+        let keyframes = match self {
+            Animated::Static(v) => return v.clone(),
+            Animated::Keyframes(kfs) => kfs,
+        };
+
+        // Value at the start of `idx`-th keyframe
+        let start = |idx: usize| -> T {
+            keyframes[..=idx]
+                .iter()
+                .rev()
+                .find_map(|kf| kf.start.as_ref())
+                .or_else(|| keyframes.iter().find_map(|kf| kf.start.as_ref()))
+                .cloned()
+                // Keyframes without any values are rejected when parsing, but just in case
+                .unwrap_or_default()
+        };
+
+        let Some(first) = keyframes.first() else {
+            return T::default();
+        };
+        if keyframes.len() == 1 || frame <= first.time {
+            return start(0);
+        }
+
+        for (idx, w) in keyframes.windows(2).enumerate() {
+            let [a, b] = [&w[0], &w[1]];
+
+            if frame >= b.time {
+                continue;
+            }
+
+            let from = start(idx);
+            if a.hold {
+                return from;
+            }
+
+            let to = match a.end.as_ref().or(b.start.as_ref()) {
+                Some(to) => to,
+                None => return from,
+            };
+
+            let progress = (frame - a.time) / (b.time - a.time);
+            let eased = |dim: usize| match (&a.ease_out, &a.ease_in) {
+                (Some(o), Some(i)) => ease(o, i, dim, progress),
+                _ => progress,
+            };
+
+            return lerp(&from, to, a, &eased);
+        }
+
+        // After the last keyframe
+        let last = keyframes.len() - 1;
+        match (
+            &keyframes[last].start,
+            last.checked_sub(1).map(|i| &keyframes[i]),
+        ) {
+            (Some(v), _) => v.clone(),
+            (None, Some(Keyframe { end: Some(v), .. })) => v.clone(),
+            (None, _) => start(last),
+        }
+    }
+}
+
+impl Animated<Values> {
+    /// Returns the first component of the value.
+    pub(super) fn scalar_at(&self, frame: f32) -> f32 {
+        self.at(frame).0.first().copied().unwrap_or(0.)
+    }
+
+    /// Returns the first two components of the value, useful for positions and sizes.
+    pub(super) fn point_at(&self, frame: f32) -> [f32; 2] {
+        xy(&self.at(frame).0)
+    }
+
+    /// Same as [`point_at`], but follows spatial tangents (i.e. the position moves along a curve, not a straight line).
+    ///
+    /// [`point_at`]: Animated::point_at
+    pub(super) fn position_at(&self, frame: f32) -> [f32; 2] {
+        let v = self.at_with(frame, |a, b, kf, t| {
+            match (&kf.tangent_out, &kf.tangent_in) {
+                (Some(to), Some(ti)) if to.iter().chain(ti).any(|&x| x != 0.) => {
+                    let (p0, p3) = (xy(&a.0), xy(&b.0));
+                    let (to, ti) = (xy(to), xy(ti));
+                    let p1 = [p0[0] + to[0], p0[1] + to[1]];
+                    let p2 = [p3[0] + ti[0], p3[1] + ti[1]];
+
+                    let [x, y] = point_along_cubic([p0, p1, p2, p3], t(0));
+                    Values(vec![x, y])
+                }
+                _ => Values::lerp(a, b, t),
+            }
+        });
+
+        xy(&v.0)
+    }
+}
+
+/// Returns the first two components of a point, missing ones (in malformed files) are `0`.
+fn xy(v: &[f32]) -> [f32; 2] {
+    [
+        v.first().copied().unwrap_or(0.),
+        v.get(1).copied().unwrap_or(0.),
+    ]
+}
+
+impl Lerp for Values {
+    fn lerp(a: &Self, b: &Self, t: &dyn Fn(usize) -> f32) -> Self {
+        let v =
+            a.0.iter()
+                .zip(&b.0)
+                .enumerate()
+                .map(|(dim, (a, b))| a + (b - a) * t(dim))
+                .collect();
+
+        Values(v)
+    }
+}
+
+impl Lerp for Bezier {
+    fn lerp(a: &Self, b: &Self, t: &dyn Fn(usize) -> f32) -> Self {
+        let t = t(0);
+        let lerp = |xs: &[[f32; 2]], ys: &[[f32; 2]]| {
+            xs.iter()
+                .zip(ys)
+                .map(|(x, y)| [x[0] + (y[0] - x[0]) * t, x[1] + (y[1] - x[1]) * t])
+                .collect()
+        };
+
+        Bezier {
+            closed: a.closed,
+            vertices: lerp(&a.vertices, &b.vertices),
+            tangents_in: lerp(&a.tangents_in, &b.tangents_in),
+            tangents_out: lerp(&a.tangents_out, &b.tangents_out),
+        }
+    }
+}
+
+/// Applies cubic bezier easing to the linear `progress`.
+///
+/// The easing curve goes from `(0, 0)` to `(1, 1)` with `out` and `in` being the control points.
+fn ease(out: &Easing, in_: &Easing, dim: usize, progress: f32) -> f32 {
+    let get = |v: &Values| v.0.get(dim).or_else(|| v.0.first()).copied().unwrap_or(0.);
+    let (x1, y1, x2, y2) = (get(&out.x), get(&out.y), get(&in_.x), get(&in_.y));
+
+    let bezier = |p1: f32, p2: f32, t: f32| {
+        let mt = 1. - t;
+        3. * mt * mt * t * p1 + 3. * mt * t * t * p2 + t * t * t
+    };
+
+    // Find `t` such that `bezier_x(t) = progress` with a binary search.
+    // Newton's method would be faster, but it's not robust, and this is plenty fast anyway.
+    let (mut lo, mut hi) = (0f32, 1f32);
+    for _ in 0..24 {
+        let mid = (lo + hi) / 2.;
+        if bezier(x1, x2, mid) < progress {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    bezier(y1, y2, (lo + hi) / 2.)
+}
+
+/// Returns a point which is at `progress` of the cubic bezier's length.
+fn point_along_cubic(p: [[f32; 2]; 4], progress: f32) -> [f32; 2] {
+    const N: usize = 32;
+
+    let at = |t: f32| {
+        let mt = 1. - t;
+        let [a, b, c, d] = [mt * mt * mt, 3. * mt * mt * t, 3. * mt * t * t, t * t * t];
+        [
+            a * p[0][0] + b * p[1][0] + c * p[2][0] + d * p[3][0],
+            a * p[0][1] + b * p[1][1] + c * p[2][1] + d * p[3][1],
+        ]
+    };
+
+    let mut lengths = [0f32; N + 1];
+    let mut prev = at(0.);
+    for i in 1..=N {
+        let next = at(i as f32 / N as f32);
+        lengths[i] = lengths[i - 1] + (next[0] - prev[0]).hypot(next[1] - prev[1]);
+        prev = next;
+    }
+
+    let target = lengths[N] * progress;
+    let i = lengths
+        .iter()
+        .position(|&l| l >= target)
+        .unwrap_or(N)
+        .max(1);
+    let segment = lengths[i] - lengths[i - 1];
+    let local = if segment > 0. {
+        (target - lengths[i - 1]) / segment
+    } else {
+        0.
+    };
+
+    at((i as f32 - 1. + local) / N as f32)
+}
@@ -0,0 +1,934 @@
+//! Rendering of lottie layers/shapes with `tiny-skia`.
+//!
+//! Notable unsupported things (they are either forbidden in `.tgs` or are quite rare):
+//! images, text, 3d layers, blend modes, expressions, repeaters, merge paths, rounded corners, gradient highlights.
+use std::collections::HashMap;
+
+use tiny_skia::{
+    Color, FillRule, GradientStop, LineCap, LineJoin, LinearGradient, Mask, MaskType, Paint,
+    PathBuilder, Pixmap, PixmapPaint, Point, RadialGradient, Shader, SpreadMode, Stroke,
+    StrokeDash, Transform,
+};
+
+use super::model::{self, Animated, Layer, Shape, Values};
+
+/// Maximum nesting of precompositions.
+///
+/// Assets can refer to themselves (directly or through other assets) in malformed files,
+/// without a limit that would overflow the stack (and abort the whole process, since we render in `spawn_blocking`).
+const MAX_PRECOMP_DEPTH: u8 = 8;
+
+pub(super) struct Renderer<'a> {
+    animation: &'a model::Animation,
+    assets: HashMap<&'a str, &'a [Layer]>,
+}
+
+impl<'a> Renderer<'a> {
+    pub(super) fn new(animation: &'a model::Animation) -> Self {
+        let assets = animation
+            .assets
+            .iter()
+            .map(|a| (a.id.as_str(), a.layers.as_slice()))
+            .collect();
+
+        Self { animation, assets }
+    }
+
+    pub(super) fn render(&self, frame: f32, canvas: &mut Pixmap) {
+        self.render_layers(
+            &self.animation.layers,
+            frame,
+            Transform::identity(),
+            1.,
+            canvas,
+            0,
+        )
+    }
+
+    /// `depth` is the nesting of precompositions, see [`MAX_PRECOMP_DEPTH`].
+    fn render_layers(
+        &self,
+        layers: &[Layer],
+        frame: f32,
+        ts: Transform,
+        alpha: f32,
+        canvas: &mut Pixmap,
+        depth: u8,
+    ) {
+        // Layers are listed top to bottom, so we need to draw them in reverse
+        for (idx, layer) in layers.iter().enumerate().rev() {
+            if layer.hidden || layer.is_matte == 1 {
+                continue;
+            }
+
+            let matte = match (layer.matte_mode, idx.checked_sub(1).map(|i| &layers[i])) {
+                (Some(mode), Some(matte)) if matte.is_matte == 1 => Some((mode, matte)),
+                _ => None,
+            };
+
+            match matte {
+                None => self.render_layer(layers, layer, frame, ts, alpha, canvas, depth),
+                Some((mode, matte)) => {
+                    let (w, h) = (canvas.width(), canvas.height());
+                    let (mut content, mut matte_pixmap) =
+                        match (Pixmap::new(w, h), Pixmap::new(w, h)) {
+                            (Some(c), Some(m)) => (c, m),
+                            _ => continue,
+                        };
+
+                    self.render_layer(layers, layer, frame, ts, alpha, &mut content, depth);
+                    self.render_layer(layers, matte, frame, ts, 1., &mut matte_pixmap, depth);
+
+                    let mask_type = match mode {
+                        3 | 4 => MaskType::Luminance,
+                        _ => MaskType::Alpha,
+                    };
+                    let mut mask = Mask::from_pixmap(matte_pixmap.as_ref(), mask_type);
+                    if mode == 2 || mode == 4 {
+                        mask.invert();
+                    }
+
+                    content.apply_mask(&mask);
+                    draw_on(canvas, &content);
+                }
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_layer(
+        &self,
+        siblings: &[Layer],
+        layer: &Layer,
+        frame: f32,
+        ts: Transform,
+        alpha: f32,
+        canvas: &mut Pixmap,
+        depth: u8,
+    ) {
+        if frame < layer.in_point || frame >= layer.out_point {
+            return;
+        }
+
+        let local = local_frame(layer, frame);
+        let ts = ts.pre_concat(layer_transform(siblings, layer, frame, 0));
+        let alpha = alpha * opacity(&layer.transform.opacity, local);
+
+        if alpha <= 0. {
+            return;
+        }
+
+        if layer.masks.is_empty() {
+            self.render_layer_content(layer, local, ts, alpha, canvas, depth);
+            return;
+        }
+
+        let (w, h) = (canvas.width(), canvas.height());
+        let (mut content, mask) = match (Pixmap::new(w, h), layer_mask(layer, local, ts, w, h)) {
+            (Some(c), Some(m)) => (c, m),
+            _ => return,
+        };
+
+        self.render_layer_content(layer, local, ts, alpha, &mut content, depth);
+        content.apply_mask(&mask);
+        draw_on(canvas, &content);
+    }
+
+    fn render_layer_content(
+        &self,
+        layer: &Layer,
+        local: f32,
+        ts: Transform,
+        alpha: f32,
+        canvas: &mut Pixmap,
+        depth: u8,
+    ) {
+        match layer.kind {
+            // precomposition
+            0 => {
+                if depth >= MAX_PRECOMP_DEPTH {
+                    return;
+                }
+
+                let layers = match layer.ref_id.as_deref().and_then(|id| self.assets.get(id)) {
+                    Some(layers) => layers,
+                    None => return,
+                };
+
+                let frame = match &layer.time_remap {
+                    Some(tm) => tm.scalar_at(local) * self.animation.frame_rate,
+                    None => local,
+                };
+
+                self.render_layers(layers, frame, ts, alpha, canvas, depth + 1);
+            }
+            // solid
+            1 => {
+                let color = layer.solid_color.as_deref().and_then(parse_hex_color);
+                let w = layer.solid_width.unwrap_or(0.);
+                let h = layer.solid_height.unwrap_or(0.);
+
+                if let (Some(mut color), Some(rect)) =
+                    (color, tiny_skia::Rect::from_xywh(0., 0., w, h))
+                {
+                    color.apply_opacity(alpha);
+                    let paint = solid_paint(color);
+                    canvas.fill_rect(rect, &paint, ts, None);
+                }
+            }
+            // shape
+            4 => {
+                self.render_shapes(&layer.shapes, local, ts, alpha, Some(canvas));
+            }
+            // null layers are only used for parenting, images and text are not supported
+            _ => {}
+        }
+    }
+
+    /// Renders a list of shapes, returning the geometry they define (relative to `ts`).
+    ///
+    /// If `canvas` is `None`, then only geometry is computed.
+    fn render_shapes(
+        &self,
+        items: &[Shape],
+        frame: f32,
+        ts: Transform,
+        alpha: f32,
+        canvas: Option<&mut Pixmap>,
+    ) -> Vec<Contour> {
+        // Styles (fills, strokes) apply to all paths that are listed before them
+        // (including ones in nested groups), so we collect geometry front-to-back...
+        let mut geometry = Vec::new();
+        let mut snapshots = Vec::new();
+
+        for item in items {
+            match item {
+                Shape::Group(g) if !g.hidden => {
+                    let (local_ts, _) = group_transform(&g.items, frame);
+                    let contours = self.render_shapes(&g.items, frame, ts, alpha, None);
+                    geometry.extend(contours.into_iter().map(|c| c.transform(local_ts)));
+                }
+                Shape::Path(p) if !p.hidden => {
+                    geometry.push(Contour::from_bezier(&p.bezier.at(frame)))
+                }
+                Shape::Rect(r) if !r.hidden => geometry.push(Contour::rect(r, frame)),
+                Shape::Ellipse(e) if !e.hidden => geometry.push(Contour::ellipse(e, frame)),
+                Shape::Star(s) if !s.hidden => geometry.push(Contour::star(s, frame)),
+                Shape::Trim(t) if !t.hidden => trim(&mut geometry, t, frame),
+                Shape::Fill(_)
+                | Shape::Stroke(_)
+                | Shape::GradientFill(_)
+                | Shape::GradientStroke(_) => snapshots.push(geometry.clone()),
+                _ => {}
+            }
+        }
+
+        let canvas = match canvas {
+            Some(c) => c,
+            None => return geometry,
+        };
+
+        // ...and then draw them back-to-front (items listed first are on top)
+        for item in items.iter().rev() {
+            match item {
+                Shape::Group(g) if !g.hidden => {
+                    let (local_ts, group_alpha) = group_transform(&g.items, frame);
+                    let ts = ts.pre_concat(local_ts);
+                    self.render_shapes(&g.items, frame, ts, alpha * group_alpha, Some(canvas));
+                }
+                Shape::Fill(f) => {
+                    let contours = snapshots.pop().unwrap_or_default();
+                    if !f.hidden {
+                        fill(canvas, &contours, f, frame, ts, alpha);
+                    }
+                }
+                Shape::Stroke(s) => {
+                    let contours = snapshots.pop().unwrap_or_default();
+                    if !s.hidden {
+                        stroke(canvas, &contours, s, frame, ts, alpha);
+                    }
+                }
+                Shape::GradientFill(g) => {
+                    let contours = snapshots.pop().unwrap_or_default();
+                    if !g.hidden {
+                        gradient_fill(canvas, &contours, g, frame, ts, alpha);
+                    }
+                }
+                Shape::GradientStroke(g) => {
+                    let contours = snapshots.pop().unwrap_or_default();
+                    if !g.hidden {
+                        gradient_stroke(canvas, &contours, g, frame, ts, alpha);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        geometry
+    }
+}
+
+/// A single contour (i.e. a continuous path) made of cubic bezier segments.
+#[derive(Clone)]
+pub(super) struct Contour {
+    start: Point,
+    /// `[control1, control2, end]`
+    cubics: Vec<[Point; 3]>,
+    closed: bool,
+}
+
+impl Contour {
+    fn from_bezier(b: &model::Bezier) -> Self {
+        let n = b
+            .vertices
+            .len()
+            .min(b.tangents_in.len())
+            .min(b.tangents_out.len());
+        let p = |[x, y]: [f32; 2]| Point::from_xy(x, y);
+
+        let mut cubics = Vec::with_capacity(n);
+        let segment = |from: usize, to: usize| {
+            let start = p(b.vertices[from]);
+            let end = p(b.vertices[to]);
+            [
+                start + p(b.tangents_out[from]),
+                end + p(b.tangents_in[to]),
+                end,
+            ]
+        };
+
+        for i in 1..n {
+            cubics.push(segment(i - 1, i));
+        }
+
+        if b.closed && n > 1 {
+            cubics.push(segment(n - 1, 0));
+        }
+
+        Self {
+            start: b.vertices.first().copied().map(p).unwrap_or_default(),
+            cubics,
+            closed: b.closed,
+        }
+    }
+
+    fn rect(r: &model::Rect, frame: f32) -> Self {
+        let [cx, cy] = r.position.point_at(frame);
+        let [w, h] = r.size.point_at(frame);
+        let radius = r
+            .roundness
+            .as_ref()
+            .map_or(0., |r| r.scalar_at(frame))
+            .min(w / 2.)
+            .min(h / 2.);
+
+        let (l, t, r, b) = (cx - w / 2., cy - h / 2., cx + w / 2., cy + h / 2.);
+        let p = Point::from_xy;
+        let line = |a: Point, b: Point| [a, b, b];
+        let k = radius * (1. - KAPPA);
+
+        let mut cubics = Vec::new();
+        if radius <= 0. {
+            // clockwise from the top right corner, like after effects does
+            cubics.push(line(p(r, t), p(r, b)));
+            cubics.push(line(p(r, b), p(l, b)));
+            cubics.push(line(p(l, b), p(l, t)));
+            cubics.push(line(p(l, t), p(r, t)));
+
+            return Self {
+                start: p(r, t),
+                cubics,
+                closed: true,
+            };
+        }
+
+        let start = p(r, t + radius);
+        cubics.push(line(start, p(r, b - radius)));
+        cubics.push([p(r, b - k), p(r - k, b), p(r - radius, b)]);
+        cubics.push(line(p(r - radius, b), p(l + radius, b)));
+        cubics.push([p(l + k, b), p(l, b - k), p(l, b - radius)]);
+        cubics.push(line(p(l, b - radius), p(l, t + radius)));
+        cubics.push([p(l, t + k), p(l + k, t), p(l + radius, t)]);
+        cubics.push(line(p(l + radius, t), p(r - radius, t)));
+        cubics.push([p(r - k, t), p(r, t + k), start]);
+
+        Self {
+            start,
+            cubics,
+            closed: true,
+        }
+    }
+
+    fn ellipse(e: &model::Ellipse, frame: f32) -> Self {
+        let [cx, cy] = e.position.point_at(frame);
+        let [w, h] = e.size.point_at(frame);
+        let (rx, ry) = (w / 2., h / 2.);
+        let (kx, ky) = (rx * KAPPA, ry * KAPPA);
+        let p = Point::from_xy;
+
+        // clockwise from the top
+        let top = p(cx, cy - ry);
+        let cubics = vec![
+            [p(cx + kx, cy - ry), p(cx + rx, cy - ky), p(cx + rx, cy)],
+            [p(cx + rx, cy + ky), p(cx + kx, cy + ry), p(cx, cy + ry)],
+            [p(cx - kx, cy + ry), p(cx - rx, cy + ky), p(cx - rx, cy)],
+            [p(cx - rx, cy - ky), p(cx - kx, cy - ry), top],
+        ];
+
+        Self {
+            start: top,
+            cubics,
+            closed: true,
+        }
+    }
+
+    fn star(s: &model::Star, frame: f32) -> Self {
+        let [cx, cy] = s.position.point_at(frame);
+        let points = s.points.scalar_at(frame).round().max(3.) as usize;
+        let rotation = s.rotation.as_ref().map_or(0., |r| r.scalar_at(frame));
+        let outer = s.outer_radius.scalar_at(frame);
+        let inner = s
+            .inner_radius
+            .as_ref()
+            .map_or(outer, |r| r.scalar_at(frame));
+
+        let vertices = match s.star_kind {
+            // polygon
+            2 => points,
+            // star
+            _ => points * 2,
+        };
+
+        let vertex = |i: usize| {
+            let radius = if s.star_kind != 2 && i % 2 == 1 {
+                inner
+            } else {
+                outer
+            };
+            let angle =
+                (rotation - 90.).to_radians() + std::f32::consts::TAU * i as f32 / vertices as f32;
+            Point::from_xy(cx + radius * angle.cos(), cy + radius * angle.sin())
+        };
+
+        let cubics = (1..=vertices)
+            .map(|i| {
+                let v = vertex(i % vertices);
+                [vertex(i - 1), v, v]
+            })
+            .collect();
+
+        Self {
+            start: vertex(0),
+            cubics,
+            closed: true,
+        }
+    }
+
+    fn transform(mut self, ts: Transform) -> Self {
+        ts.map_point(&mut self.start);
+        for cubic in &mut self.cubics {
+            ts.map_points(cubic);
+        }
+        self
+    }
+
+    fn length(&self) -> f32 {
+        let mut prev = self.start;
+        self.cubics
+            .iter()
+            .map(|c| {
+                let len = cubic_length([prev, c[0], c[1], c[2]]);
+                prev = c[2];
+                len
+            })
+            .sum()
+    }
+
+    /// Returns a part of this contour between `from` and `to` (both are lengths along the contour).
+    fn slice(&self, from: f32, to: f32) -> Option<Self> {
+        let mut result: Option<Contour> = None;
+        let mut offset = 0.;
+        let mut prev = self.start;
+
+        for c in &self.cubics {
+            let cubic = [prev, c[0], c[1], c[2]];
+            prev = c[2];
+
+            let len = cubic_length(cubic);
+            let (seg_from, seg_to) = (offset, offset + len);
+            offset = seg_to;
+
+            if seg_to <= from || seg_from >= to || len <= 0. {
+                continue;
+            }
+
+            let t0 = ((from - seg_from) / len).max(0.);
+            let t1 = ((to - seg_from) / len).min(1.);
+            let [s, c1, c2, e] = sub_cubic(cubic, t0, t1);
+
+            result
+                .get_or_insert_with(|| Contour {
+                    start: s,
+                    cubics: Vec::new(),
+                    closed: false,
+                })
+                .cubics
+                .push([c1, c2, e]);
+        }
+
+        result
+    }
+}
+
+/// Magic constant to approximate circle quarters with cubic beziers.
+const KAPPA: f32 = 0.552_284_8;
+
+fn to_path(contours: &[Contour]) -> Option<tiny_skia::Path> {
+    let mut pb = PathBuilder::new();
+
+    for c in contours {
+        pb.move_to(c.start.x, c.start.y);
+        for [c1, c2, e] in &c.cubics {
+            pb.cubic_to(c1.x, c1.y, c2.x, c2.y, e.x, e.y);
+        }
+        if c.closed {
+            pb.close();
+        }
+    }
+
+    pb.finish()
+}
+
+fn trim(geometry: &mut Vec<Contour>, t: &model::Trim, frame: f32) {
+    let start = t.start.scalar_at(frame) / 100.;
+    let end = t.end.scalar_at(frame) / 100.;
+    let offset = t.offset.as_ref().map_or(0., |o| o.scalar_at(frame)) / 360.;
+
+    let (start, end) = if start <= end {
+        (start, end)
+    } else {
+        (end, start)
+    };
+    if end - start >= 1. {
+        return;
+    }
+
+    let start = (start + offset).rem_euclid(1.);
+    let end = start + (end - start);
+
+    // Ranges as fractions of the total length
+    let ranges = if end <= 1. {
+        vec![(start, end)]
+    } else {
+        vec![(start, 1.), (0., end - 1.)]
+    };
+
+    let slice = |contour: &Contour, offset: f32, total: f32| -> Vec<Contour> {
+        ranges
+            .iter()
+            .filter_map(|&(from, to)| contour.slice(from * total - offset, to * total - offset))
+            .collect()
+    };
+
+    match t.mode {
+        // trim individually, as if all the contours were one
+        2 => {
+            let total = geometry.iter().map(Contour::length).sum();
+            let mut offset = 0.;
+            let trimmed = geometry
+                .iter()
+                .flat_map(|c| {
+                    let res = slice(c, offset, total);
+                    offset += c.length();
+                    res
+                })
+                .collect();
+
+            *geometry = trimmed;
+        }
+        // trim simultaneously
+        _ => {
+            let trimmed = geometry
+                .iter()
+                .flat_map(|c| slice(c, 0., c.length()))
+                .collect();
+            *geometry = trimmed;
+        }
+    }
+}
+
+fn cubic_length(c: [Point; 4]) -> f32 {
+    const N: usize = 16;
+
+    let mut prev = c[0];
+    (1..=N)
+        .map(|i| {
+            let next = cubic_at(c, i as f32 / N as f32);
+            let d = prev.distance(next);
+            prev = next;
+            d
+        })
+        .sum()
+}
+
+fn cubic_at(c: [Point; 4], t: f32) -> Point {
+    let mt = 1. - t;
+    let [a, b, cc, d] = [mt * mt * mt, 3. * mt * mt * t, 3. * mt * t * t, t * t * t];
+
+    Point::from_xy(
+        a * c[0].x + b * c[1].x + cc * c[2].x + d * c[3].x,
+        a * c[0].y + b * c[1].y + cc * c[2].y + d * c[3].y,
+    )
+}
+
+/// Returns the part of the cubic between `t0` and `t1`.
+fn sub_cubic(c: [Point; 4], t0: f32, t1: f32) -> [Point; 4] {
+    fn split_left(c: [Point; 4], t: f32) -> [Point; 4] {
+        let l = |a: Point, b: Point| Point::from_xy(a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t);
+
+        let ab = l(c[0], c[1]);
+        let bc = l(c[1], c[2]);
+        let cd = l(c[2], c[3]);
+        let abc = l(ab, bc);
+        let bcd = l(bc, cd);
+        [c[0], ab, abc, l(abc, bcd)]
+    }
+
+    fn split_right(c: [Point; 4], t: f32) -> [Point; 4] {
+        let [d, c2, c1, a] = split_left([c[3], c[2], c[1], c[0]], 1. - t);
+        [a, c1, c2, d]
+    }
+
+    let left = split_left(c, t1);
+    if t1 <= 0. {
+        return left;
+    }
+
+    split_right(left, t0 / t1)
+}
+
+fn local_frame(layer: &Layer, frame: f32) -> f32 {
+    let stretch = if layer.stretch == 0. {
+        1.
+    } else {
+        layer.stretch
+    };
+    (frame - layer.start_time) / stretch
+}
+
+/// Returns the transform of the layer, taking parents into account.
+fn layer_transform(siblings: &[Layer], layer: &Layer, frame: f32, depth: u8) -> Transform {
+    let own = transform(&layer.transform, local_frame(layer, frame));
+
+    // `depth` protects us from parenting loops in malformed files
+    let parent = layer
+        .parent
+        .filter(|_| depth < 32)
+        .and_then(|p| siblings.iter().find(|l| l.index == Some(p)));
+
+    match parent {
+        Some(parent) => layer_transform(siblings, parent, frame, depth + 1).pre_concat(own),
+        None => own,
+    }
+}
+
+fn transform(t: &model::Transform, frame: f32) -> Transform {
+    let [ax, ay] = t.anchor.as_ref().map_or([0.; 2], |a| a.point_at(frame));
+    let [px, py] = match &t.position {
+        Some(model::Position::Combined(p)) => p.position_at(frame),
+        Some(model::Position::Split { x, y }) => [x.scalar_at(frame), y.scalar_at(frame)],
+        None => [0.; 2],
+    };
+    let [sx, sy] = t.scale.as_ref().map_or([100.; 2], |s| s.point_at(frame));
+    let rotation = t.rotation.as_ref().map_or(0., |r| r.scalar_at(frame));
+    let skew = t.skew.as_ref().map_or(0., |s| s.scalar_at(frame));
+    let skew_axis = t.skew_axis.as_ref().map_or(0., |s| s.scalar_at(frame));
+
+    let mut ts = Transform::from_translate(px, py).pre_rotate(rotation);
+
+    if skew != 0. {
+        ts = ts
+            .pre_rotate(skew_axis)
+            .pre_concat(Transform::from_skew((-skew).to_radians().tan(), 0.))
+            .pre_rotate(-skew_axis);
+    }
+
+    ts.pre_scale(sx / 100., sy / 100.).pre_translate(-ax, -ay)
+}
+
+/// Returns the transform and opacity of a group, based on its `tr` item.
+fn group_transform(items: &[Shape], frame: f32) -> (Transform, f32) {
+    items
+        .iter()
+        .find_map(|s| match s {
+            Shape::Transform(t) => Some((transform(t, frame), opacity(&t.opacity, frame))),
+            _ => None,
+        })
+        .unwrap_or((Transform::identity(), 1.))
+}
+
+fn opacity(o: &Option<Animated<Values>>, frame: f32) -> f32 {
+    o.as_ref()
+        .map_or(1., |o| (o.scalar_at(frame) / 100.).clamp(0., 1.))
+}
+
+fn layer_mask(layer: &Layer, frame: f32, ts: Transform, w: u32, h: u32) -> Option<Mask> {
+    let mut mask = Mask::new(w, h)?;
+
+    for (idx, m) in layer.masks.iter().enumerate() {
+        let mode = m.mode.as_str();
+        if mode == "n" {
+            continue;
+        }
+
+        let mut shape = Mask::new(w, h)?;
+        if let Some(path) = to_path(&[Contour::from_bezier(&m.pt.at(frame))]) {
+            shape.fill_path(&path, FillRule::Winding, true, ts);
+        }
+        if m.inv {
+            shape.invert();
+        }
+
+        let op = opacity(&m.opacity, frame);
+
+        // If the first mask subtracts, it subtracts from the whole layer
+        if idx == 0 && mode == "s" {
+            mask.data_mut().fill(255);
+        }
+
+        for (dst, &src) in mask.data_mut().iter_mut().zip(shape.data()) {
+            let src = src as f32 * op;
+            let d = *dst as f32;
+
+            *dst = match mode {
+                "s" => d * (1. - src / 255.),
+                "i" => d * src / 255.,
+                _ => d.max(src),
+            } as u8;
+        }
+    }
+
+    Some(mask)
+}
+
+fn fill(
+    canvas: &mut Pixmap,
+    contours: &[Contour],
+    f: &model::Fill,
+    frame: f32,
+    ts: Transform,
+    alpha: f32,
+) {
+    let path = match to_path(contours) {
+        Some(p) => p,
+        None => return,
+    };
+
+    let mut color = color(&f.color.at(frame));
+    color.apply_opacity(alpha * opacity(&f.opacity, frame));
+
+    canvas.fill_path(&path, &solid_paint(color), fill_rule(f.fill_rule), ts, None);
+}
+
+fn stroke(
+    canvas: &mut Pixmap,
+    contours: &[Contour],
+    s: &model::Stroke,
+    frame: f32,
+    ts: Transform,
+    alpha: f32,
+) {
+    let path = match to_path(contours) {
+        Some(p) => p,
+        None => return,
+    };
+
+    let mut color = color(&s.color.at(frame));
+    color.apply_opacity(alpha * opacity(&s.opacity, frame));
+
+    canvas.stroke_path(&path, &solid_paint(color), &line(&s.line, frame), ts, None);
+}
+
+fn gradient_fill(
+    canvas: &mut Pixmap,
+    contours: &[Contour],
+    g: &model::GradientFill,
+    frame: f32,
+    ts: Transform,
+    alpha: f32,
+) {
+    let (path, shader) = match (to_path(contours), gradient(&g.gradient, frame, alpha)) {
+        (Some(p), Some(s)) => (p, s),
+        _ => return,
+    };
+
+    let paint = Paint {
+        shader,
+        anti_alias: true,
+        ..Paint::default()
+    };
+
+    canvas.fill_path(&path, &paint, fill_rule(g.fill_rule), ts, None);
+}
+
+fn gradient_stroke(
+    canvas: &mut Pixmap,
+    contours: &[Contour],
+    g: &model::GradientStroke,
+    frame: f32,
+    ts: Transform,
+    alpha: f32,
+) {
+    let (path, shader) = match (to_path(contours), gradient(&g.gradient, frame, alpha)) {
+        (Some(p), Some(s)) => (p, s),
+        _ => return,
+    };
+
+    let paint = Paint {
+        shader,
+        anti_alias: true,
+        ..Paint::default()
+    };
+
+    canvas.stroke_path(&path, &paint, &line(&g.line, frame), ts, None);
+}
+
+fn gradient(g: &model::Gradient, frame: f32, alpha: f32) -> Option<Shader<'static>> {
+    let [sx, sy] = g.start.point_at(frame);
+    let [ex, ey] = g.end.point_at(frame);
+    let alpha = alpha * opacity(&g.opacity, frame);
+
+    let values = g.colors.values.at(frame).0;
+    let n = g.colors.count;
+    let (colors, opacities) = values.split_at((n * 4).min(values.len()));
+
+    // Opacity stops may have different offsets than color stops, so we sample them at color stops offsets
+    let opacity_at = |offset: f32| -> f32 {
+        let stops: Vec<_> = opacities.chunks_exact(2).map(|c| (c[0], c[1])).collect();
+        match stops.iter().position(|&(o, _)| o >= offset) {
+            None => stops.last().map_or(1., |&(_, a)| a),
+            Some(0) => stops[0].1,
+            Some(i) => {
+                let (o0, a0) = stops[i - 1];
+                let (o1, a1) = stops[i];
+                let t = if o1 > o0 {
+                    (offset - o0) / (o1 - o0)
+                } else {
+                    0.
+                };
+                a0 + (a1 - a0) * t
+            }
+        }
+    };
+
+    let stops = colors
+        .chunks_exact(4)
+        .map(|c| {
+            let a = opacity_at(c[0]) * alpha;
+            let color = Color::from_rgba(
+                c[1].clamp(0., 1.),
+                c[2].clamp(0., 1.),
+                c[3].clamp(0., 1.),
+                a.clamp(0., 1.),
+            )
+            .unwrap_or(Color::TRANSPARENT);
+            GradientStop::new(c[0], color)
+        })
+        .collect();
+
+    let (start, end) = (Point::from_xy(sx, sy), Point::from_xy(ex, ey));
+    match g.kind {
+        2 => RadialGradient::new(
+            start,
+            start,
+            start.distance(end),
+            stops,
+            SpreadMode::Pad,
+            Transform::identity(),
+        ),
+        _ => LinearGradient::new(start, end, stops, SpreadMode::Pad, Transform::identity()),
+    }
+}
+
+fn line(l: &model::Line, frame: f32) -> Stroke {
+    let mut dashes = Vec::new();
+    let mut offset = 0.;
+    for d in &l.dashes {
+        match d.kind.as_str() {
+            "o" => offset = d.value.scalar_at(frame),
+            _ => dashes.push(d.value.scalar_at(frame)),
+        }
+    }
+
+    // tiny-skia wants an even number of dashes
+    if dashes.len() % 2 == 1 {
+        dashes.extend_from_within(..);
+    }
+
+    Stroke {
+        width: l.width.scalar_at(frame),
+        miter_limit: l.miter_limit.unwrap_or(4.),
+        line_cap: match l.cap {
+            2 => LineCap::Round,
+            3 => LineCap::Square,
+            _ => LineCap::Butt,
+        },
+        line_join: match l.join {
+            2 => LineJoin::Round,
+            3 => LineJoin::Bevel,
+            _ => LineJoin::Miter,
+        },
+        dash: StrokeDash::new(dashes, offset),
+    }
+}
+
+fn fill_rule(r: u8) -> FillRule {
+    match r {
+        2 => FillRule::EvenOdd,
+        _ => FillRule::Winding,
+    }
+}
+
+fn color(v: &Values) -> Color {
+    let c = |i: usize| v.0.get(i).copied().unwrap_or(1.);
+
+    // Some (old) lottie files use `0..=255` range for colors
+    let scale = if v.0.iter().take(3).any(|&x| x > 1.) {
+        255.
+    } else {
+        1.
+    };
+
+    Color::from_rgba(
+        (c(0) / scale).clamp(0., 1.),
+        (c(1) / scale).clamp(0., 1.),
+        (c(2) / scale).clamp(0., 1.),
+        c(3).clamp(0., 1.),
+    )
+    .unwrap_or(Color::BLACK)
+}
+
+fn parse_hex_color(s: &str) -> Option<Color> {
+    let s = s.strip_prefix('#')?;
+    let c = |i: usize| u8::from_str_radix(s.get(i..i + 2)?, 16).ok();
+
+    Some(Color::from_rgba8(c(0)?, c(2)?, c(4)?, 255))
+}
+
+fn solid_paint(color: Color) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color(color);
+    paint.anti_alias = true;
+    paint
+}
+
+fn draw_on(canvas: &mut Pixmap, layer: &Pixmap) {
+    canvas.draw_pixmap(
+        0,
+        0,
+        layer.as_ref(),
+        &PixmapPaint::default(),
+        Transform::identity(),
+        None,
+    );
+}
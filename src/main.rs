@@ -1,31 +1,51 @@
 // Status:
 // - Basic functionality (downloading sticker packs) works!
 // - Zips do have thumbnails
-// - Converting to .png is implemented, but
-//   - Is not rate limited (it's quite easy to DDOS the bot)
+// - Converting to .png is implemented (and rate limited, see `limiter`)
 // - For some reason the bot is slow (need to check why)
 // - Messages/interface are very much work in progress
 // - The code is quite bad in some places/wip
 
+mod archive;
+mod artifacts;
+mod cache;
+mod cancel;
+mod command;
+mod config;
+mod convert;
 mod download;
 mod error;
+mod history;
+mod i18n;
+mod limiter;
+mod listener;
+mod lottie;
+mod pack;
 mod preview;
 mod progress;
 mod query_command;
+mod queue;
+mod resize;
+mod settings;
 mod sticker_set_info;
 mod stuff;
+mod video;
+mod webp_anim;
 
-use std::future::ready;
+use std::{future::ready, mem, sync::Arc};
 
 use futures::{stream, StreamExt, TryStreamExt};
-use lodepng::RGBA;
 use teloxide::{
     adaptors::{DefaultParseMode, Throttle},
-    dispatching::{update_listeners::Polling, MessageFilterExt, UpdateHandler},
+    dispatching::{MessageFilterExt, UpdateHandler},
     dptree::{self, deps},
     payloads::SendDocumentSetters,
     prelude::{AutoSend, Dispatcher, RequesterExt},
-    types::{CallbackQuery, ChatAction::UploadDocument, InputFile, ParseMode, StickerSet, Update},
+    types::{
+        CallbackQuery, ChatAction::UploadDocument, ChatId, InlineQuery, InlineQueryResult,
+        InlineQueryResultCachedSticker, InputFile, InputMedia, InputMediaDocument,
+        InputMessageContent, InputMessageContentText, ParseMode, StickerSet, Update,
+    },
     utils::command::parse_command,
     RequestError,
 };
@@ -33,15 +53,31 @@ use teloxide::{
     dispatching::UpdateFilterExt,
     payloads::setters::*,
     prelude::Requester,
-    types::{InlineKeyboardButton, InlineKeyboardMarkup, Me, Message, Sticker},
+    types::{
+        InlineKeyboardButton, InlineKeyboardMarkup, Me, Message, MessageEntityKind, Sticker,
+        StickerKind,
+    },
 };
 
 use crate::{
-    download::{Downloader, Task, Tasks},
-    error::{callback_query::CallbackQueryError, Error, ResultExt},
+    archive::Archive,
+    artifacts::Artifacts,
+    cache::Cache,
+    command::Command,
+    config::Config,
+    download::{Cancel, Downloader, Task, Tasks},
+    error::{archiving::ArchiveError, callback_query::CallbackQueryError, Error, ResultExt},
+    history::{History, Job},
+    i18n::Language,
+    limiter::Limiter,
+    listener::Listener,
     progress::{KiB, Progress},
-    query_command::{ActionDownload, DownloadFormat, DownloadTarget, QueryAction, QueryCommand},
-    stuff::{archive, sticker_name},
+    query_command::{
+        ActionDownload, ActionHistory, ActionSettings, ArchiveFormat, DownloadFormat,
+        DownloadTarget, QueryAction, QueryCommand, Resize, ResizeMode,
+    },
+    queue::Queue,
+    settings::{Settings, ThumbnailStyle, UserSettings, NAMING_PRESETS},
 };
 
 type Bot = AutoSend<DefaultParseMode<Throttle<teloxide::Bot>>>;
@@ -49,16 +85,32 @@ type Bot = AutoSend<DefaultParseMode<Throttle<teloxide::Bot>>>;
 fn main() {
     pretty_env_logger::init();
 
-    let test = true;
+    let mut config = match Config::load() {
+        Ok(config) => config,
+        Err(err) => {
+            log::error!("Invalid configuration: {err}");
+            std::process::exit(1);
+        }
+    };
+    let listener = Listener::new(config.webhook.take());
 
-    // Using single-thread runtime is not really needed, I could use multi-thread runtime here.
-    // However, since I don't expect this bot to be used much, I can save some VPS resources (?probably).
+    // By default single-thread runtime is used, since I don't expect this bot to be used much,
+    // so I can save some VPS resources (?probably). Heavy work (conversions) is done in `spawn_blocking` anyway.
     //
     // I don't use `#[tokio::main]` to reduce macros & magic used and speedup compilation a little bit
-    let rt = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .unwrap();
+    let rt = match config.runtime.multi_thread {
+        true => {
+            let mut builder = tokio::runtime::Builder::new_multi_thread();
+            if config.runtime.worker_threads != 0 {
+                builder.worker_threads(config.runtime.worker_threads);
+            }
+            builder
+        }
+        false => tokio::runtime::Builder::new_current_thread(),
+    }
+    .enable_all()
+    .build()
+    .unwrap();
 
     let _rt_guard = rt.enter();
 
@@ -70,29 +122,49 @@ fn main() {
         // Allow using `.await` without `.send()` or requests
         .auto_send();
 
+    // Register commands, so that they are shown in the menu of telegram clients
+    rt.block_on(bot.set_my_commands(Command::bot_commands()))
+        .fine();
+
+    let cache = Cache::open(&config.cache)
+        .map_err(|err| log::error!("Couldn't open the cache, continuing without it: {err}"))
+        .ok();
+    let artifacts = Artifacts::open(&config.cache.dir)
+        .map_err(|err| {
+            log::error!("Couldn't open the artifact cache, continuing without it: {err}")
+        })
+        .ok();
+
+    // Silently losing everyone's settings is worse than not starting,
+    // `settings.path = ":memory:"` can be used to explicitly not save anything
+    let settings = Settings::open(&config.settings.path).unwrap_or_else(|err| {
+        log::error!("Couldn't open the settings database: {err}");
+        std::process::exit(1);
+    });
+
+    let history = History::open(&config.settings.path).unwrap_or_else(|err| {
+        log::error!("Couldn't open the history database: {err}");
+        std::process::exit(1);
+    });
+
     let mut dp = Dispatcher::builder(bot.clone(), dispatch_tree())
         .distribution_function(|_| None::<()>)
-        .dependencies(deps![Downloader::new(bot.clone())])
+        .dependencies(deps![
+            Downloader::new(bot.clone(), cache, &config.download),
+            Limiter::new(&config.limits),
+            Queue::new(&config.queue),
+            settings,
+            history,
+            artifacts,
+            Arc::new(config)
+        ])
         .enable_ctrlc_handler()
         .build();
 
-    if test {
-        let listener = Polling::builder(bot)
-            .timeout(std::time::Duration::from_secs(1))
-            .build();
-
-        rt.block_on(async {
-            dp.dispatch_with_listener(
-                listener,
-                teloxide::error_handlers::LoggingErrorHandler::new(),
-            )
-            .await
-        });
-
-        return;
+    if let Err(err) = rt.block_on(listener.dispatch(bot, &mut dp)) {
+        log::error!("Couldn't set up the webhook: {err}");
+        std::process::exit(1);
     }
-
-    rt.block_on(async { dp.dispatch().await });
 }
 
 fn dispatch_tree() -> UpdateHandler<RequestError> {
@@ -103,78 +175,524 @@ fn dispatch_tree() -> UpdateHandler<RequestError> {
                 .branch(Message::filter_text().endpoint(text)),
         )
         .branch(Update::filter_callback_query().endpoint(callback_query))
+        .branch(Update::filter_inline_query().endpoint(inline_query))
 }
 
-async fn sticker(bot: Bot, message: Message) -> Result<(), RequestError> {
-    let download_png = InlineKeyboardButton::callback(
-        "sticker as .png",
-        QueryCommand::download(DownloadTarget::Single, DownloadFormat::Png).encode(),
-    );
-    let download_webp = InlineKeyboardButton::callback(
-        "sticker as .webp",
-        QueryCommand::download(DownloadTarget::Single, DownloadFormat::Webp).encode(),
-    );
-    let download_png_set = InlineKeyboardButton::callback(
-        "set as .png",
-        QueryCommand::download(DownloadTarget::All, DownloadFormat::Png).encode(),
-    );
-    let download_webp_set = InlineKeyboardButton::callback(
-        "set as .webp",
-        QueryCommand::download(DownloadTarget::All, DownloadFormat::Webp).encode(),
-    );
+async fn sticker(bot: Bot, message: Message, settings: Settings) -> Result<(), RequestError> {
+    // `filter_sticker` guarantees that the message is a sticker
+    let sticker = message.sticker().unwrap();
 
-    let kb = InlineKeyboardMarkup::new([
-        [download_png_set, download_webp_set],
-        [download_png, download_webp],
-    ]);
+    // Stickers that are not in a set can only be downloaded by themselves
+    let targets: &[_] = match sticker.set_name {
+        Some(_) => &[DownloadTarget::All, DownloadTarget::Single],
+        None => &[DownloadTarget::Single],
+    };
 
-    bot.send_message(message.chat.id, "What do you want to download?")
-        .reply_markup(kb)
+    let settings = user_settings(&settings, &message);
+    bot.send_message(message.chat.id, settings.language.what_to_download())
+        .reply_markup(download_keyboard(&sticker.kind, targets, &settings, None))
         .reply_to_message_id(message.id)
         .await?;
 
     Ok(())
 }
 
-async fn text(bot: Bot, text: String, message: Message, me: Me) -> Result<(), RequestError> {
+/// Returns a keyboard with buttons to download stickers of kind `kind` as every format that is supported for them.
+///
+/// The preferred format of the user goes first.
+fn download_keyboard(
+    kind: &StickerKind,
+    targets: &[DownloadTarget],
+    settings: &UserSettings,
+    resize: Option<Resize>,
+) -> InlineKeyboardMarkup {
+    let formats: Vec<_> = settings
+        .format
+        .into_iter()
+        .chain(
+            DownloadFormat::ALL
+                .into_iter()
+                .filter(|&f| Some(f) != settings.format),
+        )
+        .filter(|&f| convert::is_supported(kind, f, resize))
+        .collect();
+
+    let language = settings.language;
+    let rows = formats.chunks(2).flat_map(|formats| {
+        targets.iter().map(move |&target| {
+            formats
+                .iter()
+                // Packs are made of whole sets
+                .filter(|format| target == DownloadTarget::All || !format.is_pack())
+                .map(|&format| {
+                    InlineKeyboardButton::callback(
+                        language.download_button(target, format),
+                        QueryCommand::download(target, format)
+                            .with_resize(resize)
+                            .encode(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        })
+    });
+
+    // Rows of single stickers are empty when both formats are packs, telegram doesn't like empty rows
+    InlineKeyboardMarkup::new(rows.filter(|row| !row.is_empty()))
+}
+
+/// Returns settings of the author of `message` (defaults if it's sent on behalf of a channel).
+fn user_settings(settings: &Settings, message: &Message) -> UserSettings {
+    message
+        .from()
+        .map(|user| settings.get(user.id))
+        .unwrap_or_default()
+}
+
+/// Returns the text and the keyboard of the `/settings` menu.
+fn settings_menu(settings: &UserSettings) -> (String, InlineKeyboardMarkup) {
+    // Every button switches its setting to the next value
+    fn next<T: PartialEq + Copy>(all: &[T], current: T) -> T {
+        let idx = all.iter().position(|&x| x == current).map_or(0, |i| i + 1);
+        all[idx % all.len()]
+    }
+
+    let formats: Vec<_> = [None]
+        .into_iter()
+        .chain(DownloadFormat::ALL.map(Some))
+        .collect();
+    // A custom template is switched to the first preset
+    let naming = NAMING_PRESETS
+        .iter()
+        .position(|&p| p == settings.naming)
+        .map_or(0, |i| (i + 1) % NAMING_PRESETS.len());
+
+    let actions = [
+        ActionSettings::Format(next(&formats, settings.format)),
+        ActionSettings::Archive(next(&ArchiveFormat::ALL, settings.archive)),
+        ActionSettings::Naming(naming as u8),
+        ActionSettings::Language(next(&Language::ALL, settings.language)),
+        ActionSettings::Thumbnail(next(&ThumbnailStyle::ALL, settings.thumbnail)),
+    ];
+
+    let language = settings.language;
+    let buttons = language.settings_buttons(settings);
+    let rows = buttons.into_iter().zip(actions).map(|(text, action)| {
+        [InlineKeyboardButton::callback(
+            text,
+            QueryCommand::settings(action).encode(),
+        )]
+    });
+
+    (
+        language.settings_text(settings),
+        InlineKeyboardMarkup::new(rows),
+    )
+}
+
+async fn text(
+    bot: Bot,
+    text: String,
+    message: Message,
+    me: Me,
+    settings: Settings,
+    history: History,
+) -> Result<(), RequestError> {
+    use teloxide::utils::html::escape;
+
     let chat_id = message.chat.id;
+    let language = user_settings(&settings, &message).language;
+
+    if let Some((name, args)) = parse_command(&text, me.username()) {
+        let Some(command) = Command::parse(name) else {
+            bot.send_message(chat_id, language.unknown_command(&escape(name)))
+                .await?;
+
+            return Ok(());
+        };
 
-    // We could use teloxide derive macros for commands, but for just /start and /help that's a bit of an overkill.
-    if let Some((command, _args)) = parse_command(&text, me.username()) {
         match command {
-            "start" => {
-                bot.send_message(chat_id, format!("start (TODO)")).await?;
+            // Deep link, e.g. from inline mode, see `inline_query`
+            Command::Start if args.len() == 1 && args[0].starts_with("set_") => {
+                let args = [&args[0]["set_".len()..]];
+                download_command(&bot, &message, &args, &settings).await?
+            }
+            Command::Start => {
+                let text = format!(
+                    "{}\n\n{}",
+                    language.greeting(),
+                    Command::help_text(me.username(), language)
+                );
+                bot.send_message(chat_id, text).await?;
             }
-            "help" => {
-                bot.send_message(chat_id, format!("help (TODO)")).await?;
+            Command::Help => {
+                bot.send_message(chat_id, Command::help_text(me.username(), language))
+                    .await?;
             }
-            _ => {
+            Command::Download => download_command(&bot, &message, &args, &settings).await?,
+            Command::Archive => archive_command(&bot, &message, &args, &settings).await?,
+            Command::Resize => resize_command(&bot, &message, &args, &settings).await?,
+            Command::Settings => settings_command(&bot, &message, &args, &settings).await?,
+            Command::History => history_command(&bot, &message, &settings, &history).await?,
+        }
+
+        return Ok(());
+    }
+
+    bot.send_message(chat_id, language.use_help()).await?;
+
+    Ok(())
+}
+
+/// `/download <set_name | https://t.me/addstickers/set_name>`
+async fn download_command(
+    bot: &Bot,
+    message: &Message,
+    args: &[&str],
+    settings: &Settings,
+) -> Result<(), RequestError> {
+    use teloxide::{utils::html::*, ApiError};
+
+    let name = match args {
+        [arg] => stuff::sticker_set_name(arg),
+        _ => None,
+    };
+
+    let Some(name) = name else {
+        bot.send_message(
+            message.chat.id,
+            "Usage: <code>/download &lt;set name or https://t.me/addstickers/... link&gt;</code>",
+        )
+        .reply_to_message_id(message.id)
+        .await?;
+
+        return Ok(());
+    };
+
+    let set = match bot.get_sticker_set(name).await {
+        Ok(set) => set,
+        Err(RequestError::Api(ApiError::InvalidStickersSet)) => {
+            bot.send_message(
+                message.chat.id,
+                format!("Sticker set {} doesn't exist", code_inline(&escape(name))),
+            )
+            .reply_to_message_id(message.id)
+            .await?;
+
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    // The set is identified by the link in the message, see `linked_sticker_set`
+    let link = link(&set_link(&set.name), &escape(&set.title));
+    let settings = user_settings(settings, message);
+    let info = settings.language.set_info(&link, set.stickers.len());
+    let what = settings.language.what_to_download();
+    bot.send_message(message.chat.id, format!("{info}\n\n{what}"))
+        .reply_markup(download_keyboard(
+            &set.kind,
+            &[DownloadTarget::All],
+            &settings,
+            None,
+        ))
+        .reply_to_message_id(message.id)
+        .disable_web_page_preview(true)
+        .await?;
+
+    Ok(())
+}
+
+/// `/archive [zip | tar | tar.gz | tar.zst | 7z]`
+async fn archive_command(
+    bot: &Bot,
+    message: &Message,
+    args: &[&str],
+    settings: &Settings,
+) -> Result<(), RequestError> {
+    use teloxide::utils::html::{code_inline, escape};
+
+    // Commands can be sent on behalf of channels, there is no user to remember the setting for
+    let Some(user) = message.from() else {
+        return Ok(());
+    };
+
+    let formats = ArchiveFormat::ALL
+        .iter()
+        .map(|f| code_inline(f.ext()))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let text = match args {
+        [] => {
+            let current = settings.get(user.id).archive;
+            format!(
+                "Archives are sent as {}.\n\nUse <code>/archive &lt;format&gt;</code> to change that, \
+                available formats: {formats}",
+                code_inline(current.ext())
+            )
+        }
+        [arg] => match ArchiveFormat::from_ext(arg) {
+            Some(format) => match settings.update(user.id, |s| s.archive = format) {
+                Ok(_) => format!(
+                    "Archives will be sent as {} from now on",
+                    code_inline(format.ext())
+                ),
+                Err(err) => {
+                    log::error!("Couldn't save settings of {}: {err}", user.id);
+                    "Couldn't save the settings, try again later".to_owned()
+                }
+            },
+            None => format!(
+                "Unknown archive format {}, available formats: {formats}",
+                code_inline(&escape(arg))
+            ),
+        },
+        _ => format!("Usage: <code>/archive &lt;format&gt;</code>, available formats: {formats}"),
+    };
+
+    bot.send_message(message.chat.id, text)
+        .reply_to_message_id(message.id)
+        .await?;
+
+    Ok(())
+}
+
+/// `/resize <size> [fit | fill | pad]`, in reply to a sticker
+async fn resize_command(
+    bot: &Bot,
+    message: &Message,
+    args: &[&str],
+    settings: &Settings,
+) -> Result<(), RequestError> {
+    let resize = match args {
+        [size] => Resize::parse(size, None),
+        [size, mode] => Resize::parse(size, Some(mode)),
+        _ => None,
+    };
+    let reply = message.reply_to_message().filter(|r| r.sticker().is_some());
+
+    let (Some(resize), Some(reply)) = (resize, reply) else {
+        let sizes = Resize::PRESETS.map(|s| s.to_string()).join(", ");
+        let modes = ResizeMode::ALL.map(|m| m.name()).join(" | ");
+        let text = format!(
+            "Usage: reply to a sticker with <code>/resize &lt;size&gt; [{modes}]</code>\n\
+            \n\
+            Size can be {sizes} or anything else up to {}. \
+            <code>fit</code> (the default) keeps the aspect ratio, \
+            <code>fill</code> crops the sticker to a square, \
+            <code>pad</code> adds transparent borders to make it a square.",
+            Resize::MAX_SIZE
+        );
+        bot.send_message(message.chat.id, text)
+            .reply_to_message_id(message.id)
+            .await?;
+
+        return Ok(());
+    };
+
+    // `filter` above guarantees that the reply is a sticker
+    let sticker = reply.sticker().unwrap();
+    let targets: &[_] = match sticker.set_name {
+        Some(_) => &[DownloadTarget::All, DownloadTarget::Single],
+        None => &[DownloadTarget::Single],
+    };
+
+    // The keyboard is a reply to the sticker itself (not to the command), since that's where the download
+    // takes the sticker from
+    let settings = user_settings(settings, message);
+    let language = settings.language;
+    let text = format!(
+        "{}\n\n{}",
+        language.what_to_download(),
+        language.resized_to(resize)
+    );
+    bot.send_message(message.chat.id, text)
+        .reply_markup(download_keyboard(
+            &sticker.kind,
+            targets,
+            &settings,
+            Some(resize),
+        ))
+        .reply_to_message_id(reply.id)
+        .await?;
+
+    Ok(())
+}
+
+/// `/settings` or `/settings naming <template>`
+async fn settings_command(
+    bot: &Bot,
+    message: &Message,
+    args: &[&str],
+    settings: &Settings,
+) -> Result<(), RequestError> {
+    use teloxide::utils::html::{code_inline, escape};
+
+    // Commands can be sent on behalf of channels, there is no user to remember the settings for
+    let Some(user) = message.from() else {
+        return Ok(());
+    };
+
+    let update = match args {
+        [] => Ok(settings.get(user.id)),
+        ["naming", template @ ..] if !template.is_empty() => {
+            let template = template.join(" ");
+            // Telegram doesn't allow long file names anyway
+            if template.chars().count() > 64 {
                 bot.send_message(
-                    chat_id,
-                    format!(
-                        "Unknown command `{command}`, see /help for the list of available commands"
-                    ),
+                    message.chat.id,
+                    "The template is too long (at most 64 characters)",
                 )
+                .reply_to_message_id(message.id)
                 .await?;
+
+                return Ok(());
             }
+
+            settings.update(user.id, |s| s.naming = template)
+        }
+        _ => {
+            let text = format!(
+                "Usage: {} or {}",
+                code_inline("/settings"),
+                code_inline(&escape("/settings naming <template>"))
+            );
+            bot.send_message(message.chat.id, text)
+                .reply_to_message_id(message.id)
+                .await?;
+
+            return Ok(());
+        }
+    };
+
+    let text = match update {
+        Ok(user_settings) => {
+            let (text, keyboard) = settings_menu(&user_settings);
+            bot.send_message(message.chat.id, text)
+                .reply_markup(keyboard)
+                .reply_to_message_id(message.id)
+                .await?;
+
+            return Ok(());
+        }
+        Err(err) => {
+            log::error!("Couldn't save settings of {}: {err}", user.id);
+            "Couldn't save the settings, try again later"
         }
+    };
+
+    bot.send_message(message.chat.id, text)
+        .reply_to_message_id(message.id)
+        .await?;
+
+    Ok(())
+}
 
+/// `/history`
+async fn history_command(
+    bot: &Bot,
+    message: &Message,
+    settings: &Settings,
+    history: &History,
+) -> Result<(), RequestError> {
+    // Commands can be sent on behalf of channels, there is no user to show the history of
+    let Some(user) = message.from() else {
         return Ok(());
-    }
+    };
 
-    bot.send_message(
-        chat_id,
-        format!(
-            "Use /help for the list of available commands and instructions on how to use the bot"
-        ),
-    )
-    .await?;
+    let language = settings.get(user.id).language;
+    let (text, keyboard) = match history.page(user.id, 0) {
+        Ok((jobs, pages)) => history_page(&jobs, 0, pages, language),
+        Err(err) => {
+            log::error!("Couldn't load history of {}: {err}", user.id);
+            let text = "Couldn't load the history, try again later".to_owned();
+            (text, InlineKeyboardMarkup::default())
+        }
+    };
+
+    bot.send_message(message.chat.id, text)
+        .reply_markup(keyboard)
+        .reply_to_message_id(message.id)
+        .await?;
 
     Ok(())
 }
 
-async fn callback_query(bot: Bot, query: CallbackQuery, d: Downloader) -> Result<(), RequestError> {
-    match callback_query_inner(&bot, &query, d).await {
+/// Returns the text and the keyboard of the `page`-th page of `/history` with `jobs`.
+fn history_page(
+    jobs: &[Job],
+    page: u32,
+    pages: u32,
+    language: Language,
+) -> (String, InlineKeyboardMarkup) {
+    use teloxide::utils::html::{bold, escape};
+
+    if jobs.is_empty() {
+        return (
+            language.history_empty().to_owned(),
+            InlineKeyboardMarkup::default(),
+        );
+    }
+
+    let mut text = language.history_title(page + 1, pages);
+    let mut buttons = Vec::new();
+    for (n, job) in (1..).zip(jobs) {
+        let set = job.set_name.as_deref().unwrap_or("—");
+        let what = language.download_button(job.action.target, job.action.format);
+        let archive = job
+            .action
+            .archive
+            .map(|a| format!(" (.{})", a.ext()))
+            .unwrap_or_default();
+        let size = job
+            .action
+            .resize
+            .map(|r| format!(", {}px {}", r.size, r.mode.name()))
+            .unwrap_or_default();
+        let date = stuff::format_utc(job.created);
+
+        text += &format!(
+            "\n{n}. {}: {what}{archive}{size}, {date}",
+            bold(&escape(set))
+        );
+        buttons.push(InlineKeyboardButton::callback(
+            n.to_string(),
+            QueryCommand::history(ActionHistory::Resend(job.id)).encode(),
+        ));
+    }
+
+    let [newer, older] = language.history_pages();
+    let mut navigation = Vec::new();
+    if page > 0 {
+        navigation.push(InlineKeyboardButton::callback(
+            newer,
+            QueryCommand::history(ActionHistory::Page(page - 1)).encode(),
+        ));
+    }
+    if page + 1 < pages {
+        navigation.push(InlineKeyboardButton::callback(
+            older,
+            QueryCommand::history(ActionHistory::Page(page + 1)).encode(),
+        ));
+    }
+
+    (text, InlineKeyboardMarkup::new([buttons, navigation]))
+}
+
+// FIXME: see `callback_query_download`
+#[allow(clippy::too_many_arguments)]
+async fn callback_query(
+    bot: Bot,
+    query: CallbackQuery,
+    d: Downloader,
+    l: Limiter,
+    q: Queue,
+    a: Option<Artifacts>,
+    s: Settings,
+    h: History,
+    config: Arc<Config>,
+) -> Result<(), RequestError> {
+    match callback_query_inner(&bot, &query, d, l, q, a, s, h, &config).await {
         Ok(()) => Ok(()),
         Err(Error::Req(e)) => Err(e),
         Err(Error::Show(e)) if !e.is_post() => {
@@ -195,10 +713,18 @@ async fn callback_query(bot: Bot, query: CallbackQuery, d: Downloader) -> Result
     }
 }
 
+// FIXME: see `callback_query_download`
+#[allow(clippy::too_many_arguments)]
 async fn callback_query_inner(
     bot: &Bot,
     query: &CallbackQuery,
     d: Downloader,
+    l: Limiter,
+    q: Queue,
+    a: Option<Artifacts>,
+    s: Settings,
+    h: History,
+    config: &Config,
 ) -> Result<(), Error<CallbackQueryError>> {
     use error::callback_query as err;
 
@@ -214,89 +740,457 @@ async fn callback_query_inner(
         None => return err::invalid_button_data(data),
     };
 
-    let QueryAction::Download(action) = command.action;
-    callback_query_download(bot, action, query, d).await?;
+    match command.action {
+        QueryAction::Download(action) => {
+            callback_query_download(bot, action, query, d, l, q, a, s, h, config).await?
+        }
+        QueryAction::Cancel => callback_query_cancel(bot, query, d).await?,
+        QueryAction::Settings(action) => callback_query_settings(bot, query, action, &s).await?,
+        QueryAction::History(action) => callback_query_history(bot, query, action, &s, &h).await?,
+    }
+
+    Ok(())
+}
+
+async fn callback_query_cancel(
+    bot: &Bot,
+    query: &CallbackQuery,
+    d: Downloader,
+) -> Result<(), Error<CallbackQueryError>> {
+    use error::callback_query as err;
+
+    let message = query.message.as_ref().ok_or_else(err::no_message)?;
+    match d.cancel(message.id, query.from.id) {
+        Cancel::Cancelled => {}
+        Cancel::NoSuchJob => return Err(err::nothing_to_cancel()),
+        // In groups everyone sees the button, but it's not their download to cancel
+        Cancel::NotOwner => return Err(err::not_your_download()),
+    }
+
+    bot.answer_callback_query(&query.id)
+        .text("Cancelling...")
+        .await?;
 
     Ok(())
 }
 
+async fn callback_query_settings(
+    bot: &Bot,
+    query: &CallbackQuery,
+    action: ActionSettings,
+    settings: &Settings,
+) -> Result<(), Error<CallbackQueryError>> {
+    use error::callback_query as err;
+
+    let message = query.message.as_ref().ok_or_else(err::no_message)?;
+
+    // Settings are changed for whoever pressed the button, so in groups everyone can use the same menu
+    let user_settings = settings.update(query.from.id, |s| match action {
+        ActionSettings::Format(format) => s.format = format,
+        ActionSettings::Archive(archive) => s.archive = archive,
+        ActionSettings::Naming(preset) => {
+            let preset = NAMING_PRESETS
+                .get(preset as usize)
+                .unwrap_or(&NAMING_PRESETS[0]);
+            s.naming = (*preset).to_owned()
+        }
+        ActionSettings::Language(language) => s.language = language,
+        ActionSettings::Thumbnail(thumbnail) => s.thumbnail = thumbnail,
+    })?;
+
+    let (text, keyboard) = settings_menu(&user_settings);
+    bot.edit_message_text(message.chat.id, message.id, text)
+        .reply_markup(keyboard)
+        .await?;
+    bot.answer_callback_query(&query.id).await?;
+
+    Ok(())
+}
+
+async fn callback_query_history(
+    bot: &Bot,
+    query: &CallbackQuery,
+    action: ActionHistory,
+    settings: &Settings,
+    history: &History,
+) -> Result<(), Error<CallbackQueryError>> {
+    use error::{callback_query as err, downloading::SendDocumentError};
+
+    let message = query.message.as_ref().ok_or_else(err::no_message)?;
+
+    // Only the history of whoever pressed the button is shown/sent, even if the message was sent to someone else
+    match action {
+        ActionHistory::Page(page) => {
+            let language = settings.get(query.from.id).language;
+            let (jobs, pages) = history.page(query.from.id, page)?;
+            // Same as in `History::page`, button data can be anything
+            let page = page.min(pages.saturating_sub(1));
+            let (text, keyboard) = history_page(&jobs, page, pages, language);
+
+            bot.edit_message_text(message.chat.id, message.id, text)
+                .reply_markup(keyboard)
+                .await?;
+            bot.answer_callback_query(&query.id).await?;
+        }
+        ActionHistory::Resend(id) => {
+            let job = history
+                .get(query.from.id, id)?
+                .ok_or_else(err::no_such_job)?;
+
+            // The query is answered only after sending, so that errors are shown as alerts
+            // (and not instead of the menu, as post errors are)
+            let mut file_ids = job.file_ids;
+            let sent = match file_ids.len() {
+                1 => bot
+                    .send_document(message.chat.id, InputFile::file_id(file_ids.remove(0)))
+                    .caption(job.caption)
+                    .reply_to_message_id(message.id)
+                    .await
+                    .map(drop),
+                _ => {
+                    let files = file_ids.into_iter().map(InputFile::file_id).collect();
+                    send_volumes(bot, message.chat.id, message.id, files, None, job.caption)
+                        .await
+                        .map(drop)
+                }
+            };
+            sent.map_err(|e| err::resend_failed(SendDocumentError(e)))?;
+
+            bot.answer_callback_query(&query.id).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// FIXME: group the services (downloader, limiter, etc) in a struct, this takes way too many arguments
+#[allow(clippy::too_many_arguments)]
 async fn callback_query_download(
     bot: &Bot,
     action: ActionDownload,
     query: &CallbackQuery,
     d: Downloader,
+    l: Limiter,
+    q: Queue,
+    a: Option<Artifacts>,
+    s: Settings,
+    h: History,
+    config: &Config,
 ) -> Result<(), Error<CallbackQueryError>> {
     use error::callback_query as err;
     use error::downloading::SendDocumentError;
 
     let message = query.message.as_ref().ok_or_else(err::no_message)?;
-    let reply = message.reply_to_message().ok_or_else(err::empty_reply)?;
-    let sticker = reply
-        .sticker()
-        .ok_or_else(err::reply_is_not_sticker)
-        .and_then(check_supported_sticker)?;
+    l.check(query.from.id, message.chat.id)?;
+
+    // Keyboards sent in response to `/download` are not replies to a sticker, instead they link the set
+    let (sticker, set) = match linked_sticker_set(message) {
+        Some(name) => {
+            let set = bot.get_sticker_set(name).await?;
+            let sticker = set.stickers.first().cloned().ok_or_else(err::empty_set)?;
+            check_supported_sticker(&sticker, action)?;
+
+            (sticker, Some(set))
+        }
+        None => {
+            let reply = message.reply_to_message().ok_or_else(err::empty_reply)?;
+            let sticker = reply
+                .sticker()
+                .ok_or_else(err::reply_is_not_sticker)
+                .and_then(|s| check_supported_sticker(s, action))?
+                .clone();
+
+            let set = match &sticker.set_name {
+                Some(name) => Some(bot.get_sticker_set(name).await?),
+                None => None,
+            };
+
+            (sticker, set)
+        }
+    };
+
+    if action.format.is_pack() {
+        let Some(set) = set
+            .as_ref()
+            .filter(|_| action.target == DownloadTarget::All)
+        else {
+            return Err(err::unsupported_format(&sticker.kind, action.format));
+        };
+
+        let min = pack::min_stickers(action.format);
+        if set.stickers.len() < min {
+            return Err(err::pack_too_small(action.format, min));
+        }
+    }
+
+    let reply_message_id = message.reply_to_message().map_or(message.id, |r| r.id);
+
+    // Buttons usually don't specify the archive format, so that the setting of the user who pressed it is used
+    let settings = s.get(query.from.id);
+    // Packs are always `.zip`s, since that's what importers expect
+    let archive_format = match action.format.is_pack() {
+        true => ArchiveFormat::Zip,
+        false => action.archive.unwrap_or(settings.archive),
+    };
+    let action = ActionDownload {
+        archive: Some(archive_format),
+        ..action
+    };
+
+    // Finished jobs are remembered, so they can be re-sent with `/history`
+    let record = |file_ids: &[String]| {
+        let caption = format_caption(set.as_ref(), settings.language);
+        let set_name = sticker.set_name.as_deref();
+        h.record(query.from.id, set_name, action, file_ids, &caption)
+            .fine()
+    };
+
+    // The job is in flight until the end of the function, i.e. until the stickers are sent.
+    // This is before the artifact cache, so that pressing the button twice doesn't re-send the document twice
+    // (the rate limit is checked at the very beginning, so re-sends are limited too).
+    let in_flight = d.start(message.id, action.target, query.from.id)?;
+    let token = in_flight.token().clone();
+
+    // If the same thing was already sent, just send it again, without downloading/converting/uploading anything
+    let artifact_key = Artifacts::key(&sticker, set.as_ref(), action, &settings, config);
+    if let Some(file_id) = a.as_ref().and_then(|a| a.get(&artifact_key)) {
+        let resend = bot
+            .send_document(message.chat.id, InputFile::file_id(file_id))
+            .caption(format_caption(set.as_ref(), settings.language))
+            .reply_to_message_id(reply_message_id)
+            .await;
+
+        match resend {
+            Ok(sent) => {
+                let file_ids: Vec<_> = sent
+                    .document()
+                    .map(|d| d.file_id.clone())
+                    .into_iter()
+                    .collect();
+                record(&file_ids);
+
+                bot.answer_callback_query(&query.id).await?;
+                bot.delete_message(message.chat.id, message.id).await.fine();
+                return Ok(());
+            }
+            Err(err) => {
+                // The file could be deleted from telegram servers, fallback to the usual path
+                log::warn!("Couldn't re-send a cached document: {err}");
+                if let Some(a) = &a {
+                    a.remove(&artifact_key).await.fine();
+                }
+            }
+        }
+    }
 
     let mut progress = Progress::new(
-        &bot,
+        bot,
         "Queueing download request...",
         message.chat.id,
         message.id,
     );
 
     let sticker_set_name = sticker.set_name.clone();
-    let (tasks, set) =
-        prepare_download_tasks(bot, message.id, sticker, action, &mut progress).await?;
+    let tasks = prepare_download_tasks(
+        bot,
+        &d,
+        &sticker,
+        set.as_ref(),
+        action,
+        &settings.naming,
+        config.download.info_concurrency,
+        &mut progress,
+    )
+    .await?;
     let total_size = tasks.total_size();
+    let sticker_count = tasks.stickers.len();
 
-    let stream = d.download(tasks, action.target)?;
+    // The ticket is held until the end of the function, like `in_flight`
+    let stream = d.download(tasks);
+    let mut ticket = q.enqueue(query.from.id)?;
 
     bot.answer_callback_query(&query.id).await?;
 
+    let cancel = InlineKeyboardButton::callback(
+        settings.language.cancel_button(),
+        QueryCommand::cancel().encode(),
+    );
+    progress.keyboard(Some(InlineKeyboardMarkup::new([[cancel]])));
+
+    // The download stream is lazy, so nothing is downloaded until it's our turn
+    if token
+        .or_cancelled(ticket.wait(&mut progress))
+        .await
+        .is_none()
+    {
+        return cancelled(&mut progress);
+    }
+
     let bot = bot.clone();
     let chat_id = message.chat.id;
     let message_id = message.id;
-    let reply_message_id = reply.id;
+
+    let kind = sticker.kind.clone();
+    let format = action.format;
+    let resize = action.resize;
+    let needs_conversion = convert::is_needed(&kind, format, resize);
+
+    // A single sticker in a "good" format is sent as is, everything else is sent in an archive
+    let mut output = match sticker_count == 1 && format.is_fine_for_sending_alone() {
+        true => Output::Single(None),
+        false => {
+            let archive = Archive::new(archive_format, &config.archive).map_err(ArchiveError)?;
+            Output::Archive(Box::new(archive))
+        }
+    };
+
+    // Stickers are kept until they are converted or added to the archive in `spawn_blocking`,
+    // since both take a while (originals are small anyway, it's the converted ones that are big)
+    let mut originals = Vec::new();
+    // The thumbnail is generated from the original sticker, before it's converted
+    let mut first = None;
 
     let mut scope = progress
         .scope("Downloading stickers", total_size as _)
         .with_unit(KiB);
 
-    let mut stickers: Vec<_> = stream
-        .map(|(file_name, res)| {
-            res.map(|bytes| {
-                scope.inc_by(bytes.len() as _);
-                (file_name, bytes)
-            })
-        })
-        .try_collect()
-        .await?;
+    let download = async {
+        let mut stream = Box::pin(stream);
+        while let Some((file_name, res)) = stream.next().await {
+            let bytes = res?;
+            scope.inc_by(bytes.len() as _);
 
-    let thumbnail = preview::generate_thumbnail(&stickers[0].1);
+            if first.is_none() {
+                first = Some(bytes.clone());
+            }
 
-    match action.format {
-        DownloadFormat::Png => {
-            let a = tokio::task::spawn_blocking(|| {
-                let mut scope = progress.scope("Converting stickers to .png", stickers.len() as _);
+            originals.push((file_name, bytes));
+        }
 
-                for (_file_name, bytes) in &mut stickers {
-                    let (w, h, raw) = libwebp::WebPDecodeRGBA(bytes).unwrap();
+        Ok::<_, Error<CallbackQueryError>>(())
+    };
 
-                    *bytes =
-                        lodepng::encode32(bytemuck::cast_slice::<u8, RGBA>(&*raw), w as _, h as _)
-                            .unwrap();
+    match token.or_cancelled(download).await {
+        Some(res) => res?,
+        None => return cancelled(&mut progress),
+    }
 
-                    scope.inc();
+    // Stickers that are sent as is don't take a conversion slot (the thumbnail is cheap enough)
+    let permit = match needs_conversion {
+        true => {
+            progress.title("Waiting for other conversions to finish...");
+            match token.or_cancelled(l.conversion()).await {
+                Some(permit) => Some(permit),
+                None => return cancelled(&mut progress),
+            }
+        }
+        false => None,
+    };
+
+    let conversion_token = token.clone();
+    let thumbnail_config = config.thumbnail.clone();
+    let thumbnail_background = settings.thumbnail.background();
+    let convert_config = config.convert.clone();
+    let archive_name = sticker_set_name.as_deref().unwrap_or("stickers").to_owned();
+    let archive_set = set.clone();
+    let (mut progress, files, thumbnail) = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let token = conversion_token;
+
+        let cover = match &first {
+            Some(first) => pack::cover(format, &kind, first)?,
+            None => None,
+        };
+
+        let thumbnail = thumbnail_background
+            .zip(first)
+            .and_then(|(background, first)| {
+                let (w, h, raw) = convert::first_frame(&kind, &first).ok()?;
+                Some(preview::generate_thumbnail(
+                    w,
+                    h,
+                    &raw,
+                    background,
+                    &thumbnail_config,
+                ))
+            });
+
+        // Directory of the pack that is being added, see `pack::dir`
+        let mut current_pack = None;
+        let mut add = |name: String, bytes: Vec<u8>| {
+            if let (Some(dir), Output::Archive(archive)) = (pack::dir(format, &name), &mut output) {
+                if current_pack.as_deref() != Some(dir) {
+                    archive
+                        .start_group(pack::max_size(format))
+                        .map_err(ArchiveError)?;
+                    current_pack = Some(dir.to_owned());
                 }
+            }
 
-                (progress, stickers)
-            })
-            .await
-            .unwrap();
+            output.add(name, bytes)
+        };
+
+        if needs_conversion {
+            let title = format!(
+                "Converting stickers to {}",
+                Language::English.format_name(format)
+            );
+            let mut scope = progress.scope(&title, originals.len() as _);
+
+            let (mut names, stickers): (Vec<_>, Vec<_>) = originals.into_iter().unzip();
+            convert::convert_all(
+                &kind,
+                format,
+                resize,
+                stickers,
+                &convert_config,
+                || token.is_cancelled(),
+                |idx, bytes| {
+                    add(mem::take(&mut names[idx]), bytes)?;
+                    scope.inc();
+
+                    Ok::<_, Error<CallbackQueryError>>(())
+                },
+            )?;
+        } else {
+            // `.tar.gz`, `.tar.zst` and `.7z` compress files as they are added
+            for (name, bytes) in originals {
+                add(name, bytes)?;
+            }
+        }
 
-            progress = a.0;
-            stickers = a.1;
-        } // FIXME: not fine
-        DownloadFormat::Webp => {}
+        let files = match output {
+            Output::Single(sticker) => {
+                let (name, bytes) = sticker.expect("there is exactly one sticker");
+                vec![(InputFile::memory(bytes).file_name(name), None)]
+            }
+            Output::Archive(archive) => {
+                let info = |entries: &[_]| {
+                    let Some(set) = archive_set.as_ref() else {
+                        return Vec::new();
+                    };
+                    let info = sticker_set_info::StickerSetInfo::new(set, entries);
+
+                    // Packs are whole in every volume, thanks to `start_group` above
+                    if format.is_pack() {
+                        return pack::metadata(format, &info, cover.as_deref());
+                    }
+
+                    let info = serde_json::to_vec_pretty(&info).unwrap(); // FIXME: unwrap bad
+                    vec![("sticker_info.json".to_owned(), info)]
+                };
+
+                archive.finish(&archive_name, info).map_err(ArchiveError)?
+            }
+        };
+
+        Ok::<_, Error<CallbackQueryError>>((progress, files, thumbnail))
+    })
+    .await
+    .unwrap()?;
+
+    if token.is_cancelled() {
+        return cancelled(&mut progress);
     }
 
     // FIXME: fix the message when downloading a single sticker
@@ -304,53 +1198,175 @@ async fn callback_query_download(
 
     bot.send_chat_action(chat_id, UploadDocument).await.fine();
 
-    let file = if stickers.len() == 1 && action.format.is_fine_for_sending_alone() {
-        let (name, bytes) = stickers.pop().unwrap();
-        InputFile::memory(bytes).file_name(name)
-    } else {
-        if let Some(set) = &set {
-            stickers.push((
-                "sticker_info.json".to_owned(),
-                serde_json::to_vec_pretty(&sticker_set_info::StickerSetInfo::new(&set, &stickers))
-                    .unwrap(), // FIXME: unwrap bad
-            ));
-        }
+    // N.B. `_tmp` must be alive until the archive is uploaded
+    let (mut files, _tmp): (Vec<_>, Vec<_>) = files.into_iter().unzip();
 
-        let zip = archive(sticker_set_name.as_deref().unwrap_or("stickers"), stickers);
+    if files.len() > 1 {
+        progress.title_imp(&format!("Uploading sticker set ({} parts)", files.len()));
 
-        let file = match zip {
-            Ok(z) => z,
-            _ => return Ok(()), // FIXME
+        let send = send_volumes(
+            &bot,
+            chat_id,
+            reply_message_id,
+            files,
+            thumbnail,
+            format_caption(set.as_ref(), settings.language),
+        );
+
+        let file_ids = match token.or_cancelled(send).await {
+            Some(res) => res.map_err(SendDocumentError)?,
+            None => return cancelled(&mut progress),
         };
+        record(&file_ids);
+
+        // Volumes are not put in the artifact cache, it only supports single documents
+        bot.delete_message(chat_id, message_id).await.fine();
+        return Ok(());
+    }
+
+    let mut send = bot
+        .send_document(chat_id, files.pop().unwrap())
+        .caption(format_caption(set.as_ref(), settings.language))
+        .reply_to_message_id(reply_message_id);
 
-        file
+    if let Some(thumbnail) = thumbnail {
+        send = send.thumb(thumbnail);
+    }
+
+    let sent = match token.or_cancelled(send).await {
+        Some(res) => res.map_err(SendDocumentError)?,
+        None => return cancelled(&mut progress),
     };
 
-    bot.send_document(chat_id, file)
-        .caption(format_caption(set.as_ref()))
-        .thumb(thumbnail)
-        .reply_to_message_id(reply_message_id)
-        .await
-        .map_err(SendDocumentError)?;
+    if let Some(document) = sent.document() {
+        record(std::slice::from_ref(&document.file_id));
+
+        if let Some(a) = &a {
+            a.put(artifact_key, document.file_id.clone()).await.fine();
+        }
+    }
 
     bot.delete_message(chat_id, message_id).await.fine();
 
     Ok(())
 }
 
+/// Sends volumes of an archive as media groups.
+///
+/// A media group must contain from 2 to 10 documents, so a lone last volume (e.g. the 11th one) is sent by itself.
+///
+/// Returns `file_id`s of the sent documents.
+async fn send_volumes(
+    bot: &Bot,
+    chat_id: ChatId,
+    reply_message_id: i32,
+    files: Vec<InputFile>,
+    thumbnail: Option<InputFile>,
+    caption: String,
+) -> Result<Vec<String>, RequestError> {
+    let mut volumes = files.into_iter().peekable();
+    let mut sent = Vec::new();
+
+    while volumes.peek().is_some() {
+        let mut group: Vec<_> = volumes
+            .by_ref()
+            .take(10)
+            .map(|file| {
+                let mut document = InputMediaDocument::new(file);
+                document.thumb = thumbnail.clone();
+                document
+            })
+            .collect();
+
+        // The caption of the last document is shown under the whole group
+        if volumes.peek().is_none() {
+            let last = group.last_mut().unwrap();
+            last.caption = Some(caption.clone());
+            // `DefaultParseMode` doesn't apply to media
+            last.parse_mode = Some(ParseMode::Html);
+        }
+
+        if let [document] = &mut group[..] {
+            let mut send = bot
+                .send_document(chat_id, document.media.clone())
+                .caption(caption.clone())
+                .reply_to_message_id(reply_message_id);
+
+            if let Some(thumbnail) = document.thumb.take() {
+                send = send.thumb(thumbnail);
+            }
+
+            sent.push(send.await?);
+            continue;
+        }
+
+        let messages = bot
+            .send_media_group(chat_id, group.into_iter().map(InputMedia::Document))
+            .reply_to_message_id(reply_message_id)
+            .await?;
+        sent.extend(messages);
+    }
+
+    let file_ids = sent
+        .iter()
+        .filter_map(|m| m.document())
+        .map(|d| d.file_id.clone())
+        .collect();
+
+    Ok(file_ids)
+}
+
+/// Where downloaded (and converted) stickers go.
+enum Output {
+    /// A single sticker that is sent as is.
+    Single(Option<(String, Vec<u8>)>),
+    // Boxed, since the archive is quite big
+    Archive(Box<Archive>),
+}
+
+impl Output {
+    fn add(&mut self, name: String, bytes: Vec<u8>) -> Result<(), ArchiveError> {
+        match self {
+            Output::Single(sticker) => *sticker = Some((name, bytes)),
+            Output::Archive(archive) => archive.add(&name, &bytes).map_err(ArchiveError)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Shows that the job was cancelled (removing the cancel button).
+fn cancelled(progress: &mut Progress) -> Result<(), Error<CallbackQueryError>> {
+    progress.keyboard(None);
+    progress.title_imp("Cancelled");
+
+    Ok(())
+}
+
+// FIXME: see `callback_query_download`
+#[allow(clippy::too_many_arguments)]
 async fn prepare_download_tasks(
     bot: &Bot,
-    message_id: i32,
+    d: &Downloader,
     sticker: &Sticker,
-    ActionDownload { target, format }: ActionDownload,
+    set: Option<&StickerSet>,
+    ActionDownload { target, format, .. }: ActionDownload,
+    naming: &str,
+    info_concurrency: usize,
     progress: &mut Progress,
-) -> Result<(Tasks, Option<StickerSet>), Error<CallbackQueryError>> {
-    let set = match &sticker.set_name {
-        Some(name) => Some(bot.get_sticker_set(name).await?),
-        None => None,
+) -> Result<Tasks, Error<CallbackQueryError>> {
+    let set_name = set.map(|set| set.name.as_str());
+    let name = |idx: Option<u8>, s: &Sticker| {
+        // Packs have a fixed layout, so the naming setting is ignored for them
+        if let (true, Some(set), Some(idx)) = (format.is_pack(), set, idx) {
+            return pack::file_name(format, set, idx as usize);
+        }
+
+        let emojis = s.emoji.as_deref().unwrap_or_default();
+        stuff::file_name(naming, idx, emojis, set_name, &s.file_unique_id)
     };
 
-    let named_and_identified = match (target, set.as_ref()) {
+    let mut named_and_identified: Vec<_> = match (target, set) {
         (DownloadTarget::Single, set) | (DownloadTarget::All, set @ None) => {
             let idx = set.and_then(|set| {
                 set.stickers
@@ -360,36 +1376,43 @@ async fn prepare_download_tasks(
                     .map(|(i, _)| i as u8)
             });
 
-            vec![(
-                sticker_name(idx, sticker.emoji.as_deref().unwrap_or_default()),
-                sticker.file_id.clone(),
-            )]
+            vec![(name(idx, sticker), sticker.clone())]
         }
         (DownloadTarget::All, Some(set)) => set
             .stickers
             .iter()
             .enumerate()
-            .map(|(idx, s)| {
-                (
-                    sticker_name(Some(idx as u8), s.emoji.as_deref().unwrap_or_default()),
-                    s.file_id.clone(),
-                )
-            })
+            .map(|(idx, s)| (name(Some(idx as u8), s), s.clone()))
             .collect(),
     };
 
+    stuff::dedup_names(named_and_identified.iter_mut().map(|(name, _)| name));
+
     let mut scope = progress.scope("Fetching sticker info", named_and_identified.len() as _);
 
     let mut stickers = Vec::new();
     stream::iter(named_and_identified)
-        .map(|(name, file_id)| async {
-            bot.get_file(file_id).await.map(|f| Task {
+        .map(|(name, s)| async move {
+            // Cached files are read from the cache, so there is no need to ask telegram where they are
+            if d.is_cached(&s.file_unique_id) {
+                return Ok(Task {
+                    unique_id: s.file_unique_id,
+                    file_id: s.file_id,
+                    size: s.file_size as usize,
+                    path: None,
+                    name,
+                });
+            }
+
+            bot.get_file(&s.file_id).await.map(|f| Task {
+                unique_id: f.file_unique_id.clone(),
+                file_id: s.file_id,
                 size: f.file_size as usize,
-                path: f.file_path,
+                path: Some(f.file_path),
                 name,
             })
         })
-        .buffered(16 /* FIXME: choose constant */)
+        .buffered(info_concurrency)
         //.try_collect()
         .try_for_each(|task| {
             stickers.push(task);
@@ -400,35 +1423,115 @@ async fn prepare_download_tasks(
         .await?;
 
     let tasks = Tasks {
-        message_id,
         format,
+        kind: sticker.kind.clone(),
         stickers,
     };
 
-    Ok((tasks, set))
+    Ok(tasks)
+}
+
+/// Inline mode: `@bot <set_name | https://t.me/addstickers/set_name>` shares the set
+/// with a button to download it in private chat with the bot.
+///
+/// The result is the first sticker of the set, which is sent as a message with the title and the number of stickers.
+/// Articles would be nicer, but they only accept thumbnails by url, and urls of telegram files contain the bot token.
+async fn inline_query(
+    bot: Bot,
+    query: InlineQuery,
+    me: Me,
+    settings: Settings,
+) -> Result<(), RequestError> {
+    use teloxide::{utils::html::*, ApiError};
+
+    let Some(name) = stuff::sticker_set_name(&query.query) else {
+        bot.answer_inline_query(query.id, []).await?;
+        return Ok(());
+    };
+
+    let set = match bot.get_sticker_set(name).await {
+        Ok(set) => set,
+        Err(RequestError::Api(ApiError::InvalidStickersSet)) => {
+            bot.answer_inline_query(query.id, []).await?;
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let url = set_link(&set.name);
+    // The message is sent on behalf of the user, so it's in their language
+    let language = settings.get(query.from.id).language;
+    let text = language.set_info(&link(&url, &escape(&set.title)), set.stickers.len());
+    let content = InputMessageContentText::new(text)
+        .parse_mode(ParseMode::Html)
+        .disable_web_page_preview(true);
+
+    let [open, download] = language.set_buttons();
+    let mut buttons = vec![InlineKeyboardButton::url(
+        open,
+        url.parse().expect("set links are valid urls"),
+    )];
+
+    // Deep link payloads are limited to 64 characters, some set names are too long for that :(
+    let payload = format!("set_{}", set.name);
+    if payload.len() <= 64 {
+        let username = me.username();
+        let deep_link = format!("https://t.me/{username}?start={payload}");
+        buttons.push(InlineKeyboardButton::url(
+            download,
+            deep_link.parse().expect("deep links are valid urls"),
+        ));
+    }
+
+    let Some(first) = set.stickers.first() else {
+        bot.answer_inline_query(query.id, []).await?;
+        return Ok(());
+    };
+
+    let result = InlineQueryResultCachedSticker::new(set.name.clone(), first.file_id.clone())
+        .input_message_content(InputMessageContent::Text(content))
+        .reply_markup(InlineKeyboardMarkup::new([buttons]));
+
+    bot.answer_inline_query(query.id, [InlineQueryResult::CachedSticker(result)])
+        .await?;
+
+    Ok(())
+}
+
+/// Returns the name of the sticker set linked in the message (see `download_command`).
+fn linked_sticker_set(message: &Message) -> Option<&str> {
+    message
+        .entities()?
+        .iter()
+        .find_map(|entity| match &entity.kind {
+            MessageEntityKind::TextLink { url } => stuff::sticker_set_name(url.as_str()),
+            _ => None,
+        })
+}
+
+fn set_link(name: &str) -> String {
+    format!("https://t.me/addstickers/{name}")
 }
 
-fn check_supported_sticker(sticker: &Sticker) -> Result<&Sticker, Error<CallbackQueryError>> {
+fn check_supported_sticker(
+    sticker: &Sticker,
+    action: ActionDownload,
+) -> Result<&Sticker, Error<CallbackQueryError>> {
     use error::callback_query as err;
-    use teloxide::types::StickerKind::*;
-
-    match sticker.kind {
-        // FIXME: ideally we would simply either
-        //        A) support animated/video stickers
-        //        B) answer w/ error when the sticker is sent, not when the button is pressed
-        Animated => Err(err::animated_sticker_not_supported()),
-        Video => Err(err::video_sticker_not_supported()),
-        Webp => Ok(sticker),
+
+    match &sticker.kind {
+        // The keyboard only has buttons for supported formats,
+        // but buttons of old messages may still have unsupported ones
+        kind if convert::is_supported(kind, action.format, action.resize) => Ok(sticker),
+        kind => Err(err::unsupported_format(kind, action.format)),
     }
 }
 
-fn format_caption(set: Option<&StickerSet>) -> String {
+fn format_caption(set: Option<&StickerSet>, language: Language) -> String {
     set.map(|ss| {
         use teloxide::utils::html::*;
 
-        let title = bold(&escape(&ss.title));
-        let count = bold(&ss.stickers.len().to_string());
-        format!("Stickers set: {title}\nStickers in set: {count}")
+        language.set_info(&bold(&escape(&ss.title)), ss.stickers.len())
     })
     .unwrap_or_default()
 }
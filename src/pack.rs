@@ -0,0 +1,498 @@
+//! Exports of whole sticker sets as sticker packs for other messengers (and as custom emoji for Discord/Slack).
+//!
+//! A pack is a `.zip` with the stickers converted to whatever the messenger wants (see [`convert`]),
+//! laid out the way importers expect, with metadata built from [`StickerSetInfo`].
+use serde::Serialize;
+use teloxide::types::{StickerKind, StickerSet};
+
+use crate::{
+    convert,
+    error::converting::ConvertError,
+    query_command::{DownloadFormat, Resize, ResizeMode},
+    resize,
+    sticker_set_info::{StickerSetInfo, StickerSetKind},
+    stuff,
+};
+
+/// WhatsApp stickers must be exactly 512×512.
+pub const WHATSAPP_RESIZE: Resize = Resize {
+    size: 512,
+    mode: ResizeMode::Pad,
+};
+
+/// Maximum size of a static WhatsApp sticker.
+pub const WHATSAPP_STATIC_LIMIT: usize = 100 * 1024;
+
+/// Maximum size of an animated WhatsApp sticker.
+pub const WHATSAPP_ANIMATED_LIMIT: usize = 500 * 1024;
+
+/// Icon of a pack in the WhatsApp sticker picker, must be a 96×96 png.
+const WHATSAPP_TRAY: Resize = Resize {
+    size: 96,
+    mode: ResizeMode::Pad,
+};
+
+/// A WhatsApp pack must have from 3 to 30 stickers, bigger sets are split into multiple packs.
+const WHATSAPP_MIN_STICKERS: usize = 3;
+const WHATSAPP_MAX_STICKERS: usize = 30;
+
+/// Signal stickers (and the cover) are 512×512 too, animated ones are downscaled to fit into [`SIGNAL_LIMIT`].
+pub const SIGNAL_RESIZE: Resize = WHATSAPP_RESIZE;
+
+/// Maximum size of a Signal sticker.
+pub const SIGNAL_LIMIT: usize = 300 * 1024;
+
+/// Telegram sets have at most 120 stickers, while Signal packs can have up to 200, so they are never split.
+const TELEGRAM_MAX_STICKERS: usize = 120;
+
+/// Custom emoji are (at most) 128×128 on both Discord and Slack.
+pub const EMOJI_SIZE: u32 = 128;
+
+/// Maximum size of a custom emoji, this is the limit of Slack (Discord's is 256 KiB).
+pub const EMOJI_LIMIT: usize = 128 * 1024;
+
+/// Discord wants names of 2 to 32 characters, this leaves some space for suffixes of [`stuff::dedup_names`].
+const EMOJI_NAME_MAX: usize = 28;
+
+/// Returns the directory of a pack in the archive from the path of a sticker,
+/// `None` for formats whose packs can be split between volumes (every volume has metadata of its stickers).
+///
+/// Packs that must be kept whole are added as groups, see [`Archive::start_group`](crate::archive::Archive::start_group).
+pub fn dir(format: DownloadFormat, path: &str) -> Option<&str> {
+    match format {
+        DownloadFormat::WhatsApp | DownloadFormat::Signal => {
+            path.rsplit_once('/').map(|(dir, _)| dir)
+        }
+        _ => None,
+    }
+}
+
+/// Returns an upper bound of the size of a single pack in the archive, including metadata.
+pub fn max_size(format: DownloadFormat) -> u64 {
+    // Generous estimates of per-sticker archive headers and metadata, and of the size of a cover
+    const OVERHEAD: usize = 2 * 1024;
+    const COVER: usize = 64 * 1024;
+
+    let (stickers, limit) = match format {
+        DownloadFormat::WhatsApp => (WHATSAPP_MAX_STICKERS, WHATSAPP_ANIMATED_LIMIT),
+        DownloadFormat::Signal => (TELEGRAM_MAX_STICKERS, SIGNAL_LIMIT),
+        _ => (TELEGRAM_MAX_STICKERS, EMOJI_LIMIT),
+    };
+
+    (stickers * (limit + OVERHEAD) + COVER.max(limit)) as u64
+}
+
+/// Returns the minimum number of stickers in a set that can be exported as `format`.
+pub fn min_stickers(format: DownloadFormat) -> usize {
+    match format {
+        DownloadFormat::WhatsApp => WHATSAPP_MIN_STICKERS,
+        _ => 1,
+    }
+}
+
+/// Returns the path (without the extension) of the `idx`-th sticker of `set` in a pack.
+pub fn file_name(format: DownloadFormat, set: &StickerSet, idx: usize) -> String {
+    match format {
+        // `<identifier>/<index>.webp`, as in the WhatsApp sample app
+        DownloadFormat::WhatsApp => {
+            let pack = whatsapp_pack(idx, set.stickers.len());
+            format!("{}/{idx:03}", whatsapp_identifier(&set.name, pack))
+        }
+        DownloadFormat::Signal => format!("{}/{idx:03}", set.name),
+        DownloadFormat::Emoji => {
+            let emojis = set.stickers[idx].emoji.as_deref().unwrap_or_default();
+            emoji_name(emojis, idx)
+        }
+        _ => format!("{idx:03}"),
+    }
+}
+
+/// Returns the cover of a pack (e.g. the tray icon of WhatsApp packs), made from the first sticker of the set.
+pub fn cover(
+    format: DownloadFormat,
+    kind: &StickerKind,
+    first: &[u8],
+) -> Result<Option<Vec<u8>>, ConvertError> {
+    match format {
+        DownloadFormat::WhatsApp => {
+            let (w, h, raw) = convert::first_frame(kind, first)?;
+            let raw = resize::resize(w, h, raw, Some(WHATSAPP_TRAY))?;
+            let size = WHATSAPP_TRAY.size;
+
+            convert::encode_png(size, size, &raw).map(Some)
+        }
+        DownloadFormat::Signal => {
+            let (w, h, raw) = convert::first_frame(kind, first)?;
+            let raw = resize::resize(w, h, raw, Some(SIGNAL_RESIZE))?;
+            let size = SIGNAL_RESIZE.size;
+
+            convert::encode_webp_within(size, size, &raw, SIGNAL_LIMIT).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Returns metadata files of a pack (or of its part, if the archive is split into volumes).
+pub fn metadata(
+    format: DownloadFormat,
+    info: &StickerSetInfo,
+    cover: Option<&[u8]>,
+) -> Vec<(String, Vec<u8>)> {
+    match format {
+        DownloadFormat::WhatsApp => whatsapp_metadata(info, cover),
+        DownloadFormat::Signal => signal_metadata(info, cover),
+        DownloadFormat::Emoji => vec![("emoji.csv".to_owned(), emoji_csv(info))],
+        _ => Vec::new(),
+    }
+}
+
+/// `contents.json` in the format of the WhatsApp sample app, which third-party importers follow.
+///
+/// See <https://github.com/WhatsApp/stickers/tree/main/Android#modifying-the-contentsjson-file>.
+#[derive(Serialize)]
+struct WhatsAppContents<'a> {
+    android_play_store_link: &'a str,
+    ios_app_store_link: &'a str,
+    sticker_packs: Vec<WhatsAppPack<'a>>,
+}
+
+#[derive(Serialize)]
+struct WhatsAppPack<'a> {
+    identifier: &'a str,
+    name: String,
+    publisher: &'a str,
+    tray_image_file: &'a str,
+    image_data_version: &'a str,
+    avoid_cache: bool,
+    publisher_email: &'a str,
+    publisher_website: String,
+    privacy_policy_website: &'a str,
+    license_agreement_website: &'a str,
+    animated_sticker_pack: bool,
+    stickers: Vec<WhatsAppSticker<'a>>,
+}
+
+#[derive(Serialize)]
+struct WhatsAppSticker<'a> {
+    image_file: &'a str,
+    emojis: Vec<&'a str>,
+}
+
+fn whatsapp_metadata(info: &StickerSetInfo, tray: Option<&[u8]>) -> Vec<(String, Vec<u8>)> {
+    const TRAY_FILE: &str = "tray.png";
+
+    let mut packs: Vec<WhatsAppPack<'_>> = Vec::new();
+    for sticker in &info.stickers {
+        let Some((identifier, image_file)) = sticker.path.split_once('/') else {
+            continue;
+        };
+
+        let sticker = WhatsAppSticker {
+            image_file,
+            emojis: sticker.emoji.as_deref().into_iter().collect(),
+        };
+
+        match packs.last_mut() {
+            Some(pack) if pack.identifier == identifier => pack.stickers.push(sticker),
+            _ => packs.push(WhatsAppPack {
+                identifier,
+                // Packs of a split set would have the same name otherwise. The number comes from the identifier
+                // (see `whatsapp_identifier`), since other packs of the set may be in other volumes.
+                name: match identifier
+                    .strip_prefix(&*info.name)
+                    .and_then(|n| n.strip_prefix('_'))
+                {
+                    Some(n) => format!("{} ({n})", info.title),
+                    None => info.title.clone(),
+                },
+                publisher: "Telegram",
+                tray_image_file: TRAY_FILE,
+                image_data_version: "1",
+                avoid_cache: false,
+                publisher_email: "",
+                publisher_website: format!("https://t.me/addstickers/{}", info.name),
+                privacy_policy_website: "",
+                license_agreement_website: "",
+                animated_sticker_pack: !matches!(info.kind, StickerSetKind::Common),
+                stickers: vec![sticker],
+            }),
+        }
+    }
+
+    let mut files: Vec<_> = tray
+        .into_iter()
+        .flat_map(|tray| {
+            packs
+                .iter()
+                .map(move |pack| (format!("{}/{TRAY_FILE}", pack.identifier), tray.to_vec()))
+        })
+        .collect();
+
+    let contents = WhatsAppContents {
+        android_play_store_link: "",
+        ios_app_store_link: "",
+        sticker_packs: packs,
+    };
+    let contents =
+        serde_json::to_vec_pretty(&contents).expect("serializing plain structs doesn't fail");
+    files.push(("contents.json".to_owned(), contents));
+
+    files
+}
+
+/// `manifest.json` mirroring the manifest of Signal packs (`Pack` in signal's `StickerResources.proto`),
+/// which is what uploaders (e.g. `signalstickers-client`) build from the images.
+#[derive(Serialize)]
+struct SignalManifest<'a> {
+    title: &'a str,
+    author: &'a str,
+    cover: Option<SignalSticker<'a>>,
+    stickers: Vec<SignalSticker<'a>>,
+}
+
+#[derive(Serialize)]
+struct SignalSticker<'a> {
+    file: &'a str,
+    /// Signal only supports a single emoji per sticker (and so does telegram).
+    emoji: &'a str,
+}
+
+fn signal_metadata(info: &StickerSetInfo, cover: Option<&[u8]>) -> Vec<(String, Vec<u8>)> {
+    const COVER_FILE: &str = "cover.webp";
+
+    let stickers = info
+        .stickers
+        .iter()
+        .filter_map(|sticker| {
+            let (_, file) = sticker.path.split_once('/')?;
+            let emoji = sticker.emoji.as_deref().unwrap_or_default();
+
+            Some(SignalSticker { file, emoji })
+        })
+        .collect();
+
+    // Sets don't have authors (at least not in the bot api), so the best we can do is to point to the original
+    let author = format!("t.me/addstickers/{}", info.name);
+    let manifest = SignalManifest {
+        title: &info.title,
+        author: &author,
+        // The cover is made from the first sticker, so it gets its emoji
+        cover: cover.map(|_| SignalSticker {
+            file: COVER_FILE,
+            emoji: info
+                .stickers
+                .first()
+                .and_then(|sticker| sticker.emoji.as_deref())
+                .unwrap_or_default(),
+        }),
+        stickers,
+    };
+    let manifest =
+        serde_json::to_vec_pretty(&manifest).expect("serializing plain structs doesn't fail");
+
+    let mut files: Vec<_> = cover
+        .map(|cover| (format!("{}/{COVER_FILE}", info.name), cover.to_vec()))
+        .into_iter()
+        .collect();
+    files.push((format!("{}/manifest.json", info.name), manifest));
+
+    files
+}
+
+/// Makes a name of a custom emoji from the name of the sticker's emoji, e.g. `grinning_face`.
+///
+/// Names are used as `:shortcodes:`, so they are limited to lowercase letters, digits and underscores
+/// (Discord also allows uppercase letters, Slack doesn't).
+fn emoji_name(emojis: &str, idx: usize) -> String {
+    let mut name = String::new();
+    for c in stuff::emoji_name(emojis).unwrap_or_default().chars() {
+        match c {
+            c if c.is_ascii_alphanumeric() => name.push(c.to_ascii_lowercase()),
+            // Spaces, colons, etc (and non-ascii letters, sadly) are collapsed into a single underscore
+            _ if !name.is_empty() && !name.ends_with('_') => name.push('_'),
+            _ => {}
+        }
+    }
+
+    name.truncate(EMOJI_NAME_MAX);
+    match name.trim_end_matches('_') {
+        name if name.len() >= 2 => name.to_owned(),
+        _ => format!("emoji_{idx:03}"),
+    }
+}
+
+/// `emoji.csv` mapping emoji of the stickers to the names and files of custom emoji.
+fn emoji_csv(info: &StickerSetInfo) -> Vec<u8> {
+    let mut csv = String::from("emoji,name,file\n");
+    for sticker in &info.stickers {
+        let emoji = sticker.emoji.as_deref().unwrap_or_default();
+        let name = sticker
+            .path
+            .rsplit_once('.')
+            .map_or(&*sticker.path, |(name, _)| name);
+
+        csv.push_str(&format!(
+            "{},{},{}\n",
+            csv_field(emoji),
+            csv_field(name),
+            csv_field(&sticker.path)
+        ));
+    }
+
+    csv.into_bytes()
+}
+
+/// Quotes a csv field if needed (names are safe, but emoji come from telegram and may be anything).
+fn csv_field(field: &str) -> String {
+    match field.contains(['"', ',', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_owned(),
+    }
+}
+
+/// Returns the index of the WhatsApp pack the `idx`-th of `count` stickers goes to.
+///
+/// Stickers are spread evenly, so that the last pack doesn't end up with less than the minimum.
+fn whatsapp_pack(idx: usize, count: usize) -> usize {
+    let packs = count.div_ceil(WHATSAPP_MAX_STICKERS).max(1);
+    idx * packs / count.max(1)
+}
+
+fn whatsapp_identifier(set_name: &str, pack: usize) -> String {
+    match pack {
+        0 => set_name.to_owned(),
+        _ => format!("{set_name}_{}", pack + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::{Sticker, StickerKind, StickerSet};
+
+    use super::{
+        csv_field, emoji_name, file_name, metadata, whatsapp_pack, WHATSAPP_MAX_STICKERS,
+        WHATSAPP_MIN_STICKERS,
+    };
+    use crate::{archive::Entry, query_command::DownloadFormat, sticker_set_info::StickerSetInfo};
+
+    fn set(count: usize) -> StickerSet {
+        let sticker = |idx: usize| Sticker {
+            file_id: format!("id{idx}"),
+            file_unique_id: format!("uid{idx}"),
+            width: 512,
+            height: 512,
+            kind: StickerKind::Webp,
+            thumb: None,
+            emoji: Some(["😀", "🐈"][idx % 2].to_owned()),
+            set_name: Some("cats".to_owned()),
+            premium_animation: None,
+            mask_position: None,
+            file_size: 1000,
+        };
+
+        StickerSet {
+            name: "cats".to_owned(),
+            title: "Cats".to_owned(),
+            kind: StickerKind::Webp,
+            contains_masks: false,
+            stickers: (0..count).map(sticker).collect(),
+            thumb: None,
+        }
+    }
+
+    #[test]
+    fn emoji_names() {
+        assert_eq!(emoji_name("😀", 0), "grinning_face");
+        assert_eq!(emoji_name("👍🏽", 0), "thumbs_up_medium_skin_tone");
+        assert_eq!(emoji_name("🇨🇮", 0), "flag_c_te_d_ivoire");
+        // "woman shrugging: medium skin tone" is too long
+        assert_eq!(emoji_name("🤷🏽‍♀️", 0), "woman_shrugging_medium_skin");
+        assert_eq!(emoji_name("", 7), "emoji_007");
+
+        assert_eq!(csv_field("😀"), "😀");
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn whatsapp_packs() {
+        for count in [3usize, 30, 31, 61, 120] {
+            let mut sizes = vec![0; count.div_ceil(WHATSAPP_MAX_STICKERS)];
+            for idx in 0..count {
+                sizes[whatsapp_pack(idx, count)] += 1;
+            }
+
+            assert!(
+                sizes
+                    .iter()
+                    .all(|size| (WHATSAPP_MIN_STICKERS..=WHATSAPP_MAX_STICKERS).contains(size)),
+                "{count}: {sizes:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn whatsapp_contents() {
+        let set = set(40);
+        // The second volume of an archive, with only the second pack of the set
+        let entries: Vec<_> = (20..40)
+            .map(|idx| Entry {
+                idx,
+                name: format!("{}.webp", file_name(DownloadFormat::WhatsApp, &set, idx)),
+                size: 1000,
+            })
+            .collect();
+        let info = StickerSetInfo::new(&set, &entries);
+
+        let files = metadata(DownloadFormat::WhatsApp, &info, Some(b"tray"));
+        let names: Vec<_> = files.iter().map(|(name, _)| &**name).collect();
+        assert_eq!(names, ["cats_2/tray.png", "contents.json"]);
+
+        let contents: serde_json::Value = serde_json::from_slice(&files[1].1).unwrap();
+        let packs = contents["sticker_packs"].as_array().unwrap();
+        assert_eq!(packs.len(), 1);
+
+        let pack = &packs[0];
+        assert_eq!(pack["identifier"], "cats_2");
+        assert_eq!(pack["name"], "Cats (2)");
+        assert_eq!(pack["tray_image_file"], "tray.png");
+        assert_eq!(pack["animated_sticker_pack"], false);
+
+        let stickers = pack["stickers"].as_array().unwrap();
+        assert_eq!(stickers.len(), 20);
+        assert_eq!(stickers[0]["image_file"], "020.webp");
+        assert_eq!(stickers[0]["emojis"], serde_json::json!(["😀"]));
+        assert_eq!(stickers[1]["emojis"], serde_json::json!(["🐈"]));
+    }
+
+    #[test]
+    fn signal_manifest() {
+        let set = set(3);
+        let entries: Vec<_> = (0..3)
+            .map(|idx| Entry {
+                idx,
+                name: format!("{}.webp", file_name(DownloadFormat::Signal, &set, idx)),
+                size: 1000,
+            })
+            .collect();
+        let info = StickerSetInfo::new(&set, &entries);
+
+        let files = metadata(DownloadFormat::Signal, &info, Some(b"cover"));
+        let names: Vec<_> = files.iter().map(|(name, _)| &**name).collect();
+        assert_eq!(names, ["cats/cover.webp", "cats/manifest.json"]);
+
+        let manifest: serde_json::Value = serde_json::from_slice(&files[1].1).unwrap();
+        assert_eq!(
+            manifest,
+            serde_json::json!({
+                "title": "Cats",
+                "author": "t.me/addstickers/cats",
+                "cover": { "file": "cover.webp", "emoji": "😀" },
+                "stickers": [
+                    { "file": "000.webp", "emoji": "😀" },
+                    { "file": "001.webp", "emoji": "🐈" },
+                    { "file": "002.webp", "emoji": "😀" },
+                ],
+            })
+        );
+    }
+}
@@ -1,60 +1,78 @@
 use std::io;
 
-use fast_image_resize::{Image, ImageView, MulDiv, PixelType, ResizeAlg, Resizer};
+use fast_image_resize::{FilterType, Image, MulDiv, PixelType, ResizeAlg, Resizer};
 use teloxide::types::InputFile;
 
-/// Generates a thumbnail for a sticker archive given a webp image.
-pub fn generate_thumbnail(webp: &[u8]) -> InputFile {
+use crate::config::ThumbnailConfig;
+
+/// Generates a thumbnail for a sticker archive given an rgba image (e.g. the first sticker in the set).
+///
+/// Transparent parts of the image are filled with `background` (gray level, `0` is black, `255` is white).
+pub fn generate_thumbnail(
+    w: u32,
+    h: u32,
+    raw: &[u8],
+    background: u8,
+    config: &ThumbnailConfig,
+) -> InputFile {
     // FIXME: remove unwraps
 
-    let (w, h, raw) = libwebp::WebPDecodeRGBA(webp).unwrap();
-
-    let mut src = ImageView::from_buffer(
+    let mut src = Image::from_vec_u8(
         w.try_into().unwrap(),
         h.try_into().unwrap(),
-        &raw,
+        raw.to_vec(),
         PixelType::U8x4,
     )
     .unwrap();
 
     // Desired size
-    let w = 256.try_into().unwrap();
+    let w = config.size.try_into().unwrap();
     let h = w;
 
-    // Set cropping so the result is not squished
-    src.set_crop_box_to_fit_dst_size(w, h, None);
+    // With color channels multiplied by alpha (the result will be that transparent pixel are black)
+    //
+    // This is needed for blending below, and also for resizing (otherwise colors of transparent pixels
+    // would bleed into the visible ones)
+    MulDiv::default()
+        .multiply_alpha_inplace(&mut src.view_mut())
+        .unwrap();
 
     // Resized to desired size
-    let resized = {
+    let mut no_alpha = {
+        let mut src = src.view();
+        // Set cropping so the result is not squished
+        src.set_crop_box_to_fit_dst_size(w, h, None);
+
         let mut dst = Image::new(w, h, PixelType::U8x4);
 
-        let mut resizer = Resizer::new(ResizeAlg::Nearest);
+        let mut resizer = Resizer::new(ResizeAlg::Convolution(FilterType::Lanczos3));
         resizer.resize(&src, &mut dst.view_mut()).unwrap();
 
-        dst
+        dst.into_vec()
     };
 
-    // With color channels multiplied by alpha (the result will be that transparent pixel are black)
-    //
-    // This is needed
-    let no_alpha = {
-        let mut dst = Image::new(w, h, PixelType::U8x4);
-
-        let mul_div = MulDiv::default();
-        mul_div
-            .multiply_alpha(&resized.view(), &mut dst.view_mut())
-            .unwrap();
-
-        dst
-    };
+    // Blended with the background (which is just adding the background multiplied by `1 - alpha`)
+    if background != 0 {
+        for pixel in no_alpha.chunks_exact_mut(4) {
+            let fill = (background as u16 * (255 - pixel[3] as u16) / 255) as u8;
+            for channel in &mut pixel[..3] {
+                *channel = channel.saturating_add(fill);
+            }
+        }
+    }
 
     // Converted to jpeg
     let compressed = {
         let mut dst = io::Cursor::new(Vec::new());
 
-        let encoder = jpeg_encoder::Encoder::new(&mut dst, 90);
+        let encoder = jpeg_encoder::Encoder::new(&mut dst, config.jpeg_quality);
         encoder
-            .encode(&no_alpha.buffer(), 256, 256, jpeg_encoder::ColorType::Rgba)
+            .encode(
+                &no_alpha,
+                config.size as u16,
+                config.size as u16,
+                jpeg_encoder::ColorType::Rgba,
+            )
             .unwrap();
 
         dst.into_inner()
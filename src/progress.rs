@@ -1,6 +1,11 @@
 use futures::future::FutureExt;
-use std::pin::Pin;
-use teloxide::{prelude::Requester, types::ChatId};
+use std::{future::Future, pin::Pin};
+use teloxide::{
+    payloads::EditMessageTextSetters,
+    prelude::Requester,
+    types::{ChatId, InlineKeyboardMarkup, Message},
+    RequestError,
+};
 use tokio::task::JoinHandle;
 
 use crate::{error::ResultExt, Bot};
@@ -10,6 +15,8 @@ pub struct Progress {
     bot: Bot,
     chat_id: ChatId,
     message_id: i32,
+    /// Keyboard that is kept under the progress message (editing the text without it would remove it).
+    keyboard: Option<InlineKeyboardMarkup>,
     task: Option<JoinHandle<()>>,
 }
 
@@ -20,6 +27,7 @@ impl Progress {
             bot: bot.clone(),
             chat_id,
             message_id,
+            keyboard: None,
             task: None,
         };
 
@@ -37,12 +45,16 @@ impl Progress {
         }
     }
 
-    #[allow(dead_code)]
     pub fn title(&mut self, title: &str) {
         self.title = title.to_owned();
         self.do_update(self.title.clone())
     }
 
+    /// Sets the keyboard shown under the progress message, it's updated with the next update of the message.
+    pub fn keyboard(&mut self, keyboard: Option<InlineKeyboardMarkup>) {
+        self.keyboard = keyboard;
+    }
+
     pub fn title_imp(&mut self, title: &str) {
         self.title = title.to_owned();
         self.do_update_imp(self.title.clone())
@@ -55,38 +67,37 @@ impl Progress {
             }
         }
 
-        self.task.get_or_insert_with(|| {
-            let bot = self.bot.clone();
-            let &mut Self {
-                chat_id,
-                message_id,
-                ..
-            } = self;
-
-            tokio::spawn(async move { bot.edit_message_text(chat_id, message_id, to).await.fine() })
-        });
+        if self.task.is_none() {
+            let edit = self.edit(to);
+            self.task = Some(tokio::spawn(async move { edit.await.fine() }));
+        }
     }
 
     fn do_update_imp(&mut self, to: String) {
         let task = self.task.take();
-
-        let bot = self.bot.clone();
-        let &mut Self {
-            chat_id,
-            message_id,
-            ..
-        } = self;
+        let edit = self.edit(to);
 
         let handle = tokio::spawn(async move {
             if let Some(task) = task {
                 task.await.fine();
             }
 
-            bot.edit_message_text(chat_id, message_id, to).await.fine()
+            edit.await.fine()
         });
 
         self.task = Some(handle);
     }
+
+    fn edit(&self, to: String) -> impl Future<Output = Result<Message, RequestError>> {
+        let mut edit = self
+            .bot
+            .edit_message_text(self.chat_id, self.message_id, to);
+        if let Some(keyboard) = &self.keyboard {
+            edit = edit.reply_markup(keyboard.clone());
+        }
+
+        edit
+    }
 }
 
 pub struct ProgressScope<'p, U = Dimensionless> {
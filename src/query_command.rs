@@ -1,3 +1,8 @@
+use serde::{Deserialize, Serialize};
+use teloxide::types::StickerKind;
+
+use crate::{i18n::Language, settings::ThumbnailStyle};
+
 use Version::*;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -14,12 +19,58 @@ enum Version {
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum QueryAction {
     Download(ActionDownload),
+    /// Cancel the download started from the message with the button.
+    Cancel,
+    /// Change a setting of the user who pressed the button (see `/settings`).
+    Settings(ActionSettings),
+    History(ActionHistory),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct ActionDownload {
     pub target: DownloadTarget,
     pub format: DownloadFormat,
+    /// Container format of the archive, `None` means "use the setting of the user who pressed the button".
+    pub archive: Option<ArchiveFormat>,
+    /// Size of downloaded stickers (see `/resize`), `None` means the original size.
+    pub resize: Option<Resize>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Resize {
+    /// Width and height of the square the stickers are resized to.
+    pub size: u32,
+    pub mode: ResizeMode,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResizeMode {
+    /// Scale the sticker to fit into the square, keeping the aspect ratio.
+    Fit,
+    /// Scale the sticker to cover the square, cropping what doesn't fit.
+    Fill,
+    /// Like [`Fit`](Self::Fit), but the result is padded with transparent pixels to be a square.
+    Pad,
+}
+
+/// Sets a setting to a value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ActionSettings {
+    Format(Option<DownloadFormat>),
+    Archive(ArchiveFormat),
+    /// Index in [`NAMING_PRESETS`](crate::settings::NAMING_PRESETS).
+    Naming(u8),
+    Language(Language),
+    Thumbnail(ThumbnailStyle),
+}
+
+/// Buttons of the `/history` message.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ActionHistory {
+    /// Show a page of the history.
+    Page(u32),
+    /// Send the documents of the job with the given id again.
+    Resend(i64),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -28,10 +79,45 @@ pub enum DownloadTarget {
     All,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum DownloadFormat {
     Png,
     Webp,
+    /// Gzipped lottie animation, exactly as telegram stores it.
+    Tgs,
+    /// Decompressed lottie animation.
+    Lottie,
+    /// N.B. there is no `.mp4` format, encoding h264 would need yet another C library. A single `.gif` is converted
+    ///      to an (mp4) animation by telegram itself, which is what people usually want an `.mp4` for.
+    Gif,
+    /// Animated png.
+    Apng,
+    /// Video sticker, exactly as telegram stores it.
+    Webm,
+    /// `.zip` with every frame of the animation as a `.png`.
+    Frames,
+    /// Jpeg, with transparent parts filled with `convert.jpeg_background` (see [`config`](crate::config)).
+    Jpeg,
+    Avif,
+    /// Webp re-encoded losslessly (telegram stickers are lossy), mostly useful for editing.
+    WebpLossless,
+    /// Whole set as a WhatsApp sticker pack (see [`pack`](crate::pack)).
+    WhatsApp,
+    /// Whole set as a Signal sticker pack (see [`pack`](crate::pack)).
+    Signal,
+    /// Whole set as custom emoji for Discord and Slack (see [`pack`](crate::pack)).
+    Emoji,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+    SevenZ,
 }
 
 impl QueryCommand {
@@ -40,7 +126,51 @@ impl QueryCommand {
 
         Self {
             _v: V0,
-            action: Download(ActionDownload { target, format }),
+            action: Download(ActionDownload {
+                target,
+                format,
+                archive: None,
+                resize: None,
+            }),
+        }
+    }
+
+    /// Sets the archive format of a download command.
+    pub fn with_archive(mut self, archive: Option<ArchiveFormat>) -> Self {
+        if let QueryAction::Download(action) = &mut self.action {
+            action.archive = archive;
+        }
+
+        self
+    }
+
+    /// Sets the size of stickers of a download command.
+    pub fn with_resize(mut self, resize: Option<Resize>) -> Self {
+        if let QueryAction::Download(action) = &mut self.action {
+            action.resize = resize;
+        }
+
+        self
+    }
+
+    pub fn cancel() -> Self {
+        Self {
+            _v: V0,
+            action: QueryAction::Cancel,
+        }
+    }
+
+    pub fn settings(action: ActionSettings) -> Self {
+        Self {
+            _v: V0,
+            action: QueryAction::Settings(action),
+        }
+    }
+
+    pub fn history(action: ActionHistory) -> Self {
+        Self {
+            _v: V0,
+            action: QueryAction::History(action),
         }
     }
 
@@ -53,8 +183,8 @@ impl QueryCommand {
         out
     }
 
-    pub fn decode(mut data: &str) -> Option<Self> {
-        let mut d = Decoder(&mut data);
+    pub fn decode(data: &str) -> Option<Self> {
+        let mut d = Decoder(data);
 
         let _v = Version::decode(&mut d)?;
         let action = QueryAction::decode(_v, &mut d)?;
@@ -88,6 +218,15 @@ impl QueryAction {
                     out.push('d');
                     action_download.encode(v, out)
                 }
+                QueryAction::Cancel => out.push('c'),
+                QueryAction::Settings(action_settings) => {
+                    out.push('s');
+                    action_settings.encode(v, out)
+                }
+                QueryAction::History(action_history) => {
+                    out.push('h');
+                    action_history.encode(v, out)
+                }
             },
         }
     }
@@ -100,6 +239,9 @@ impl QueryAction {
 
                     Some(Self::Download(action_download))
                 }
+                'c' => Some(Self::Cancel),
+                's' => Some(Self::Settings(ActionSettings::decode(v, d)?)),
+                'h' => Some(Self::History(ActionHistory::decode(v, d)?)),
                 _ => None,
             },
         }
@@ -110,9 +252,22 @@ impl ActionDownload {
     fn encode(&self, v: Version, out: &mut String) {
         match v {
             V0 => {
-                let Self { target, format } = self;
+                let Self {
+                    target,
+                    format,
+                    archive,
+                    resize,
+                } = self;
                 target.encode(v, out);
                 format.encode(v, out);
+
+                // Optional, so that old buttons (and buttons that follow user settings) stay short
+                if let Some(archive) = archive {
+                    archive.encode(v, out);
+                }
+                if let Some(resize) = resize {
+                    resize.encode(v, out);
+                }
             }
         }
     }
@@ -120,8 +275,113 @@ impl ActionDownload {
     fn decode(v: Version, d: &mut Decoder<'_>) -> Option<Self> {
         let target = DownloadTarget::decode(v, d)?;
         let format = DownloadFormat::decode(v, d)?;
+        // `r` is not an archive format, so it can only be the start of a resize
+        let archive = match d.peek() {
+            None | Some('r') => None,
+            Some(_) => Some(ArchiveFormat::decode(v, d)?),
+        };
+        let resize = match d.0.is_empty() {
+            true => None,
+            false => Some(Resize::decode(v, d)?),
+        };
+
+        Some(Self {
+            target,
+            format,
+            archive,
+            resize,
+        })
+    }
+}
+
+impl ActionSettings {
+    fn encode(&self, v: Version, out: &mut String) {
+        match v {
+            V0 => match self {
+                Self::Format(format) => {
+                    out.push('f');
+                    match format {
+                        Some(format) => format.encode(v, out),
+                        None => out.push('-'),
+                    }
+                }
+                Self::Archive(archive) => {
+                    out.push('a');
+                    archive.encode(v, out)
+                }
+                Self::Naming(preset) => {
+                    out.push('n');
+                    out.push_str(&preset.to_string())
+                }
+                Self::Language(language) => {
+                    out.push('l');
+                    out.push(match language {
+                        Language::English => 'e',
+                        Language::Russian => 'r',
+                    })
+                }
+                Self::Thumbnail(thumbnail) => {
+                    out.push('t');
+                    out.push(match thumbnail {
+                        ThumbnailStyle::Black => 'b',
+                        ThumbnailStyle::White => 'w',
+                        ThumbnailStyle::None => 'n',
+                    })
+                }
+            },
+        }
+    }
+
+    fn decode(v: Version, d: &mut Decoder<'_>) -> Option<Self> {
+        match v {
+            V0 => match d.eat()? {
+                'f' => match d.0 {
+                    "-" => Some(Self::Format(None)),
+                    _ => Some(Self::Format(Some(DownloadFormat::decode(v, d)?))),
+                },
+                'a' => Some(Self::Archive(ArchiveFormat::decode(v, d)?)),
+                'n' => Some(Self::Naming(d.0.parse().ok()?)),
+                'l' => match d.eat()? {
+                    'e' => Some(Self::Language(Language::English)),
+                    'r' => Some(Self::Language(Language::Russian)),
+                    _ => None,
+                },
+                't' => match d.eat()? {
+                    'b' => Some(Self::Thumbnail(ThumbnailStyle::Black)),
+                    'w' => Some(Self::Thumbnail(ThumbnailStyle::White)),
+                    'n' => Some(Self::Thumbnail(ThumbnailStyle::None)),
+                    _ => None,
+                },
+                _ => None,
+            },
+        }
+    }
+}
+
+impl ActionHistory {
+    fn encode(&self, v: Version, out: &mut String) {
+        match v {
+            V0 => match self {
+                Self::Page(page) => {
+                    out.push('p');
+                    out.push_str(&page.to_string())
+                }
+                Self::Resend(id) => {
+                    out.push('r');
+                    out.push_str(&id.to_string())
+                }
+            },
+        }
+    }
 
-        Some(Self { target, format })
+    fn decode(v: Version, d: &mut Decoder<'_>) -> Option<Self> {
+        match v {
+            V0 => match d.eat()? {
+                'p' => Some(Self::Page(d.0.parse().ok()?)),
+                'r' => Some(Self::Resend(d.0.parse().ok()?)),
+                _ => None,
+            },
+        }
     }
 }
 
@@ -147,11 +407,41 @@ impl DownloadTarget {
 }
 
 impl DownloadFormat {
+    /// All formats, originals first, then conversions.
+    pub const ALL: [Self; 14] = [
+        Self::Webp,
+        Self::Tgs,
+        Self::Lottie,
+        Self::Webm,
+        Self::Png,
+        Self::WebpLossless,
+        Self::Jpeg,
+        Self::Avif,
+        Self::Gif,
+        Self::Apng,
+        Self::Frames,
+        Self::WhatsApp,
+        Self::Signal,
+        Self::Emoji,
+    ];
+
     fn encode(&self, v: Version, out: &mut String) {
         match v {
             V0 => match self {
                 Self::Png => out.push('p'),
                 Self::Webp => out.push('w'),
+                Self::Tgs => out.push('t'),
+                Self::Lottie => out.push('l'),
+                Self::Gif => out.push('g'),
+                Self::Apng => out.push('a'),
+                Self::Webm => out.push('m'),
+                Self::Frames => out.push('f'),
+                Self::Jpeg => out.push('j'),
+                Self::Avif => out.push('v'),
+                Self::WebpLossless => out.push('W'),
+                Self::WhatsApp => out.push('A'),
+                Self::Signal => out.push('S'),
+                Self::Emoji => out.push('E'),
             },
         }
     }
@@ -161,6 +451,18 @@ impl DownloadFormat {
             V0 => match d.eat()? {
                 'p' => Some(Self::Png),
                 'w' => Some(Self::Webp),
+                't' => Some(Self::Tgs),
+                'l' => Some(Self::Lottie),
+                'g' => Some(Self::Gif),
+                'a' => Some(Self::Apng),
+                'm' => Some(Self::Webm),
+                'f' => Some(Self::Frames),
+                'j' => Some(Self::Jpeg),
+                'v' => Some(Self::Avif),
+                'W' => Some(Self::WebpLossless),
+                'A' => Some(Self::WhatsApp),
+                'S' => Some(Self::Signal),
+                'E' => Some(Self::Emoji),
                 _ => None,
             },
         }
@@ -170,17 +472,186 @@ impl DownloadFormat {
         match self {
             DownloadFormat::Png => "png",
             DownloadFormat::Webp => "webp",
+            DownloadFormat::Tgs => "tgs",
+            DownloadFormat::Lottie => "json",
+            DownloadFormat::Gif => "gif",
+            // Apng is backwards compatible with png, so everyone uses .png
+            DownloadFormat::Apng => "png",
+            DownloadFormat::Webm => "webm",
+            DownloadFormat::Frames => "zip",
+            DownloadFormat::Jpeg => "jpg",
+            DownloadFormat::Avif => "avif",
+            DownloadFormat::WebpLossless => "webp",
+            // Extension of the stickers in the pack, the pack itself is a .zip
+            DownloadFormat::WhatsApp | DownloadFormat::Signal => "webp",
+            DownloadFormat::Emoji => "gif",
+        }
+    }
+
+    /// Returns the extension of stickers of kind `kind` downloaded as this format.
+    ///
+    /// This is [`ext`](Self::ext), except for emoji, where static stickers are `.png`s
+    /// (gif transparency is 1-bit, which makes edges look awful), and for animated Signal stickers,
+    /// which are apngs.
+    pub fn ext_for(&self, kind: &StickerKind) -> &'static str {
+        match (self, kind) {
+            (DownloadFormat::Emoji, StickerKind::Webp) => "png",
+            (DownloadFormat::Signal, StickerKind::Animated | StickerKind::Video) => "png",
+            _ => self.ext(),
         }
     }
 
     pub fn is_fine_for_sending_alone(&self) -> bool {
-        !matches!(self, Self::Webp)
+        // Telegram shows both .webp and .tgs documents as stickers, .webm documents as videos
+        // and converts .gif documents to (mp4) animations
+        !matches!(
+            self,
+            Self::Webp | Self::WebpLossless | Self::Tgs | Self::Webm | Self::Gif
+        ) && !self.is_pack()
+    }
+
+    /// Returns `true` if this is a pack for another messenger (or an emoji bundle),
+    /// which can only be made of a whole set and is always a `.zip`.
+    pub fn is_pack(&self) -> bool {
+        matches!(self, Self::WhatsApp | Self::Signal | Self::Emoji)
+    }
+}
+
+impl ArchiveFormat {
+    pub const ALL: [Self; 5] = [
+        Self::Zip,
+        Self::Tar,
+        Self::TarGz,
+        Self::TarZst,
+        Self::SevenZ,
+    ];
+
+    fn encode(&self, v: Version, out: &mut String) {
+        match v {
+            V0 => match self {
+                Self::Zip => out.push('z'),
+                Self::Tar => out.push('t'),
+                Self::TarGz => out.push('g'),
+                Self::TarZst => out.push('s'),
+                Self::SevenZ => out.push('7'),
+            },
+        }
+    }
+
+    fn decode(v: Version, d: &mut Decoder<'_>) -> Option<Self> {
+        match v {
+            V0 => match d.eat()? {
+                'z' => Some(Self::Zip),
+                't' => Some(Self::Tar),
+                'g' => Some(Self::TarGz),
+                's' => Some(Self::TarZst),
+                '7' => Some(Self::SevenZ),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn ext(&self) -> &'static str {
+        match self {
+            Self::Zip => "zip",
+            Self::Tar => "tar",
+            Self::TarGz => "tar.gz",
+            Self::TarZst => "tar.zst",
+            Self::SevenZ => "7z",
+        }
+    }
+
+    /// Parses the format from its extension (as returned by [`ext`](Self::ext)), e.g. `tar.gz`.
+    pub fn from_ext(ext: &str) -> Option<Self> {
+        let ext = ext.trim().trim_start_matches('.');
+        Self::ALL
+            .into_iter()
+            .find(|f| f.ext().eq_ignore_ascii_case(ext))
+    }
+}
+
+impl Resize {
+    /// Sizes suggested in `/resize` usage (any other up to [`MAX_SIZE`](Self::MAX_SIZE) works too).
+    pub const PRESETS: [u32; 5] = [512, 256, 128, 96, 64];
+
+    /// Stickers are 512px, there is no point in upscaling them much more than that.
+    pub const MAX_SIZE: u32 = 1024;
+
+    /// Parses arguments of `/resize`, e.g. `128 pad`, the mode is [`Fit`](ResizeMode::Fit) by default.
+    pub fn parse(size: &str, mode: Option<&str>) -> Option<Self> {
+        let size = size.trim_end_matches("px").parse().ok()?;
+        let mode = match mode {
+            Some(mode) => ResizeMode::from_name(mode)?,
+            None => ResizeMode::Fit,
+        };
+
+        (1..=Self::MAX_SIZE)
+            .contains(&size)
+            .then_some(Self { size, mode })
+    }
+
+    fn encode(&self, v: Version, out: &mut String) {
+        match v {
+            V0 => {
+                out.push('r');
+                out.push(match self.mode {
+                    ResizeMode::Fit => 'f',
+                    ResizeMode::Fill => 'c',
+                    ResizeMode::Pad => 'p',
+                });
+                out.push_str(&self.size.to_string());
+            }
+        }
+    }
+
+    fn decode(v: Version, d: &mut Decoder<'_>) -> Option<Self> {
+        match v {
+            V0 => {
+                if d.eat()? != 'r' {
+                    return None;
+                }
+
+                let mode = match d.eat()? {
+                    'f' => ResizeMode::Fit,
+                    'c' => ResizeMode::Fill,
+                    'p' => ResizeMode::Pad,
+                    _ => return None,
+                };
+                let size = d.0.parse().ok()?;
+
+                (1..=Self::MAX_SIZE)
+                    .contains(&size)
+                    .then_some(Self { size, mode })
+            }
+        }
+    }
+}
+
+impl ResizeMode {
+    pub const ALL: [Self; 3] = [Self::Fit, Self::Fill, Self::Pad];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fit => "fit",
+            Self::Fill => "fill",
+            Self::Pad => "pad",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|m| m.name().eq_ignore_ascii_case(name))
     }
 }
 
 struct Decoder<'a>(&'a str);
 
 impl Decoder<'_> {
+    fn peek(&self) -> Option<char> {
+        self.0.chars().next()
+    }
+
     fn eat(&mut self) -> Option<char> {
         let c = self.0.chars().next()?;
 
@@ -192,9 +663,16 @@ impl Decoder<'_> {
 
 #[cfg(test)]
 mod tests {
+    use teloxide::types::StickerKind;
+
     use crate::query_command::QueryCommand;
 
-    use super::{DownloadFormat, DownloadTarget};
+    use crate::{i18n::Language, settings::ThumbnailStyle};
+
+    use super::{
+        ActionHistory, ActionSettings, ArchiveFormat, DownloadFormat, DownloadTarget, Resize,
+        ResizeMode,
+    };
 
     #[test]
     fn smoke() {
@@ -203,4 +681,139 @@ mod tests {
         assert_eq!(command.encode(), "0dsp");
         assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
     }
+
+    #[test]
+    fn animated_formats() {
+        let command = QueryCommand::download(DownloadTarget::All, DownloadFormat::Tgs);
+        assert_eq!(command.encode(), "0dat");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+
+        let command = QueryCommand::download(DownloadTarget::Single, DownloadFormat::Lottie);
+        assert_eq!(command.encode(), "0dsl");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+    }
+
+    #[test]
+    fn cancel() {
+        let command = QueryCommand::cancel();
+        assert_eq!(command.encode(), "0c");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+    }
+
+    #[test]
+    fn video_formats() {
+        let command = QueryCommand::download(DownloadTarget::All, DownloadFormat::Webm);
+        assert_eq!(command.encode(), "0dam");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+
+        let command = QueryCommand::download(DownloadTarget::Single, DownloadFormat::Frames);
+        assert_eq!(command.encode(), "0dsf");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+    }
+
+    #[test]
+    fn static_formats() {
+        let formats = [
+            (DownloadFormat::Jpeg, "0dsj"),
+            (DownloadFormat::Avif, "0dsv"),
+            (DownloadFormat::WebpLossless, "0dsW"),
+        ];
+
+        for (format, encoded) in formats {
+            let command = QueryCommand::download(DownloadTarget::Single, format);
+            assert_eq!(command.encode(), encoded);
+            assert_eq!(QueryCommand::decode(encoded).unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn pack_formats() {
+        let command = QueryCommand::download(DownloadTarget::All, DownloadFormat::WhatsApp);
+        assert_eq!(command.encode(), "0daA");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+
+        let command = QueryCommand::download(DownloadTarget::All, DownloadFormat::Signal);
+        assert_eq!(command.encode(), "0daS");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+
+        let command = QueryCommand::download(DownloadTarget::All, DownloadFormat::Emoji);
+        assert_eq!(command.encode(), "0daE");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+        assert_eq!(DownloadFormat::Emoji.ext_for(&StickerKind::Webp), "png");
+        assert_eq!(DownloadFormat::Emoji.ext_for(&StickerKind::Video), "gif");
+        assert_eq!(DownloadFormat::Signal.ext_for(&StickerKind::Webp), "webp");
+        assert_eq!(
+            DownloadFormat::Signal.ext_for(&StickerKind::Animated),
+            "png"
+        );
+    }
+
+    #[test]
+    fn archive_formats() {
+        let command = QueryCommand::download(DownloadTarget::All, DownloadFormat::Png)
+            .with_archive(Some(ArchiveFormat::TarZst));
+        assert_eq!(command.encode(), "0daps");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+
+        assert!(QueryCommand::decode("0dapx").is_none());
+        assert_eq!(
+            ArchiveFormat::from_ext(".TAR.GZ"),
+            Some(ArchiveFormat::TarGz)
+        );
+    }
+
+    #[test]
+    fn resize() {
+        let resize = Resize {
+            size: 96,
+            mode: ResizeMode::Pad,
+        };
+
+        let command = QueryCommand::download(DownloadTarget::Single, DownloadFormat::Png)
+            .with_resize(Some(resize));
+        assert_eq!(command.encode(), "0dsprp96");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+
+        let command = command.with_archive(Some(ArchiveFormat::Zip));
+        assert_eq!(command.encode(), "0dspzrp96");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+
+        assert!(QueryCommand::decode("0dsprp0").is_none());
+        assert!(QueryCommand::decode("0dsprx96").is_none());
+
+        assert_eq!(Resize::parse("96px", Some("PAD")), Some(resize));
+        assert_eq!(Resize::parse("128", None).unwrap().mode, ResizeMode::Fit);
+        assert_eq!(Resize::parse("4096", None), None);
+    }
+
+    #[test]
+    fn settings() {
+        let actions = [
+            (ActionSettings::Format(None), "0sf-"),
+            (ActionSettings::Format(Some(DownloadFormat::Gif)), "0sfg"),
+            (ActionSettings::Archive(ArchiveFormat::Zip), "0saz"),
+            (ActionSettings::Naming(3), "0sn3"),
+            (ActionSettings::Language(Language::Russian), "0slr"),
+            (ActionSettings::Thumbnail(ThumbnailStyle::None), "0stn"),
+        ];
+
+        for (action, encoded) in actions {
+            let command = QueryCommand::settings(action);
+            assert_eq!(command.encode(), encoded);
+            assert_eq!(QueryCommand::decode(encoded).unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn history() {
+        let command = QueryCommand::history(ActionHistory::Page(12));
+        assert_eq!(command.encode(), "0hp12");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+
+        let command = QueryCommand::history(ActionHistory::Resend(1234567));
+        assert_eq!(command.encode(), "0hr1234567");
+        assert_eq!(QueryCommand::decode(&command.encode()).unwrap(), command);
+
+        assert!(QueryCommand::decode("0hp").is_none());
+    }
 }
@@ -0,0 +1,247 @@
+//! Queue of download jobs.
+//!
+//! Only `queue.max_running` (see [`config`], [`MAX_RUNNING`] by default) jobs run at the same time, the rest wait in the queue.
+//! The queue is fair: the next job to run is the oldest job of a user with the least number of running jobs,
+//! so a user downloading 10 sets doesn't block everyone else.
+//!
+//! [`config`]: crate::config
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use teloxide::types::UserId;
+use tokio::sync::Notify;
+
+use crate::{config::QueueConfig, error::queueing::QueueFull, progress::Progress};
+
+/// Maximum number of jobs running at the same time.
+pub const MAX_RUNNING: usize = 2;
+
+/// Maximum number of jobs waiting in the queue, when it's full new jobs are rejected.
+pub const MAX_WAITING: usize = 64;
+
+/// Initial guess of how long a job takes, before any job was finished.
+const INITIAL_JOB_DURATION: Duration = Duration::from_secs(10);
+
+#[derive(Clone)]
+pub struct Queue {
+    state: Arc<Mutex<State>>,
+    /// Notified when the queue changes (jobs are added, started or removed), so that waiting jobs update their position.
+    changed: Arc<Notify>,
+}
+
+struct State {
+    max_running: usize,
+    max_waiting: usize,
+    next_id: u64,
+    /// Waiting jobs, oldest first.
+    waiting: Vec<Job>,
+    running: Vec<Job>,
+    /// Ids of jobs that were started, but whose tickets didn't notice that yet.
+    started: HashSet<u64>,
+    /// Moving average of job duration.
+    job_duration: Duration,
+}
+
+#[derive(Clone, Copy)]
+struct Job {
+    id: u64,
+    user: UserId,
+}
+
+/// A place in the queue, the job is considered running/waiting until this is dropped.
+pub struct Ticket {
+    queue: Queue,
+    job: Job,
+    started_at: Option<Instant>,
+}
+
+impl Queue {
+    pub fn new(config: &QueueConfig) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                max_running: config.max_running,
+                max_waiting: config.max_waiting,
+                next_id: 0,
+                waiting: Vec::new(),
+                running: Vec::new(),
+                started: HashSet::new(),
+                job_duration: INITIAL_JOB_DURATION,
+            })),
+            changed: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Puts a job of `user` in the queue, use [`Ticket::wait`] to wait for its turn.
+    pub fn enqueue(&self, user: UserId) -> Result<Ticket, QueueFull> {
+        let mut state = self.state.lock().unwrap();
+        if state.waiting.len() >= state.max_waiting {
+            return Err(QueueFull);
+        }
+
+        let job = Job {
+            id: state.next_id,
+            user,
+        };
+        state.next_id += 1;
+        state.waiting.push(job);
+
+        self.schedule(&mut state);
+
+        Ok(Ticket {
+            queue: self.clone(),
+            job,
+            started_at: None,
+        })
+    }
+
+    /// Starts as many jobs as possible, this is called whenever the queue changes.
+    fn schedule(&self, state: &mut State) {
+        while state.running.len() < state.max_running && !state.waiting.is_empty() {
+            let idx = next_job(&state.running, &state.waiting);
+            let job = state.waiting.remove(idx);
+
+            state.running.push(job);
+            state.started.insert(job.id);
+        }
+
+        // Even if nothing was started, positions change when a waiting job is cancelled
+        // (or when a new job gets ahead of others, see `next_job`)
+        self.changed.notify_waiters();
+    }
+}
+
+impl Ticket {
+    /// Waits until the job can be started, showing the position in the queue in `progress`.
+    pub async fn wait(&mut self, progress: &mut Progress) {
+        let mut last_position = None;
+
+        loop {
+            // N.B. this has to be created before checking the state, so we don't miss notifications
+            let notified = self.queue.changed.notified();
+
+            let (position, eta) = {
+                let mut state = self.queue.state.lock().unwrap();
+                if state.started.remove(&self.job.id) {
+                    self.started_at = Some(Instant::now());
+                    return;
+                }
+
+                let position = state.position(self.job.id);
+                let eta = state.job_duration * (position / state.max_running + 1) as u32;
+                (position, eta)
+            };
+
+            if last_position != Some(position) {
+                last_position = Some(position);
+                progress.title(&format!(
+                    "Waiting in queue: {} job(s) before yours, starting in about {} seconds...",
+                    position,
+                    eta.as_secs().max(1),
+                ));
+            }
+
+            notified.await;
+        }
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        let id = self.job.id;
+
+        state.waiting.retain(|j| j.id != id);
+        state.running.retain(|j| j.id != id);
+        state.started.remove(&id);
+
+        if let Some(started_at) = self.started_at {
+            // Exponential moving average, so that the estimate follows the load
+            let took = started_at.elapsed();
+            state.job_duration = (state.job_duration * 3 + took) / 4;
+        }
+
+        self.queue.schedule(&mut state);
+    }
+}
+
+impl State {
+    /// Returns the number of jobs that will be started before the job `id`.
+    fn position(&self, id: u64) -> usize {
+        // Simulate scheduling (there are at most `max_waiting` jobs, so this is cheap)
+        let mut running = self.running.clone();
+        let mut waiting = self.waiting.clone();
+
+        let mut position = 0;
+        while !waiting.is_empty() {
+            let job = waiting.remove(next_job(&running, &waiting));
+            if job.id == id {
+                break;
+            }
+
+            running.push(job);
+            position += 1;
+        }
+
+        position
+    }
+}
+
+/// Returns the index of the job that should be started next.
+///
+/// `waiting` must not be empty.
+fn next_job(running: &[Job], waiting: &[Job]) -> usize {
+    let running_of = |user| running.iter().filter(|j| j.user == user).count();
+
+    // `min_by_key` returns the first minimum, i.e. the oldest job
+    waiting
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, j)| running_of(j.user))
+        .map(|(idx, _)| idx)
+        .expect("`waiting` must not be empty")
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+    use teloxide::types::UserId;
+
+    use super::{next_job, Job, Queue};
+    use crate::config::QueueConfig;
+
+    #[test]
+    fn fairness() {
+        let job = |id, user| Job {
+            id,
+            user: UserId(user),
+        };
+
+        // User 1 has a job running and two waiting, user 2 came later
+        let running = [job(0, 1)];
+        let waiting = [job(1, 1), job(2, 1), job(3, 2)];
+
+        assert_eq!(next_job(&running, &waiting), 2);
+        assert_eq!(next_job(&[job(0, 1), job(3, 2)], &waiting[..2]), 0);
+    }
+
+    #[test]
+    fn cancelled_waiting_job_notifies() {
+        let queue = Queue::new(&QueueConfig {
+            max_running: 1,
+            max_waiting: 8,
+        });
+
+        let _running = queue.enqueue(UserId(1)).ok().unwrap();
+        let waiting = queue.enqueue(UserId(2)).ok().unwrap();
+        let _behind = queue.enqueue(UserId(3)).ok().unwrap();
+
+        // Nothing starts, but the job behind is now first in the queue
+        let notified = queue.changed.notified();
+        drop(waiting);
+        assert!(notified.now_or_never().is_some());
+        assert_eq!(queue.state.lock().unwrap().waiting.len(), 1);
+    }
+}
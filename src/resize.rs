@@ -0,0 +1,128 @@
+//! Resizing of downloaded stickers (see `/resize`).
+use std::num::NonZeroU32;
+
+use fast_image_resize::{FilterType, Image, MulDiv, PixelType, ResizeAlg, Resizer};
+
+use crate::{
+    convert::encode_err,
+    error::converting::ConvertError,
+    query_command::{Resize, ResizeMode},
+};
+
+/// Returns the size of a `w`×`h` image after resizing it with `resize`.
+pub fn dimensions(w: u32, h: u32, resize: Option<Resize>) -> (u32, u32) {
+    match resize {
+        None => (w, h),
+        Some(Resize { size, mode }) => match mode {
+            ResizeMode::Fit => fit(w, h, size),
+            ResizeMode::Fill | ResizeMode::Pad => (size, size),
+        },
+    }
+}
+
+/// Resizes a `w`×`h` rgba image, the size of the result is given by [`dimensions`].
+pub fn resize(
+    w: u32,
+    h: u32,
+    rgba: Vec<u8>,
+    resize: Option<Resize>,
+) -> Result<Vec<u8>, ConvertError> {
+    let Some(Resize { size, mode }) = resize else {
+        return Ok(rgba);
+    };
+
+    let (dst_w, dst_h) = match mode {
+        ResizeMode::Fit | ResizeMode::Pad => fit(w, h, size),
+        ResizeMode::Fill => (size, size),
+    };
+
+    let (Some(src_w), Some(src_h), Some(dst_w), Some(dst_h)) = (
+        NonZeroU32::new(w),
+        NonZeroU32::new(h),
+        NonZeroU32::new(dst_w),
+        NonZeroU32::new(dst_h),
+    ) else {
+        return Err(ConvertError::Unsupported);
+    };
+
+    let mut src = Image::from_vec_u8(src_w, src_h, rgba, PixelType::U8x4).map_err(encode_err)?;
+
+    // Convolution mixes neighbouring pixels, with straight alpha colors of (invisible) transparent pixels
+    // would bleed into the visible ones, so the resizing is done with premultiplied alpha
+    let mul_div = MulDiv::default();
+    mul_div
+        .multiply_alpha_inplace(&mut src.view_mut())
+        .map_err(encode_err)?;
+
+    let mut src_view = src.view();
+    if mode == ResizeMode::Fill {
+        src_view.set_crop_box_to_fit_dst_size(dst_w, dst_h, None);
+    }
+
+    let mut dst = Image::new(dst_w, dst_h, PixelType::U8x4);
+    Resizer::new(ResizeAlg::Convolution(FilterType::Lanczos3))
+        .resize(&src_view, &mut dst.view_mut())
+        .map_err(encode_err)?;
+    mul_div
+        .divide_alpha_inplace(&mut dst.view_mut())
+        .map_err(encode_err)?;
+
+    let resized = dst.into_vec();
+    match mode {
+        ResizeMode::Pad => Ok(pad(dst_w.get(), dst_h.get(), &resized, size)),
+        ResizeMode::Fit | ResizeMode::Fill => Ok(resized),
+    }
+}
+
+/// Returns the size of a `w`×`h` image scaled to fit into a `size`×`size` square.
+fn fit(w: u32, h: u32, size: u32) -> (u32, u32) {
+    let longer = w.max(h).max(1) as u64;
+    let scale = |x: u32| ((x as u64 * size as u64 + longer / 2) / longer).max(1) as u32;
+
+    (scale(w), scale(h))
+}
+
+/// Centers a `w`×`h` rgba image in a transparent `size`×`size` square.
+fn pad(w: u32, h: u32, rgba: &[u8], size: u32) -> Vec<u8> {
+    let mut out = vec![0; (size * size * 4) as usize];
+    let (x, y) = ((size - w) / 2, (size - h) / 2);
+
+    for (row, line) in rgba
+        .chunks_exact(w as usize * 4)
+        .take(h as usize)
+        .enumerate()
+    {
+        let start = (((y + row as u32) * size + x) * 4) as usize;
+        out[start..start + line.len()].copy_from_slice(line);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query_command::{Resize, ResizeMode};
+
+    use super::{dimensions, resize};
+
+    #[test]
+    fn modes() {
+        // Opaque red 512x256 image
+        let rgba = [255, 0, 0, 255].repeat(512 * 256);
+        let resize_to = |mode| Some(Resize { size: 64, mode });
+
+        for mode in ResizeMode::ALL {
+            let (w, h) = dimensions(512, 256, resize_to(mode));
+            let resized = resize(512, 256, rgba.clone(), resize_to(mode)).unwrap();
+            assert_eq!(resized.len(), (w * h * 4) as usize, "{mode:?}");
+        }
+
+        assert_eq!(dimensions(512, 256, resize_to(ResizeMode::Fit)), (64, 32));
+
+        // Padding is transparent, the image is in the middle
+        let padded = resize(512, 256, rgba, resize_to(ResizeMode::Pad)).unwrap();
+        let pixel = |x: usize, y: usize| &padded[(y * 64 + x) * 4..][..4];
+        assert_eq!(pixel(0, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(32, 32), [255, 0, 0, 255]);
+    }
+}
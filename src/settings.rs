@@ -0,0 +1,177 @@
+//! Per-user settings, stored in a sqlite database (see `settings.path` in [`config`](crate::config)).
+//!
+//! Settings of a user are stored as a single json object, so adding new settings doesn't require migrations
+//! (missing fields are filled with defaults).
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use teloxide::types::UserId;
+
+use crate::{
+    error::database::DbError,
+    i18n::Language,
+    query_command::{ArchiveFormat, DownloadFormat},
+};
+
+/// Naming templates that can be chosen in the `/settings` menu (any other can be set by a command).
+pub const NAMING_PRESETS: [&str; 4] = ["{index}_{emoji}", "{emoji}", "{index}", "{set}_{index}"];
+
+#[derive(Clone)]
+pub struct Settings {
+    // Queries are tiny (a lookup by the primary key), so they are just done on the runtime threads
+    db: Arc<Mutex<Connection>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserSettings {
+    /// Format that is offered first, `None` means that all formats are offered equally.
+    pub format: Option<DownloadFormat>,
+    /// Container format of archives, used when the button doesn't specify one.
+    pub archive: ArchiveFormat,
+    /// Template of file names of stickers, see [`stuff::file_name`](crate::stuff::file_name).
+    pub naming: String,
+    pub language: Language,
+    pub thumbnail: ThumbnailStyle,
+}
+
+/// How thumbnails of sent documents look.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailStyle {
+    /// Transparent parts of the sticker are black.
+    Black,
+    /// Transparent parts of the sticker are white.
+    White,
+    /// Don't set thumbnails at all.
+    None,
+}
+
+impl Settings {
+    pub fn open(path: &Path) -> Result<Self, DbError> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Settings that are not persisted, for tests.
+    #[cfg(test)]
+    pub fn in_memory() -> Result<Self, DbError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(db: Connection) -> Result<Self, DbError> {
+        db.execute(
+            "CREATE TABLE IF NOT EXISTS settings (user_id INTEGER PRIMARY KEY, settings TEXT NOT NULL)",
+            (),
+        )?;
+
+        Ok(Self {
+            db: Arc::new(Mutex::new(db)),
+        })
+    }
+
+    /// Returns settings of `user`, defaults if the user has never changed them.
+    pub fn get(&self, user: UserId) -> UserSettings {
+        let db = self.db.lock().unwrap();
+
+        match Self::load(&db, user) {
+            Ok(settings) => settings,
+            Err(err) => {
+                log::error!("Couldn't load settings of {user}: {err}");
+                UserSettings::default()
+            }
+        }
+    }
+
+    /// Changes settings of `user` with `f`, returning the new settings.
+    pub fn update(
+        &self,
+        user: UserId,
+        f: impl FnOnce(&mut UserSettings),
+    ) -> Result<UserSettings, DbError> {
+        let db = self.db.lock().unwrap();
+
+        let mut settings = Self::load(&db, user)?;
+        f(&mut settings);
+
+        let json = serde_json::to_string(&settings)?;
+        db.execute(
+            "INSERT INTO settings (user_id, settings) VALUES (?1, ?2)
+                ON CONFLICT (user_id) DO UPDATE SET settings = excluded.settings",
+            (user.0, json),
+        )?;
+
+        Ok(settings)
+    }
+
+    fn load(db: &Connection, user: UserId) -> Result<UserSettings, DbError> {
+        let json: Option<String> = db
+            .query_row(
+                "SELECT settings FROM settings WHERE user_id = ?1",
+                [user.0],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match json {
+            Some(json) => Ok(serde_json::from_str(&json)?),
+            None => Ok(UserSettings::default()),
+        }
+    }
+}
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        Self {
+            format: None,
+            archive: ArchiveFormat::Zip,
+            naming: NAMING_PRESETS[0].to_owned(),
+            language: Language::English,
+            thumbnail: ThumbnailStyle::Black,
+        }
+    }
+}
+
+impl ThumbnailStyle {
+    pub const ALL: [Self; 3] = [Self::Black, Self::White, Self::None];
+
+    /// Returns the color of the transparent parts of the thumbnail, `None` if there should be no thumbnail.
+    pub fn background(self) -> Option<u8> {
+        match self {
+            Self::Black => Some(0),
+            Self::White => Some(u8::MAX),
+            Self::None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use teloxide::types::UserId;
+
+    use crate::{i18n::Language, query_command::ArchiveFormat};
+
+    use super::{Settings, UserSettings};
+
+    #[test]
+    fn update_and_get() {
+        let settings = Settings::in_memory().unwrap();
+        let user = UserId(42);
+
+        assert_eq!(settings.get(user), UserSettings::default());
+
+        settings
+            .update(user, |s| s.archive = ArchiveFormat::SevenZ)
+            .unwrap();
+        let updated = settings
+            .update(user, |s| s.language = Language::Russian)
+            .unwrap();
+
+        assert_eq!(updated.archive, ArchiveFormat::SevenZ);
+        assert_eq!(settings.get(user), updated);
+        assert_eq!(settings.get(UserId(1)), UserSettings::default());
+    }
+}
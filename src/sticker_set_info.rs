@@ -1,47 +1,49 @@
 use serde::Serialize;
-use teloxide::types::{Sticker, StickerSet};
+use teloxide::types::{Sticker, StickerKind, StickerSet};
+
+use crate::archive::Entry;
 
 #[derive(Serialize)]
 pub(crate) struct StickerSetInfo {
-    name: String,
-    title: String,
-    kind: StickerSetKind,
+    pub(crate) name: String,
+    pub(crate) title: String,
+    pub(crate) kind: StickerSetKind,
     // contains_masks: bool, // FIXME: do we need to interact with masks in any way?...
-    stickers: Vec<StickerInfo>,
+    pub(crate) stickers: Vec<StickerInfo>,
 }
 
 #[derive(Serialize)]
-enum StickerSetKind {
+pub(crate) enum StickerSetKind {
     Common,
     Animated,
     Video,
 }
 
 #[derive(Serialize)]
-struct StickerInfo {
-    path: String,
+pub(crate) struct StickerInfo {
+    pub(crate) path: String,
     file_unique_id: String,
     width: u16,
     height: u16,
-    emoji: Option<String>,
+    pub(crate) emoji: Option<String>,
     size_bytes: u32,
     // mask_position: Option<MaskPosition>, // FIXME: see above
 }
 
 impl StickerSetInfo {
-    pub(crate) fn new(set: &StickerSet, stickers: &[(String, Vec<u8>)]) -> StickerSetInfo {
+    /// `entries` are the files of the stickers, `idx` of an entry is the index of the sticker in the set.
+    pub(crate) fn new(set: &StickerSet, entries: &[Entry]) -> StickerSetInfo {
         StickerSetInfo {
             name: set.name.clone(),
             title: set.title.clone(),
-            kind: match (set.is_animated(), set.is_video()) {
-                (true, _) => StickerSetKind::Animated,
-                (_, true) => StickerSetKind::Video,
-                (_, _) => StickerSetKind::Common,
+            kind: match set.kind {
+                StickerKind::Webp => StickerSetKind::Common,
+                StickerKind::Animated => StickerSetKind::Animated,
+                StickerKind::Video => StickerSetKind::Video,
             },
-            stickers: set
-                .stickers
+            stickers: entries
                 .iter()
-                .zip(stickers)
+                .filter_map(|entry| Some((set.stickers.get(entry.idx)?, entry)))
                 .map(
                     |(
                         &Sticker {
@@ -51,14 +53,14 @@ impl StickerSetInfo {
                             ref emoji,
                             ..
                         },
-                        (path, bytes),
+                        Entry { name, size, .. },
                     )| StickerInfo {
-                        path: path.clone(),
+                        path: name.clone(),
                         file_unique_id: file_unique_id.clone(),
                         width,
                         height,
                         emoji: emoji.clone(),
-                        size_bytes: bytes.len() as _,
+                        size_bytes: *size as _,
                     },
                 )
                 .collect(),
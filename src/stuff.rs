@@ -1,56 +1,173 @@
 //! Random stuff lives here.
 
-use std::io::Write;
+use std::collections::{HashMap, HashSet};
 
 use emojis::Emoji;
-use teloxide::types::InputFile;
 use unicode_segmentation::UnicodeSegmentation;
-use zip::{result::ZipError, write::FileOptions, ZipWriter};
 
-/// Archive files together making a `.zip` file.
+/// Returns a file name (without the extension) for a sticker according to the naming `template`.
 ///
-/// Files are represented by a `(name, bytes)` tuple.
-pub fn archive(name: &str, files: Vec<(String, Vec<u8>)>) -> Result<InputFile, ZipError> {
-    let zip = {
-        // Technically this does a blocking write.
-        // But since it writes to memory and does not do compression, it takes negligible time (around 5ms).
-        // So it doesn't seem to make sense to use `spawn_blocking` here.
-
-        let mut zip = ZipWriter::new(std::io::Cursor::new(Vec::with_capacity(0 /* FIXME */)));
-
-        let options = FileOptions::default()
-            // Compressing images is pointless because they are already compressed.
-            //
-            // From my non-exhaustive testing using default `deflate` compression
-            // makes archiving 29 times slower while making the resulting .zip a little bit bigger.
-            .compression_method(zip::CompressionMethod::Stored);
-
-        for (name, bytes) in files {
-            zip.start_file(name, options)?;
-            zip.write_all(&bytes)?;
-        }
-
-        zip.finish()?.into_inner()
-    };
+/// The template can contain `{index}` (index of the sticker in its set, empty if it isn't in any),
+/// `{emoji}` (name of the first emoji associated with the sticker), `{set}` (name of the set) and `{id}`
+/// (unique id of the sticker).
+pub fn file_name(
+    template: &str,
+    idx: Option<u8>,
+    emojis: &str,
+    set: Option<&str>,
+    unique_id: &str,
+) -> String {
+    let emoji = emoji_name(emojis)
+        .unwrap_or(/* FIXME: warn */ "malformed_emoji")
+        .replace(' ', "_");
 
-    let archive_name = format!("{}.zip", name);
-    let file = InputFile::memory(zip).file_name(archive_name);
+    let name = template
+        .replace(
+            "{index}",
+            &idx.map(|idx| format!("{idx:03}")).unwrap_or_default(),
+        )
+        .replace("{emoji}", &emoji)
+        .replace("{set}", set.unwrap_or_default())
+        .replace("{id}", unique_id)
+        // Names must not escape the archive (or be something weird in general)
+        .replace(
+            |c: char| matches!(c, '/' | '\\' | ':') || c.is_control(),
+            "_",
+        );
 
-    Ok(file)
+    // Separators around an empty placeholder (e.g. `{index}` of a sticker without a set) look weird
+    let name = name.trim_matches(|c| matches!(c, '_' | '-' | ' ' | '.'));
+    match name {
+        "" => unique_id.to_owned(),
+        name => name.to_owned(),
+    }
 }
 
-/// Returns a file name for a sticker that is `idx`-th in its sticker pack (`None` if it isn't in any) given its associated `emojis`.
-pub fn sticker_name(idx: Option<u8>, emojis: &str) -> String {
-    let name = emojis
+/// Returns the name of the first emoji in `emojis` (e.g. `grinning face` for 😀), `None` if there are no emoji.
+pub fn emoji_name(emojis: &str) -> Option<&'static str> {
+    emojis
         .graphemes(true)
         .flat_map(|cluster| emojis::get(cluster))
         .map(Emoji::name)
         .next()
-        .unwrap_or_else(|| /* FIXME: warn */ "malformed_emoji")
-        .replace(' ', "_");
+}
+
+/// Makes `names` unique by adding `_2`, `_3`, etc to the repeated ones.
+///
+/// Depending on the naming template, multiple stickers in a set can get the same name (e.g. with just `{emoji}`).
+pub fn dedup_names<'a>(names: impl IntoIterator<Item = &'a mut String>) {
+    let names: Vec<_> = names.into_iter().collect();
+    let mut taken: HashSet<String> = names.iter().map(|name| name.to_string()).collect();
+    let mut seen = HashMap::new();
+
+    for name in names {
+        let count = seen.entry(name.clone()).or_insert(0);
+        *count += 1;
+
+        if *count > 1 {
+            // A suffixed name may already be taken (e.g. the second `a` in `[a, a_2, a]`), so skip those
+            let (n, unique) = (*count..)
+                .map(|n| (n, format!("{name}_{n}")))
+                .find(|(_, unique)| !taken.contains(unique))
+                .expect("there are less names than numbers");
+
+            *count = n;
+            taken.insert(unique.clone());
+            *name = unique;
+        }
+    }
+}
+
+/// Formats a unix timestamp as a UTC date and time, e.g. `2022-06-01 12:34 UTC`.
+pub fn format_utc(timestamp: u64) -> String {
+    let days = (timestamp / 86400) as i64;
+    let minutes = timestamp % 86400 / 60;
+
+    // Converting days to a date, see <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{year}-{month:02}-{day:02} {:02}:{:02} UTC",
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+/// Extracts a sticker set name from either a plain name or a link to the set
+/// (`https://t.me/addstickers/<name>` or `tg://addstickers?set=<name>`).
+pub fn sticker_set_name(s: &str) -> Option<&str> {
+    let s = s.trim();
+    let name = ["https://", "http://", ""]
+        .iter()
+        .flat_map(|scheme| s.strip_prefix(scheme))
+        .flat_map(|rest| {
+            ["t.me/addstickers/", "telegram.me/addstickers/"]
+                .iter()
+                .flat_map(move |prefix| rest.strip_prefix(prefix))
+        })
+        .chain(s.strip_prefix("tg://addstickers?set="))
+        .next()
+        .unwrap_or(s)
+        .trim_end_matches('/');
+
+    // Set names can only contain letters, digits and underscores
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dedup_names, file_name, format_utc, sticker_set_name};
+
+    #[test]
+    fn set_names() {
+        assert_eq!(sticker_set_name("Animals"), Some("Animals"));
+        assert_eq!(
+            sticker_set_name("https://t.me/addstickers/Animals"),
+            Some("Animals")
+        );
+        assert_eq!(
+            sticker_set_name("t.me/addstickers/Animals/"),
+            Some("Animals")
+        );
+        assert_eq!(
+            sticker_set_name("tg://addstickers?set=Animals"),
+            Some("Animals")
+        );
+        assert_eq!(sticker_set_name("https://t.me/durov"), None);
+        assert_eq!(sticker_set_name(""), None);
+    }
+
+    #[test]
+    fn file_names() {
+        let name = |template, idx| file_name(template, idx, "😀", Some("Animals"), "AgADBQAD");
+
+        assert_eq!(name("{index}_{emoji}", Some(7)), "007_grinning_face");
+        assert_eq!(name("{index}_{emoji}", None), "grinning_face");
+        assert_eq!(name("{set}/{id}", Some(7)), "Animals_AgADBQAD");
+        assert_eq!(name("{index}", None), "AgADBQAD");
+
+        let mut names = ["a", "b", "a", "a"].map(String::from);
+        dedup_names(&mut names);
+        assert_eq!(names, ["a", "b", "a_2", "a_3"]);
+
+        let mut names = ["a", "a_2", "a", "a"].map(String::from);
+        dedup_names(&mut names);
+        assert_eq!(names, ["a", "a_2", "a_3", "a_4"]);
+    }
 
-    match idx {
-        Some(idx) => format!("{idx:03}_{name}"),
-        None => name,
+    #[test]
+    fn dates() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00 UTC");
+        assert_eq!(format_utc(951782400 + 3723), "2000-02-29 01:02 UTC");
+        assert_eq!(format_utc(1654086840), "2022-06-01 12:34 UTC");
     }
 }
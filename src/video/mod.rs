@@ -0,0 +1,188 @@
+//! Decoding of video (`.webm`) stickers.
+//!
+//! There is no pure rust vp9 decoder, so this uses libvpx (and thus lives behind the `video` feature).
+//! Without the feature only the demuxer and the color conversion are compiled, so that they are still tested.
+#![cfg_attr(not(feature = "video"), allow(dead_code))]
+
+#[cfg(feature = "video")]
+mod vpx;
+mod webm;
+
+#[cfg(feature = "video")]
+use crate::error::converting::ConvertError;
+
+#[cfg(feature = "video")]
+use self::{vpx::Decoder, webm::Webm};
+
+/// Decoded frame in the I420 format (8 bit).
+pub struct Image<'a> {
+    pub width: usize,
+    pub height: usize,
+    /// `y`, `u` and `v` planes, `u` and `v` are subsampled by 2 in both dimensions.
+    pub planes: [&'a [u8]; 3],
+    pub strides: [usize; 3],
+    /// Whether the colors use the full `0..=255` range, as opposed to the "studio" `16..=235`.
+    pub full_range: bool,
+}
+
+#[cfg(feature = "video")]
+pub struct Video {
+    webm: Webm,
+}
+
+#[cfg(feature = "video")]
+impl Video {
+    pub fn from_webm(bytes: &[u8]) -> Result<Self, ConvertError> {
+        Webm::parse(bytes).map(|webm| Self { webm })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.webm.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.webm.height
+    }
+
+    /// Decodes the video frame by frame, yielding `(rgba, duration in seconds)`.
+    pub fn frames(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<(Vec<u8>, f32), ConvertError>> + '_, ConvertError> {
+        let mut color = Decoder::new(self.webm.codec)?;
+        // Most of the video stickers have alpha, but not all of them
+        let mut alpha = None;
+
+        let frames = self
+            .webm
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(move |(idx, block)| {
+                let frame = (|| {
+                    let Some(image) = color.decode(&block.data)? else {
+                        // FIXME: hidden frames are not a thing in webm (they are packed into superframes),
+                        //        but if they ever appear, the timing will be slightly off
+                        return Ok(None);
+                    };
+
+                    // Everything downstream expects frames of `width`×`height` (from the webm header),
+                    // vp9 can change the size mid-stream, but stickers shouldn't do that
+                    let size = (image.width as u32, image.height as u32);
+                    if size != (self.width(), self.height()) {
+                        return Err(ConvertError::Decode(format!(
+                            "frame is {}x{}, but the video is {}x{}",
+                            size.0,
+                            size.1,
+                            self.width(),
+                            self.height()
+                        )));
+                    }
+
+                    let mut rgba = yuv_to_rgba(&image);
+
+                    if let Some(data) = &block.alpha {
+                        let alpha = match &mut alpha {
+                            Some(d) => d,
+                            None => alpha.insert(Decoder::new(self.webm.codec)?),
+                        };
+
+                        if let Some(a) = alpha.decode(data)? {
+                            apply_alpha(&mut rgba, &image, &a);
+                        }
+                    }
+
+                    Ok(Some((rgba, self.webm.frame_duration(idx))))
+                })();
+
+                frame.transpose()
+            });
+
+        Ok(frames)
+    }
+}
+
+/// Converts an I420 image to rgba (bt.601, which is what ffmpeg uses by default for sticker-sized videos).
+fn yuv_to_rgba(image: &Image<'_>) -> Vec<u8> {
+    let Image {
+        width,
+        height,
+        planes: [y_plane, u_plane, v_plane],
+        strides: [y_stride, u_stride, v_stride],
+        full_range,
+    } = *image;
+
+    let mut out = Vec::with_capacity(width * height * 4);
+    for row in 0..height {
+        for col in 0..width {
+            let y = y_plane[row * y_stride + col] as f32;
+            let u = u_plane[row / 2 * u_stride + col / 2] as f32 - 128.;
+            let v = v_plane[row / 2 * v_stride + col / 2] as f32 - 128.;
+
+            let (r, g, b) = if full_range {
+                (y + 1.402 * v, y - 0.344 * u - 0.714 * v, y + 1.772 * u)
+            } else {
+                let y = 1.164 * (y - 16.);
+                (y + 1.596 * v, y - 0.392 * u - 0.813 * v, y + 2.017 * u)
+            };
+
+            // `as u8` saturates, so there is no need to clamp
+            out.extend([r.round() as u8, g.round() as u8, b.round() as u8, 255]);
+        }
+    }
+
+    out
+}
+
+/// Uses the luma of the `alpha` image as the alpha channel of `rgba`.
+fn apply_alpha(rgba: &mut [u8], image: &Image<'_>, alpha: &Image<'_>) {
+    if (alpha.width, alpha.height) != (image.width, image.height) {
+        return;
+    }
+
+    let [plane, ..] = alpha.planes;
+    let [stride, ..] = alpha.strides;
+
+    for (idx, pixel) in rgba.chunks_exact_mut(4).enumerate() {
+        let (row, col) = (idx / image.width, idx % image.width);
+        pixel[3] = plane[row * stride + col];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{yuv_to_rgba, Image};
+
+    #[test]
+    fn yuv() {
+        // Black, white and red in the "studio" range, with neutral chroma for the first two
+        let image = |y: [u8; 2], u, v| {
+            let planes = [y.to_vec(), vec![u], vec![v]];
+            yuv_to_rgba(&Image {
+                width: 2,
+                height: 1,
+                planes: [&planes[0], &planes[1], &planes[2]],
+                strides: [2, 1, 1],
+                full_range: false,
+            })
+        };
+
+        assert_eq!(
+            image([16, 235], 128, 128),
+            [0, 0, 0, 255, 255, 255, 255, 255]
+        );
+
+        let red = image([81, 81], 90, 240);
+        assert!(red[0] >= 250 && red[1] <= 2 && red[2] <= 2, "{red:?}");
+
+        // Full range doesn't scale the luma
+        let planes: [&[u8]; 3] = [&[100], &[128], &[128]];
+        let gray = yuv_to_rgba(&Image {
+            width: 1,
+            height: 1,
+            planes,
+            strides: [1, 1, 1],
+            full_range: true,
+        });
+        assert_eq!(gray, [100, 100, 100, 255]);
+    }
+}
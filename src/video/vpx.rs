@@ -0,0 +1,114 @@
+//! Safe-ish wrapper around libvpx decoder.
+use std::{ffi::CStr, mem::MaybeUninit, ptr};
+
+use vpx_sys::*;
+
+use crate::{
+    error::converting::ConvertError,
+    video::{webm::Codec, Image},
+};
+
+pub struct Decoder {
+    // Boxed, so the context is never moved after initialization
+    ctx: Box<vpx_codec_ctx_t>,
+}
+
+impl Decoder {
+    pub fn new(codec: Codec) -> Result<Self, ConvertError> {
+        // SAFETY: `vpx_codec_ctx_t` is a plain C struct, it's fine to zero it (libvpx examples do the same).
+        let mut ctx = Box::new(unsafe { MaybeUninit::<vpx_codec_ctx_t>::zeroed().assume_init() });
+
+        // SAFETY: FFI, interfaces are static, `ctx` is valid for writes.
+        let res = unsafe {
+            let iface = match codec {
+                Codec::Vp8 => vpx_codec_vp8_dx(),
+                Codec::Vp9 => vpx_codec_vp9_dx(),
+            };
+
+            vpx_codec_dec_init_ver(
+                &mut *ctx,
+                iface,
+                ptr::null(),
+                0,
+                VPX_DECODER_ABI_VERSION as _,
+            )
+        };
+
+        if res != VPX_CODEC_OK {
+            // N.B. no need to destroy `ctx`, since it wasn't initialized
+            return Err(ConvertError::Decode(format!(
+                "couldn't init decoder: {res:?}"
+            )));
+        }
+
+        Ok(Self { ctx })
+    }
+
+    /// Decodes a frame, returning the image, if the frame was visible.
+    pub fn decode(&mut self, data: &[u8]) -> Result<Option<Image<'_>>, ConvertError> {
+        // SAFETY: `ctx` is initialized, `data` is valid for `data.len()` bytes.
+        let res = unsafe {
+            vpx_codec_decode(
+                &mut *self.ctx,
+                data.as_ptr(),
+                data.len() as _,
+                ptr::null_mut(),
+                0,
+            )
+        };
+
+        if res != VPX_CODEC_OK {
+            return Err(ConvertError::Decode(self.error()));
+        }
+
+        let mut iter = ptr::null();
+        // SAFETY: `ctx` is initialized, the returned image lives until the next call to `decode`,
+        //         which can't happen while `Image<'_>` borrows `self`.
+        let img = unsafe { vpx_codec_get_frame(&mut *self.ctx, &mut iter).as_ref() };
+        let Some(img) = img else { return Ok(None) };
+
+        if img.fmt != vpx_img_fmt::VPX_IMG_FMT_I420 {
+            return Err(ConvertError::Decode(format!(
+                "unsupported pixel format: {:?}",
+                img.fmt
+            )));
+        }
+
+        let (width, height) = (img.d_w as usize, img.d_h as usize);
+        let strides = [0, 1, 2].map(|i| img.stride[i] as usize);
+        let rows = [height, height.div_ceil(2), height.div_ceil(2)];
+
+        // SAFETY: each plane of an I420 image has (at least) `stride * rows` bytes.
+        let planes = [0, 1, 2]
+            .map(|i| unsafe { std::slice::from_raw_parts(img.planes[i], strides[i] * rows[i]) });
+
+        Ok(Some(Image {
+            width,
+            height,
+            planes,
+            strides,
+            full_range: img.range == vpx_color_range::VPX_CR_FULL_RANGE,
+        }))
+    }
+
+    fn error(&mut self) -> String {
+        // SAFETY: `ctx` is initialized, libvpx returns valid C strings (or null for the detail).
+        unsafe {
+            let err = CStr::from_ptr(vpx_codec_error(&mut *self.ctx)).to_string_lossy();
+            match self.ctx.err_detail.as_ref() {
+                Some(_) => {
+                    let detail = CStr::from_ptr(self.ctx.err_detail).to_string_lossy();
+                    format!("{err}: {detail}")
+                }
+                None => err.into_owned(),
+            }
+        }
+    }
+}
+
+impl Drop for Decoder {
+    fn drop(&mut self) {
+        // SAFETY: `ctx` is initialized and is never used after this
+        unsafe { vpx_codec_destroy(&mut *self.ctx) };
+    }
+}
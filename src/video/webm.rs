@@ -0,0 +1,385 @@
+//! A minimal WebM demuxer, just enough to get VP9 frames (and their alpha) out of video stickers.
+//!
+//! I've tried `matroska-demuxer`, but it doesn't expose `BlockAdditional`s, which is
+//! where the alpha channel lives. Writing a demuxer that only cares about one video track is
+//! simple enough, so here we are.
+//!
+//! Things that are not supported (telegram doesn't produce them anyway):
+//! - lacing
+//! - more than one video track (only the first one is used)
+use crate::error::converting::ConvertError;
+
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const TIMECODE_SCALE: u32 = 0x2A_D7B1;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const CODEC_ID: u32 = 0x86;
+const DEFAULT_DURATION: u32 = 0x23_E383;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const BLOCK_ADDITIONS: u32 = 0x75A1;
+const BLOCK_MORE: u32 = 0xA6;
+const BLOCK_ADD_ID: u32 = 0xEE;
+const BLOCK_ADDITIONAL: u32 = 0xA5;
+
+/// Elements which we don't skip, but go inside.
+///
+/// Since we only care about a handful of elements, the whole file is read as a flat list,
+/// which also makes elements of unknown size (used by streaming muxers) a non-issue.
+const MASTERS: &[u32] = &[
+    SEGMENT,
+    INFO,
+    TRACKS,
+    TRACK_ENTRY,
+    VIDEO,
+    CLUSTER,
+    BLOCK_GROUP,
+    BLOCK_ADDITIONS,
+    BLOCK_MORE,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Vp8,
+    Vp9,
+}
+
+pub struct Webm {
+    pub codec: Codec,
+    pub width: u32,
+    pub height: u32,
+    /// Frames of the video track, in order.
+    pub blocks: Vec<Block>,
+    /// Duration of the whole video in seconds, if known.
+    pub duration: Option<f32>,
+    /// Duration of a single frame in seconds, if known.
+    pub default_frame_duration: Option<f32>,
+}
+
+pub struct Block {
+    /// Time at which the frame should be shown, in seconds.
+    pub time: f32,
+    pub data: Vec<u8>,
+    /// Encoded alpha channel of the frame, if any.
+    pub alpha: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct Track {
+    number: Option<u64>,
+    codec: Option<Codec>,
+    width: Option<u32>,
+    height: Option<u32>,
+    default_duration: Option<u64>,
+}
+
+/// A `Block` that was read, but not yet pushed (there may be `BlockAdditions` after it).
+struct PendingBlock {
+    track: u64,
+    timecode: i64,
+    data: Vec<u8>,
+    alpha: Option<Vec<u8>>,
+}
+
+impl Webm {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ConvertError> {
+        let mut r = Reader(bytes);
+
+        let mut timecode_scale = 1_000_000;
+        let mut duration = None;
+        let mut tracks: Vec<Track> = Vec::new();
+        let mut cluster_timecode = 0;
+        let mut block_add_id = 1;
+        let mut pending: Option<PendingBlock> = None;
+        let mut blocks = Vec::new();
+
+        while !r.0.is_empty() {
+            let id = r.id()?;
+            let size = r.size()?;
+
+            if MASTERS.contains(&id) {
+                match id {
+                    TRACK_ENTRY => tracks.push(Track::default()),
+                    BLOCK_MORE => block_add_id = 1,
+                    CLUSTER | BLOCK_GROUP => blocks.extend(pending.take()),
+                    _ => {}
+                }
+
+                continue;
+            }
+
+            let size = size.ok_or(ConvertError::InvalidWebm("unknown size of a leaf element"))?;
+            let data = r.take(size)?;
+            let track = tracks.last_mut();
+
+            match (id, track) {
+                (TIMECODE_SCALE, _) => timecode_scale = uint(data),
+                (DURATION, _) => duration = Some(float(data)),
+                (TRACK_NUMBER, Some(t)) => t.number = Some(uint(data)),
+                (CODEC_ID, Some(t)) => {
+                    t.codec = match data {
+                        b"V_VP8" => Some(Codec::Vp8),
+                        b"V_VP9" => Some(Codec::Vp9),
+                        _ => None,
+                    }
+                }
+                (DEFAULT_DURATION, Some(t)) => t.default_duration = Some(uint(data)),
+                (PIXEL_WIDTH, Some(t)) => t.width = Some(uint(data) as _),
+                (PIXEL_HEIGHT, Some(t)) => t.height = Some(uint(data) as _),
+                (TIMECODE, _) => cluster_timecode = uint(data) as i64,
+                (SIMPLE_BLOCK | BLOCK, _) => {
+                    blocks.extend(pending.take());
+                    pending = Some(PendingBlock::parse(data, cluster_timecode)?);
+                }
+                (BLOCK_ADD_ID, _) => block_add_id = uint(data),
+                // Alpha is stored with `BlockAddID` = 1 (which is also the default)
+                (BLOCK_ADDITIONAL, _) if block_add_id == 1 => {
+                    if let Some(p) = &mut pending {
+                        p.alpha = Some(data.to_owned());
+                    }
+                }
+                _ => {}
+            }
+        }
+        blocks.extend(pending.take());
+
+        let track = tracks
+            .into_iter()
+            .find(|t| t.codec.is_some())
+            .ok_or(ConvertError::InvalidWebm("no video track"))?;
+
+        let (Some(number), Some(codec), Some(width), Some(height)) =
+            (track.number, track.codec, track.width, track.height)
+        else {
+            return Err(ConvertError::InvalidWebm("incomplete video track"));
+        };
+
+        let scale = timecode_scale as f32 / 1e9;
+        // N.B. blocks must be decoded in the order they are stored in.
+        //      For vp8/vp9 it's the same as the presentation order (hidden frames are packed into superframes).
+        let blocks: Vec<_> = blocks
+            .into_iter()
+            .filter(|b| b.track == number)
+            .map(|b| Block {
+                time: b.timecode as f32 * scale,
+                data: b.data,
+                alpha: b.alpha,
+            })
+            .collect();
+
+        if blocks.is_empty() {
+            return Err(ConvertError::InvalidWebm("no frames"));
+        }
+
+        Ok(Self {
+            codec,
+            width,
+            height,
+            blocks,
+            duration: duration.map(|d| d * scale),
+            default_frame_duration: track.default_duration.map(|d| d as f32 / 1e9),
+        })
+    }
+
+    /// Returns the duration of the `idx`-th frame in seconds.
+    pub fn frame_duration(&self, idx: usize) -> f32 {
+        /// Used when there is no way to know the duration, 30 fps is the most common frame rate for video stickers.
+        const FALLBACK: f32 = 1. / 30.;
+
+        let time = self.blocks[idx].time;
+        match self.blocks.get(idx + 1) {
+            Some(next) => next.time - time,
+            None => self
+                .duration
+                .map(|d| d - time)
+                .filter(|&d| d > 0.)
+                .or(self.default_frame_duration)
+                .unwrap_or(FALLBACK),
+        }
+    }
+}
+
+impl PendingBlock {
+    fn parse(data: &[u8], cluster_timecode: i64) -> Result<Self, ConvertError> {
+        let mut r = Reader(data);
+
+        let (_, track) = r.vint()?;
+        let header = r.take(3)?;
+        let relative = i16::from_be_bytes([header[0], header[1]]);
+        let flags = header[2];
+
+        if flags & 0b0000_0110 != 0 {
+            return Err(ConvertError::InvalidWebm("laced blocks are not supported"));
+        }
+
+        Ok(Self {
+            track,
+            timecode: cluster_timecode + relative as i64,
+            data: r.0.to_owned(),
+            alpha: None,
+        })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: u64) -> Result<&'a [u8], ConvertError> {
+        let n = usize::try_from(n).unwrap_or(usize::MAX);
+        if n > self.0.len() {
+            return Err(ConvertError::InvalidWebm("unexpected end of file"));
+        }
+
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    /// Reads a variable size integer, returning it's length and value (with the length marker removed).
+    fn vint(&mut self) -> Result<(u32, u64), ConvertError> {
+        let first = *self
+            .0
+            .first()
+            .ok_or(ConvertError::InvalidWebm("unexpected end of file"))?;
+
+        let len = first.leading_zeros() + 1;
+        if len > 8 {
+            return Err(ConvertError::InvalidWebm("invalid variable size integer"));
+        }
+
+        let bytes = self.take(len as _)?;
+        let value = bytes[1..]
+            .iter()
+            // N.B. `u64`, since `len` can be 8, which overflows a `u8` shift
+            .fold(first as u64 & (0xFF >> len), |acc, &b| acc << 8 | b as u64);
+
+        Ok((len, value))
+    }
+
+    /// Reads an element id, ids are kept with their length marker, as the spec lists them that way.
+    fn id(&mut self) -> Result<u32, ConvertError> {
+        let (len, value) = self.vint()?;
+        if len > 4 {
+            return Err(ConvertError::InvalidWebm("invalid element id"));
+        }
+
+        Ok((value | 1 << (7 * len)) as u32)
+    }
+
+    /// Reads an element size, `None` means that the size is unknown.
+    fn size(&mut self) -> Result<Option<u64>, ConvertError> {
+        let (len, value) = self.vint()?;
+        let unknown = (1 << (7 * len)) - 1;
+
+        Ok(Some(value).filter(|&v| v != unknown))
+    }
+}
+
+fn uint(data: &[u8]) -> u64 {
+    data.iter().fold(0, |acc, &b| acc << 8 | b as u64)
+}
+
+fn float(data: &[u8]) -> f32 {
+    match *data {
+        [a, b, c, d] => f32::from_be_bytes([a, b, c, d]),
+        [a, b, c, d, e, f, g, h] => f64::from_be_bytes([a, b, c, d, e, f, g, h]) as f32,
+        _ => 0.,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Codec, Reader, Webm};
+
+    /// Element with a known size (which must fit into a single byte).
+    fn el(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.push(0x80 | data.len() as u8);
+        out.extend(data);
+        out
+    }
+
+    /// Master element of unknown size, its children just follow it.
+    fn master(id: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.push(0xFF);
+        out
+    }
+
+    #[test]
+    fn vints() {
+        assert_eq!(Reader(&[0x81]).vint().ok(), Some((1, 1)));
+        assert_eq!(Reader(&[0x40, 0x02]).vint().ok(), Some((2, 2)));
+        assert_eq!(
+            Reader(&[0x1A, 0x45, 0xDF, 0xA3]).id().ok(),
+            Some(0x1A45_DFA3)
+        );
+        // No length marker in the first byte
+        assert!(Reader(&[0x00, 0x01]).vint().is_err());
+        // Truncated
+        assert!(Reader(&[0x40]).vint().is_err());
+
+        assert_eq!(Reader(&[0x85]).size().ok(), Some(Some(5)));
+        assert_eq!(Reader(&[0xFF]).size().ok(), Some(None));
+        assert_eq!(
+            Reader(&[0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF])
+                .size()
+                .ok(),
+            Some(None)
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let webm = [
+            el(&[0x1A, 0x45, 0xDF, 0xA3], &el(&[0x42, 0x82], b"webm")),
+            master(&[0x18, 0x53, 0x80, 0x67]),
+            master(&[0x15, 0x49, 0xA9, 0x66]),
+            el(&[0x2A, 0xD7, 0xB1], &[0x0F, 0x42, 0x40]),
+            el(&[0x44, 0x89], &100f32.to_be_bytes()),
+            master(&[0x16, 0x54, 0xAE, 0x6B]),
+            master(&[0xAE]),
+            el(&[0xD7], &[1]),
+            el(&[0x86], b"V_VP9"),
+            master(&[0xE0]),
+            el(&[0xB0], &[2, 0]),
+            el(&[0xBA], &[1, 0]),
+            master(&[0x1F, 0x43, 0xB6, 0x75]),
+            el(&[0xE7], &[0]),
+            // Track 1, at 0ms, keyframe
+            el(&[0xA3], &[0x81, 0, 0, 0x80, 1, 2, 3]),
+            // Track 1, at 40ms, with alpha
+            master(&[0xA0]),
+            el(&[0xA1], &[0x81, 0, 40, 0, 4]),
+            master(&[0x75, 0xA1]),
+            master(&[0xA6]),
+            el(&[0xEE], &[1]),
+            el(&[0xA5], &[9]),
+        ]
+        .concat();
+
+        let webm = Webm::parse(&webm).unwrap_or_else(|err| panic!("{err:?}"));
+        assert!(webm.codec == Codec::Vp9);
+        assert_eq!((webm.width, webm.height), (512, 256));
+        assert_eq!(webm.blocks.len(), 2);
+        assert_eq!(webm.blocks[0].data, [1, 2, 3]);
+        assert_eq!(webm.blocks[0].alpha, None);
+        assert_eq!(webm.blocks[1].data, [4]);
+        assert_eq!(webm.blocks[1].alpha.as_deref(), Some(&[9][..]));
+
+        assert!((webm.frame_duration(0) - 0.04).abs() < 1e-6);
+        // The last frame lasts until the end of the video
+        assert!((webm.frame_duration(1) - 0.06).abs() < 1e-6);
+
+        assert!(Webm::parse(&[]).is_err());
+    }
+}
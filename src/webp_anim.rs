@@ -0,0 +1,169 @@
+//! Safe-ish wrapper around libwebp's animation encoder (`WebPAnimEncoder` from libwebpmux).
+use std::{ffi::CStr, mem::MaybeUninit, ptr, ptr::NonNull};
+
+use libwebp_sys::*;
+
+use crate::error::converting::ConvertError;
+
+pub struct AnimEncoder {
+    enc: NonNull<WebPAnimEncoder>,
+    config: WebPConfig,
+    width: u32,
+    height: u32,
+    /// Timestamp of the next frame, in milliseconds.
+    timestamp: i32,
+}
+
+impl AnimEncoder {
+    /// Creates an encoder of looping lossy animations, `quality` is from 0 to 100.
+    pub fn new(width: u32, height: u32, quality: f32) -> Result<Self, ConvertError> {
+        let mut options = MaybeUninit::<WebPAnimEncoderOptions>::uninit();
+        let mut config = MaybeUninit::<WebPConfig>::uninit();
+
+        // SAFETY: FFI, both pointers are valid for writes, the structs are initialized iff the calls succeed.
+        let (mut options, mut config) = unsafe {
+            if WebPAnimEncoderOptionsInit(options.as_mut_ptr()) == 0
+                || WebPConfigInit(config.as_mut_ptr()) == 0
+            {
+                return Err(encode_error("libwebp version mismatch"));
+            }
+
+            (options.assume_init(), config.assume_init())
+        };
+
+        // Loop forever
+        options.anim_params.loop_count = 0;
+        config.quality = quality;
+
+        // SAFETY: FFI, `config` is initialized.
+        if unsafe { WebPValidateConfig(&config) } == 0 {
+            return Err(encode_error("invalid encoder config"));
+        }
+
+        // SAFETY: FFI, `options` is initialized, null is returned on errors.
+        let enc = unsafe { WebPAnimEncoderNew(width as _, height as _, &options) };
+        let enc = NonNull::new(enc).ok_or_else(|| encode_error("couldn't create encoder"))?;
+
+        Ok(Self {
+            enc,
+            config,
+            width,
+            height,
+            timestamp: 0,
+        })
+    }
+
+    /// Adds a `width`×`height` rgba frame, shown for `duration_ms` milliseconds.
+    pub fn add(&mut self, rgba: &[u8], duration_ms: i32) -> Result<(), ConvertError> {
+        if rgba.len() != (self.width * self.height * 4) as usize {
+            return Err(encode_error("wrong size of the frame"));
+        }
+
+        let mut picture = MaybeUninit::<WebPPicture>::uninit();
+
+        // SAFETY: FFI, `picture` is valid for writes and is initialized iff the call succeeds.
+        let mut picture = unsafe {
+            if WebPPictureInit(picture.as_mut_ptr()) == 0 {
+                return Err(encode_error("libwebp version mismatch"));
+            }
+
+            picture.assume_init()
+        };
+        picture.use_argb = 1;
+        picture.width = self.width as _;
+        picture.height = self.height as _;
+
+        // SAFETY: FFI, `picture` is initialized, `rgba` has `height` rows of `width * 4` bytes (checked above),
+        //         the picture is freed after the frame is added (the encoder copies it).
+        let added = unsafe {
+            let imported =
+                WebPPictureImportRGBA(&mut picture, rgba.as_ptr(), self.width as i32 * 4);
+            let added = imported != 0
+                && WebPAnimEncoderAdd(
+                    self.enc.as_ptr(),
+                    &mut picture,
+                    self.timestamp,
+                    &self.config,
+                ) != 0;
+            WebPPictureFree(&mut picture);
+
+            added
+        };
+
+        if !added {
+            return Err(self.error());
+        }
+
+        self.timestamp += duration_ms;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, ConvertError> {
+        // SAFETY: FFI, `enc` is valid, a null frame marks the end of the animation
+        //         (and sets the duration of the last frame).
+        let ended = unsafe {
+            WebPAnimEncoderAdd(
+                self.enc.as_ptr(),
+                ptr::null_mut(),
+                self.timestamp,
+                ptr::null(),
+            )
+        };
+        if ended == 0 {
+            return Err(self.error());
+        }
+
+        let mut data = WebPData {
+            bytes: ptr::null(),
+            size: 0,
+        };
+
+        // SAFETY: FFI, `enc` is valid, on success `data` points to `data.size` bytes allocated by libwebp,
+        //         which are copied and then freed.
+        unsafe {
+            if WebPAnimEncoderAssemble(self.enc.as_ptr(), &mut data) == 0 {
+                return Err(self.error());
+            }
+
+            let out = std::slice::from_raw_parts(data.bytes, data.size).to_vec();
+            WebPDataClear(&mut data);
+
+            Ok(out)
+        }
+    }
+
+    fn error(&self) -> ConvertError {
+        // SAFETY: FFI, `enc` is valid, the error is a static C string (possibly empty).
+        let err = unsafe { CStr::from_ptr(WebPAnimEncoderGetError(self.enc.as_ptr())) };
+        encode_error(&err.to_string_lossy())
+    }
+}
+
+impl Drop for AnimEncoder {
+    fn drop(&mut self) {
+        // SAFETY: `enc` is valid and is never used after this
+        unsafe { WebPAnimEncoderDelete(self.enc.as_ptr()) };
+    }
+}
+
+fn encode_error(msg: &str) -> ConvertError {
+    ConvertError::Encode(format!("couldn't encode animated webp: {msg}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AnimEncoder;
+
+    #[test]
+    fn encodes_animation() {
+        let mut encoder = AnimEncoder::new(8, 8, 75.).unwrap();
+        encoder.add(&[255; 8 * 8 * 4], 100).unwrap();
+        encoder.add(&[0; 8 * 8 * 4], 100).unwrap();
+        assert!(encoder.add(&[0; 4], 100).is_err());
+
+        let webp = encoder.finish().unwrap();
+        assert_eq!(&webp[..4], b"RIFF");
+        assert_eq!(&webp[8..12], b"WEBP");
+        assert!(webp.windows(4).any(|chunk| chunk == b"ANIM"));
+    }
+}